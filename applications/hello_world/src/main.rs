@@ -1,3 +1,11 @@
-fn main() {
+#![no_std]
+#![no_main]
+
+use runtime::println;
+
+runtime::entry!(main);
+
+fn main() -> i32 {
     println!("Hello, world!");
+    0
 }
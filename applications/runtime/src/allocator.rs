@@ -0,0 +1,125 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::syscall::{self, number};
+
+const PROT_WRITE: u64 = 1 << 0;
+
+/// How much memory is requested from the kernel at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The block sizes of the free lists. Larger allocations are given
+/// their own mapping.
+const BLOCK_SIZES: [usize; 9] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+/// A heap backed by anonymous mappings. Small blocks are rounded up to
+/// a block size and reused through a free list for each size.
+struct Allocator {
+    locked: AtomicBool,
+    heap: core::cell::UnsafeCell<Heap>,
+}
+unsafe impl Sync for Allocator {}
+
+struct Heap {
+    free_lists: [Option<NonNull<FreeBlock>>; BLOCK_SIZES.len()],
+    next: usize,
+    end: usize,
+}
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+impl Allocator {
+    const fn new() -> Allocator {
+        Allocator {
+            locked: AtomicBool::new(false),
+            heap: core::cell::UnsafeCell::new(Heap {
+                free_lists: [None; BLOCK_SIZES.len()],
+                next: 0,
+                end: 0,
+            }),
+        }
+    }
+
+    /// Runs a function with exclusive access to the heap
+    fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match size_class(layout) {
+            Some(class) => self.with_heap(|heap| heap.alloc_block(class)),
+            None => map(layout.size()),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Large allocations are not returned to the kernel as there is
+        // no way to unmap memory
+        if let Some(class) = size_class(layout) {
+            self.with_heap(|heap| heap.free_block(ptr, class));
+        }
+    }
+}
+
+impl Heap {
+    unsafe fn alloc_block(&mut self, class: usize) -> *mut u8 {
+        // Reuse a freed block
+        if let Some(block) = self.free_lists[class] {
+            self.free_lists[class] = block.as_ref().next;
+            return block.as_ptr() as *mut u8;
+        }
+
+        // Blocks are aligned to their size, so round up the next address
+        let size = BLOCK_SIZES[class];
+        let mut start = (self.next + size - 1) & !(size - 1);
+        if self.next == 0 || start + size > self.end {
+            let chunk = map(CHUNK_SIZE);
+            if chunk.is_null() {
+                return chunk;
+            }
+            self.next = chunk as usize;
+            self.end = self.next + CHUNK_SIZE;
+            start = self.next;
+        }
+        self.next = start + size;
+        start as *mut u8
+    }
+
+    unsafe fn free_block(&mut self, ptr: *mut u8, class: usize) {
+        let block = ptr as *mut FreeBlock;
+        block.write(FreeBlock { next: self.free_lists[class] });
+        self.free_lists[class] = NonNull::new(block);
+    }
+}
+
+/// Gets the free list that an allocation belongs to
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&block| block >= size)
+}
+
+/// Maps zeroed, writable memory. Mappings are page aligned, which
+/// satisfies the alignment of every large allocation.
+fn map(len: usize) -> *mut u8 {
+    match unsafe { syscall::syscall3(number::MMAP, 0, len as u64, PROT_WRITE) } {
+        Ok(addr) => addr as *mut u8,
+        Err(_) => ptr::null_mut(),
+    }
+}
@@ -0,0 +1,59 @@
+//! The arguments and environment a program was started with
+
+/// The arguments and environment variables, as read from the stack
+static mut ARGS: (usize, *const *const u8, *const *const u8) =
+    (0, core::ptr::null(), core::ptr::null());
+
+pub(crate) unsafe fn init(argc: usize, argv: *const *const u8, envp: *const *const u8) {
+    ARGS = (argc, argv, envp);
+}
+
+/// An iterator over a NULL terminated array of strings
+#[derive(Clone)]
+pub struct Strings {
+    next: *const *const u8,
+    remaining: Option<usize>,
+}
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.next.is_null() || self.remaining == Some(0) {
+            return None;
+        }
+        let ptr = unsafe { *self.next };
+        if ptr.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        self.remaining = self.remaining.map(|n| n - 1);
+
+        // The kernel copies the strings as UTF-8 with a NUL terminator
+        unsafe {
+            let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+            let bytes = core::slice::from_raw_parts(ptr, len);
+            Some(core::str::from_utf8_unchecked(bytes))
+        }
+    }
+}
+
+/// Gets the arguments of the program. The first argument is the path it
+/// was started from.
+pub fn args() -> Strings {
+    let (argc, argv, _) = unsafe { ARGS };
+    Strings { next: argv, remaining: Some(argc) }
+}
+
+/// Gets the environment variables of the program as `KEY=value` strings
+pub fn vars() -> Strings {
+    let (_, _, envp) = unsafe { ARGS };
+    Strings { next: envp, remaining: None }
+}
+
+/// Gets the value of an environment variable
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find_map(|var| {
+        let (k, v) = var.split_at(var.find('=')?);
+        if k == key { Some(&v[1..]) } else { None }
+    })
+}
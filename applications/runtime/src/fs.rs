@@ -0,0 +1,76 @@
+//! Files on the boot volume
+
+use alloc::vec::Vec;
+
+use crate::io;
+use crate::syscall::{self, number, Error};
+
+/// How a file is opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Read = 0,
+    ReadWrite = 1,
+    Create = 2,
+}
+
+/// An open file. The file is closed when it is dropped.
+#[derive(Debug)]
+pub struct File {
+    fd: u64,
+}
+impl File {
+    /// Opens a file in a mode
+    pub fn open(path: &str, mode: OpenMode) -> Result<File, Error> {
+        let fd = unsafe {
+            syscall::syscall3(
+                number::OPEN,
+                path.as_ptr() as u64,
+                path.len() as u64,
+                mode as u64,
+            )?
+        };
+        Ok(File { fd })
+    }
+
+    /// Creates a file, or truncates it if it already exists
+    pub fn create(path: &str) -> Result<File, Error> { File::open(path, OpenMode::Create) }
+
+    /// Gets the file descriptor of the file
+    pub fn fd(&self) -> u64 { self.fd }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> { io::read(self.fd, buf) }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, Error> { io::write(self.fd, buf) }
+
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        io::write_all(self.fd, buf)
+    }
+
+    /// Reads the rest of the file
+    pub fn read_to_end(&mut self, data: &mut Vec<u8>) -> Result<usize, Error> {
+        let start = data.len();
+        let mut buf = [0; 512];
+        loop {
+            match self.read(&mut buf)? {
+                0 => return Ok(data.len() - start),
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+} impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(number::CLOSE, self.fd).ok() };
+    }
+}
+
+/// Reads the whole contents of a file
+pub fn read(path: &str) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    File::open(path, OpenMode::Read)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Creates a file containing some data
+pub fn write(path: &str, data: &[u8]) -> Result<(), Error> {
+    File::create(path)?.write_all(data)
+}
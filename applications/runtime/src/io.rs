@@ -0,0 +1,102 @@
+//! Reading from the keyboard and writing to the console
+
+use core::fmt;
+
+use crate::syscall::{self, number, Error};
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// Writes bytes to a file descriptor, returning how many were written
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize, Error> {
+    unsafe {
+        syscall::syscall3(number::WRITE, fd, buf.as_ptr() as u64, buf.len() as u64)
+            .map(|n| n as usize)
+    }
+}
+
+/// Reads bytes from a file descriptor, returning how many were read.
+/// Reading from the keyboard waits until a key is typed.
+pub fn read(fd: u64, buf: &mut [u8]) -> Result<usize, Error> {
+    loop {
+        let result = unsafe {
+            syscall::syscall3(number::READ, fd, buf.as_mut_ptr() as u64, buf.len() as u64)
+        };
+        // The kernel waits for one interrupt at a time
+        if result != Err(Error::WouldBlock) {
+            return result.map(|n| n as usize);
+        }
+    }
+}
+
+/// Writes all of a buffer to a file descriptor
+pub fn write_all(fd: u64, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match write(fd, buf)? {
+            0 => return Err(Error::Io),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Reads characters typed on the keyboard until enter is pressed. The
+/// line is returned without its newline.
+pub fn read_line() -> Result<alloc::string::String, Error> {
+    let mut line = alloc::vec::Vec::new();
+    let mut buf = [0; 1];
+    loop {
+        if read(STDIN, &mut buf)? == 0 || buf[0] == b'\n' || buf[0] == b'\r' {
+            break;
+        }
+        line.push(buf[0]);
+    }
+    alloc::string::String::from_utf8(line).map_err(|_| Error::InvalidArgument)
+}
+
+/// A writer to the console
+pub struct Console(u64);
+impl Console {
+    pub fn stdout() -> Console { Console(STDOUT) }
+
+    pub fn stderr() -> Console { Console(STDERR) }
+} impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use fmt::Write;
+    Console::stdout().write_fmt(args).ok();
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    use fmt::Write;
+    Console::stderr().write_fmt(args).ok();
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
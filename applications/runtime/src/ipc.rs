@@ -0,0 +1,140 @@
+//! Channels and shared memory for talking to other processes
+
+use alloc::vec::Vec;
+
+use crate::syscall::{self, number, Error};
+
+/// The most handles that can be sent with one message
+pub const MAX_MESSAGE_HANDLES: usize = 8;
+
+const PROT_WRITE: u64 = 1 << 0;
+
+/// A reference to a kernel object. Dropping it closes the handle.
+#[derive(Debug)]
+pub struct Handle(u64);
+impl Handle {
+    /// Takes ownership of a raw handle number, such as the handle passed
+    /// to the program by `process::spawn_with`
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may own the handle
+    pub unsafe fn from_raw(raw: u64) -> Handle { Handle(raw) }
+
+    /// Gives up ownership of the handle without closing it
+    pub fn into_raw(self) -> u64 {
+        let raw = self.0;
+        core::mem::forget(self);
+        raw
+    }
+
+    pub fn raw(&self) -> u64 { self.0 }
+} impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(number::HANDLE_CLOSE, self.0).ok() };
+    }
+}
+
+/// One end of a bidirectional message channel
+#[derive(Debug)]
+pub struct Channel {
+    handle: Handle,
+}
+impl Channel {
+    /// Creates a channel and returns both of its ends
+    pub fn pair() -> Result<(Channel, Channel), Error> {
+        let mut handles = [0u64; 2];
+        unsafe { syscall::syscall1(number::CHANNEL_CREATE, handles.as_mut_ptr() as u64)? };
+        Ok((Channel::from(Handle(handles[0])), Channel::from(Handle(handles[1]))))
+    }
+
+    /// Sends a message. Fails with `WouldBlock` if the channel is full.
+    pub fn send(&self, data: &[u8]) -> Result<(), Error> {
+        self.send_with(data, Vec::new())
+    }
+
+    /// Sends a message along with handles, which are moved to the other
+    /// end of the channel. The handles are closed if the message can not
+    /// be sent.
+    pub fn send_with(&self, data: &[u8], handles: Vec<Handle>) -> Result<(), Error> {
+        let mut raw = [0u64; MAX_MESSAGE_HANDLES];
+        if handles.len() > raw.len() {
+            return Err(Error::TooLarge);
+        }
+        for (raw, handle) in raw.iter_mut().zip(&handles) {
+            *raw = handle.raw();
+        }
+        unsafe {
+            syscall::syscall5(
+                number::CHANNEL_SEND,
+                self.handle.raw(),
+                data.as_ptr() as u64,
+                data.len() as u64,
+                raw.as_ptr() as u64,
+                handles.len() as u64,
+            )?;
+        }
+
+        // The handles belong to the other end now
+        for handle in handles {
+            handle.into_raw();
+        }
+        Ok(())
+    }
+
+    /// Receives a message into a buffer, returning its size and the
+    /// handles sent with it. Fails with `WouldBlock` if the channel is
+    /// empty, and with `TooLarge` if the message does not fit.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<(usize, Vec<Handle>), Error> {
+        let mut raw = [0u64; MAX_MESSAGE_HANDLES];
+        let value = unsafe {
+            syscall::syscall5(
+                number::CHANNEL_RECV,
+                self.handle.raw(),
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                raw.as_mut_ptr() as u64,
+                raw.len() as u64,
+            )?
+        };
+        let size = value as u32 as usize;
+        let count = (value >> 32) as usize;
+        let handles = raw[..count].iter().map(|&h| Handle(h)).collect();
+        Ok((size, handles))
+    }
+
+    pub fn handle(&self) -> &Handle { &self.handle }
+} impl From<Handle> for Channel {
+    fn from(handle: Handle) -> Channel { Channel { handle } }
+} impl From<Channel> for Handle {
+    fn from(channel: Channel) -> Handle { channel.handle }
+}
+
+/// Memory that can be mapped into several processes at once
+#[derive(Debug)]
+pub struct SharedMemory {
+    handle: Handle,
+}
+impl SharedMemory {
+    /// Creates a zeroed region of at least `len` bytes
+    pub fn new(len: usize) -> Result<SharedMemory, Error> {
+        let handle = unsafe { syscall::syscall1(number::SHM_CREATE, len as u64)? };
+        Ok(SharedMemory { handle: Handle(handle) })
+    }
+
+    /// Maps the whole region into the program as writable memory and
+    /// returns a pointer to it. The mapping lasts until the program
+    /// exits, even after the handle is closed.
+    pub fn map(&self) -> Result<*mut u8, Error> {
+        let addr = unsafe {
+            syscall::syscall3(number::SHM_MAP, self.handle.raw(), 0, PROT_WRITE)?
+        };
+        Ok(addr as *mut u8)
+    }
+
+    pub fn handle(&self) -> &Handle { &self.handle }
+} impl From<Handle> for SharedMemory {
+    fn from(handle: Handle) -> SharedMemory { SharedMemory { handle } }
+} impl From<SharedMemory> for Handle {
+    fn from(region: SharedMemory) -> Handle { region.handle }
+}
@@ -0,0 +1,52 @@
+//! The runtime that user programs are linked against.
+//!
+//! It provides the program entry point, system call wrappers, a heap
+//! allocator and the `print!` family of macros. A program declares its
+//! main function with `entry!`:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! runtime::entry!(main);
+//!
+//! fn main() -> i32 {
+//!     runtime::println!("Hello, world!");
+//!     0
+//! }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+mod allocator;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod ipc;
+pub mod process;
+mod start;
+pub mod syscall;
+pub mod window;
+
+pub use syscall::Error;
+
+/// Declares the main function of a program. The function takes no
+/// arguments and returns the exit code of the program.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        pub extern "C" fn __runtime_main() -> i32 {
+            let main: fn() -> i32 = $main;
+            main()
+        }
+    };
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    eprintln!("{}", info);
+    process::exit(101)
+}
@@ -0,0 +1,50 @@
+//! Starting, stopping and waiting for programs
+
+use crate::ipc::Handle;
+use crate::syscall::{self, number, Error};
+
+/// The raw handle that a program started with `spawn_with` is given
+pub const FIRST_HANDLE: u64 = 1;
+
+/// Exits the program with an exit code
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall::syscall1(number::EXIT, code as u64).ok() };
+    unreachable!("exit returned")
+}
+
+/// Runs a program until it exits and returns its process id. The exit
+/// code can then be collected with `wait`.
+pub fn spawn(path: &str) -> Result<u64, Error> {
+    unsafe { syscall::syscall2(number::SPAWN, path.as_ptr() as u64, path.len() as u64) }
+}
+
+/// Runs a program like `spawn`, giving it a handle. The program gets
+/// the handle as `FIRST_HANDLE`.
+pub fn spawn_with(path: &str, handle: Handle) -> Result<u64, Error> {
+    unsafe {
+        syscall::syscall3(
+            number::SPAWN,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            handle.into_raw(),
+        )
+    }
+}
+
+/// Waits for a child process to exit and returns its exit code
+pub fn wait(pid: u64) -> Result<i32, Error> {
+    unsafe { syscall::syscall1(number::WAIT, pid).map(|code| code as i32) }
+}
+
+/// Sleeps for a number of milliseconds
+pub fn sleep(ms: u64) {
+    let deadline = uptime_ms().saturating_add(ms);
+
+    // The kernel waits for one interrupt at a time
+    while let Err(Error::WouldBlock) = unsafe { syscall::syscall1(number::SLEEP, deadline) } {}
+}
+
+/// Gets the number of milliseconds since the system started
+pub fn uptime_ms() -> u64 {
+    unsafe { syscall::syscall0(number::TIME).unwrap_or(0) }
+}
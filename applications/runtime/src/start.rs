@@ -0,0 +1,31 @@
+use core::arch::global_asm;
+
+use crate::{env, process};
+
+extern "C" {
+    fn __runtime_main() -> i32;
+}
+
+// The kernel starts a program with argc on top of the stack, followed
+// by argv, envp and the auxiliary vector. The stack is 16 byte aligned.
+global_asm!(
+    ".global _start",
+    "_start:",
+    "    xor rbp, rbp",
+    "    mov rdi, rsp",
+    "    and rsp, -16",
+    "    call {start}",
+    "    ud2",
+    start = sym start,
+);
+
+/// Saves the arguments of the program, runs its main function and exits
+/// with the code it returns
+unsafe extern "C" fn start(stack: *const u64) -> ! {
+    let argc = *stack as usize;
+    let argv = stack.add(1) as *const *const u8;
+    let envp = argv.add(argc + 1);
+    env::init(argc, argv, envp);
+
+    process::exit(__runtime_main())
+}
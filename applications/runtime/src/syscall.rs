@@ -0,0 +1,151 @@
+//! Raw system calls. See the kernel's `syscalls` module for the calling
+//! convention and the meaning of each call.
+
+use core::arch::asm;
+
+pub mod number {
+    pub const EXIT: u64 = 0;
+    pub const WRITE: u64 = 1;
+    pub const READ: u64 = 2;
+    pub const OPEN: u64 = 3;
+    pub const CLOSE: u64 = 4;
+    pub const MMAP: u64 = 5;
+    pub const SPAWN: u64 = 6;
+    pub const WAIT: u64 = 7;
+    pub const SLEEP: u64 = 8;
+    pub const TIME: u64 = 9;
+    pub const WINDOW_CREATE: u64 = 10;
+    pub const WINDOW_DESTROY: u64 = 11;
+    pub const WINDOW_BLIT: u64 = 12;
+    pub const CHANNEL_CREATE: u64 = 13;
+    pub const CHANNEL_SEND: u64 = 14;
+    pub const CHANNEL_RECV: u64 = 15;
+    pub const HANDLE_CLOSE: u64 = 16;
+    pub const SHM_CREATE: u64 = 17;
+    pub const SHM_MAP: u64 = 18;
+    pub const WINDOW_BUFFER: u64 = 19;
+    pub const WINDOW_PRESENT: u64 = 20;
+}
+
+/// The errors that a system call can return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    NoSys,
+    BadAddress,
+    InvalidArgument,
+    BadDescriptor,
+    NotFound,
+    NoMemory,
+    TooManyFiles,
+    Io,
+    NoChild,
+    NotExecutable,
+    WouldBlock,
+    PeerClosed,
+    TooLarge,
+
+    /// An error code that this version of the runtime does not know
+    Unknown(u64),
+}
+impl Error {
+    fn from_code(code: u64) -> Error {
+        match code {
+            1 => Self::NoSys,
+            2 => Self::BadAddress,
+            3 => Self::InvalidArgument,
+            4 => Self::BadDescriptor,
+            5 => Self::NotFound,
+            6 => Self::NoMemory,
+            7 => Self::TooManyFiles,
+            8 => Self::Io,
+            9 => Self::NoChild,
+            10 => Self::NotExecutable,
+            11 => Self::WouldBlock,
+            12 => Self::PeerClosed,
+            13 => Self::TooLarge,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Converts the value returned in rax into a result
+fn result(value: u64) -> Result<u64, Error> {
+    // Values from -4095 to -1 are negated error codes
+    if value > (-4096i64) as u64 {
+        Err(Error::from_code(value.wrapping_neg()))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Makes a system call with up to five arguments
+///
+/// # Safety
+///
+/// The arguments must be valid for the system call. Pointers must point
+/// to memory that the kernel is allowed to read or write for the length
+/// that is passed with them.
+pub unsafe fn syscall0(nr: u64) -> Result<u64, Error> {
+    syscall4(nr, 0, 0, 0, 0)
+}
+
+/// # Safety
+///
+/// See `syscall0`
+pub unsafe fn syscall1(nr: u64, a0: u64) -> Result<u64, Error> {
+    syscall4(nr, a0, 0, 0, 0)
+}
+
+/// # Safety
+///
+/// See `syscall0`
+pub unsafe fn syscall2(nr: u64, a0: u64, a1: u64) -> Result<u64, Error> {
+    syscall4(nr, a0, a1, 0, 0)
+}
+
+/// # Safety
+///
+/// See `syscall0`
+pub unsafe fn syscall3(nr: u64, a0: u64, a1: u64, a2: u64) -> Result<u64, Error> {
+    syscall4(nr, a0, a1, a2, 0)
+}
+
+/// # Safety
+///
+/// See `syscall0`
+pub unsafe fn syscall4(
+    nr: u64,
+    a0: u64,
+    a1: u64,
+    a2: u64,
+    a3: u64,
+) -> Result<u64, Error> {
+    syscall5(nr, a0, a1, a2, a3, 0)
+}
+
+/// # Safety
+///
+/// See `syscall0`
+pub unsafe fn syscall5(
+    nr: u64,
+    a0: u64,
+    a1: u64,
+    a2: u64,
+    a3: u64,
+    a4: u64,
+) -> Result<u64, Error> {
+    let value;
+    asm!(
+        "syscall",
+        inlateout("rax") nr => value,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        in("r10") a3,
+        in("r8") a4,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+    result(value)
+}
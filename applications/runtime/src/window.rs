@@ -0,0 +1,79 @@
+//! Windows drawn by the kernel's window manager
+
+use crate::ipc::{Handle, SharedMemory};
+use crate::syscall::{self, number, Error};
+
+/// A window owned by the program. The window is closed when it is
+/// dropped.
+#[derive(Debug)]
+pub struct Window {
+    id: u64,
+    width: usize,
+    height: usize,
+
+    /// The window's pixels, once they have been mapped
+    pixels: Option<*mut u32>,
+}
+impl Window {
+    /// Opens a window with its top left corner at (x, y)
+    pub fn new(width: usize, height: usize, x: isize, y: isize) -> Result<Window, Error> {
+        let id = unsafe {
+            syscall::syscall4(
+                number::WINDOW_CREATE,
+                width as u64,
+                height as u64,
+                x as u64,
+                y as u64,
+            )?
+        };
+        Ok(Window { id, width, height, pixels: None })
+    }
+
+    pub fn id(&self) -> u64 { self.id }
+
+    pub fn size(&self) -> (usize, usize) { (self.width, self.height) }
+
+    /// Replaces the contents of the window. There must be one
+    /// `0x00RRGGBB` pixel for every pixel of the window, row by row.
+    pub fn blit(&self, pixels: &[u32]) -> Result<(), Error> {
+        if pixels.len() != self.width * self.height {
+            return Err(Error::InvalidArgument);
+        }
+        unsafe {
+            syscall::syscall3(
+                number::WINDOW_BLIT,
+                self.id,
+                pixels.as_ptr() as u64,
+                (pixels.len() * 4) as u64,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Gets the pixels of the window, which are shared with the window
+    /// manager as `0x00RRGGBB` u32s, row by row. Call `present` to show
+    /// what was drawn.
+    pub fn buffer(&mut self) -> Result<&mut [u32], Error> {
+        let pixels = match self.pixels {
+            Some(pixels) => pixels,
+            None => {
+                let handle = unsafe { syscall::syscall1(number::WINDOW_BUFFER, self.id)? };
+                let region = SharedMemory::from(unsafe { Handle::from_raw(handle) });
+                let pixels = region.map()? as *mut u32;
+                self.pixels = Some(pixels);
+                pixels
+            },
+        };
+        Ok(unsafe { core::slice::from_raw_parts_mut(pixels, self.width * self.height) })
+    }
+
+    /// Puts what was drawn to the buffer on the screen
+    pub fn present(&self) -> Result<(), Error> {
+        unsafe { syscall::syscall1(number::WINDOW_PRESENT, self.id)? };
+        Ok(())
+    }
+} impl Drop for Window {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(number::WINDOW_DESTROY, self.id).ok() };
+    }
+}
@@ -0,0 +1,236 @@
+//! Keeps crash reports across reboots. When the kernel panics, the panic
+//! message, a backtrace and the last log records are written to a UEFI
+//! variable, or to a file on the boot volume if the firmware will not
+//! store the variable. The next boot prints the report and removes it.
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use log::{debug, warn};
+use uefi::{Guid, Handle, Status};
+use uefi::proto::loaded_image::LoadedImage;
+
+use crate::filesystem::{self, OpenMode};
+use crate::log_buffer::{self, Truncate};
+use crate::system::apic;
+use crate::task::timer;
+use crate::ST;
+
+/// The UEFI variable that holds the report, under `VENDOR_GUID`
+const VARIABLE_NAME: &str = "KernelCrashReport";
+const VENDOR_GUID: Guid = Guid::from_values(
+    0x5a1c_9e47, 0x3b2d, 0x4c8f, 0x9d61, [0x0e, 0x7a, 0x43, 0xb8, 0x21, 0xf5],
+);
+
+/// Non volatile, and readable both during boot and at runtime
+const VARIABLE_ATTRIBUTES: u32 = 0x1 | 0x2 | 0x4;
+
+/// The file the report is written to when the variable can not be set
+const REPORT_PATH: &str = "/crash.txt";
+
+/// The largest report kept. Firmware often refuses variables much larger
+/// than this, and anything past it is cut off.
+const REPORT_SIZE: usize = 4096;
+
+/// The number of log records at the end of the report
+const LOG_LINES: usize = 16;
+
+/// The most return addresses followed up the stack
+const MAX_FRAMES: usize = 16;
+
+/// The furthest apart two stack frames can be before the chain is taken to
+/// be broken
+const MAX_FRAME_SIZE: u64 = 0x10_0000;
+
+/// Where the kernel image was loaded, so return addresses can be given
+/// relative to it
+static IMAGE_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Set by the first panic, so that a panic while saving the report does
+/// not try again
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// The report is built here, since the heap may be what failed
+static mut REPORT: [u8; REPORT_SIZE] = [0; REPORT_SIZE];
+
+/// The part of the runtime services table with the variable services,
+/// which the uefi crate does not wrap
+#[repr(C)]
+struct RawRuntimeServices {
+    _header: [u64; 3],
+
+    /// The time and virtual memory services
+    _pad: [usize; 6],
+    get_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        size: *mut usize,
+        data: *mut u8,
+    ) -> Status,
+    _get_next_variable_name: usize,
+    set_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        size: usize,
+        data: *const u8,
+    ) -> Status,
+}
+
+/// Records where the kernel was loaded, then prints the report of the last
+/// crash if there is one and removes it. Needs boot services.
+pub fn init(image: Handle) {
+    if let Some(st) = unsafe { ST.as_ref() } {
+        if let Ok(image) = st.boot_services().handle_protocol::<LoadedImage>(image) {
+            let image = unsafe { &*image.split().1.get() };
+            IMAGE_BASE.store(image.info().0, Ordering::SeqCst);
+        }
+    }
+
+    debug!("Looking for a crash report");
+    let report = match read_variable() {
+        Some(report) => {
+            let _ = write_variable(&[]);
+            report
+        },
+        None => match filesystem::read_file(REPORT_PATH) {
+            Ok(report) => {
+                let _ = filesystem::open(REPORT_PATH, OpenMode::ReadWrite)
+                    .and_then(|file| file.delete());
+                String::from_utf8_lossy(&report).into_owned()
+            },
+            Err(_) => return,
+        },
+    };
+
+    warn!("The previous boot crashed:");
+    for line in report.lines() {
+        warn!("  {}", line);
+    }
+}
+
+/// Writes a report of a panic to the variable, or to the boot volume if
+/// that fails. Called by the panic handler.
+pub fn save(info: &PanicInfo) {
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let report = unsafe { &mut REPORT };
+    let mut writer = Truncate::new(report);
+    write_report(&mut writer, info);
+    let len = writer.len();
+    let report = &report[..len];
+
+    if write_variable(report) {
+        return;
+    }
+    // This allocates, but the report would be lost otherwise
+    if let Ok(mut file) = filesystem::open(REPORT_PATH, OpenMode::Create) {
+        let _ = file.write(report).and_then(|_| file.flush());
+    }
+}
+
+fn write_report(f: &mut impl Write, info: &PanicInfo) {
+    let ms = timer::uptime_ms();
+    let _ = writeln!(f, "Kernel panic on cpu{} at {}.{:03}s", apic::id(), ms / 1000, ms % 1000);
+    if let Some(message) = info.message() {
+        let _ = writeln!(f, "{}", message);
+    }
+    if let Some(location) = info.location() {
+        let _ = writeln!(f, "at {}", location);
+    }
+
+    let base = IMAGE_BASE.load(Ordering::SeqCst) as u64;
+    let _ = writeln!(f, "\nBacktrace (image base {:#x}):", base);
+    backtrace(|address| {
+        let _ = match address.checked_sub(base) {
+            Some(offset) if base != 0 => writeln!(f, "  {:#018x} (image+{:#x})", address, offset),
+            _ => writeln!(f, "  {:#018x}", address),
+        };
+    });
+
+    let _ = writeln!(f, "\nLast log records:");
+    log_buffer::replay_recent(LOG_LINES, |entry| {
+        let _ = writeln!(f, "{}", entry);
+    });
+}
+
+/// Calls `f` with the return address of every frame on the stack, by
+/// following the saved frame pointers. The kernel is built with frame
+/// pointers, but the chain stops at the first frame that looks wrong.
+fn backtrace(mut f: impl FnMut(u64)) {
+    let mut frame: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) frame) };
+
+    for _ in 0..MAX_FRAMES {
+        if frame == 0 || frame % 8 != 0 {
+            break;
+        }
+        let (next, address) = unsafe {
+            let frame = frame as *const u64;
+            (*frame, *frame.add(1))
+        };
+        if address == 0 {
+            break;
+        }
+        f(address);
+
+        // The stack grows down, so callers' frames are above
+        if next <= frame || next - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = next;
+    }
+}
+
+/// Gets the runtime services table
+fn runtime_services() -> Option<&'static RawRuntimeServices> {
+    let st = unsafe { ST.as_ref() }?;
+    let rt = st.runtime_services() as *const _ as *const RawRuntimeServices;
+    Some(unsafe { &*rt })
+}
+
+/// The variable name as a null terminated UCS-2 string
+fn variable_name() -> [u16; 32] {
+    let mut name = [0; 32];
+    for (c, byte) in name.iter_mut().zip(VARIABLE_NAME.bytes()) {
+        *c = byte as u16;
+    }
+    name
+}
+
+fn read_variable() -> Option<String> {
+    let rt = runtime_services()?;
+    let mut report = vec![0u8; REPORT_SIZE];
+    let mut size = report.len();
+    let mut attributes = 0;
+    let status = unsafe {
+        (rt.get_variable)(variable_name().as_ptr(), &VENDOR_GUID, &mut attributes,
+            &mut size, report.as_mut_ptr())
+    };
+    if status != Status::SUCCESS {
+        return None;
+    }
+    report.truncate(size);
+    Some(String::from_utf8_lossy(&report).into_owned())
+}
+
+/// Sets the variable to `report`, or deletes it if `report` is empty.
+/// Returns whether the firmware took it.
+fn write_variable(report: &[u8]) -> bool {
+    let rt = match runtime_services() {
+        Some(rt) => rt,
+        None => return false,
+    };
+    let status = unsafe {
+        (rt.set_variable)(variable_name().as_ptr(), &VENDOR_GUID, VARIABLE_ATTRIBUTES,
+            report.len(), report.as_ptr())
+    };
+    status == Status::SUCCESS
+}
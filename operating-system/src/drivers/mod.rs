@@ -0,0 +1,287 @@
+//! The driver model. Devices are found by the buses (PCI, ACPI and the
+//! legacy platform devices that neither of them report), and every
+//! registered driver is bound to the devices whose ids it lists. Drivers
+//! are added to `BUILTIN_DRIVERS` or registered at runtime with
+//! `register`, so the kernel entry point only has to call `init`.
+
+mod ps2_keyboard;
+pub mod serial;
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use log::debug;
+use spin::Mutex;
+
+use crate::system::{self, acpi_devices, pci_express};
+use crate::system::pci_express::PciDevice;
+
+/// The drivers that are built into the kernel
+static BUILTIN_DRIVERS: &[&dyn Driver] = &[
+    &ps2_keyboard::Ps2Keyboard,
+    &serial::Serial,
+];
+
+/// Legacy devices that can not be enumerated, and are only used when
+/// the firmware does not report them in the acpi namespace
+static PLATFORM_DEVICES: &[PlatformDevice] = &[
+    PlatformDevice { name: "i8042" },
+    PlatformDevice { name: "com1" },
+];
+
+#[derive(Debug)]
+pub enum Error {
+    /// The driver does not support the device after all
+    Unsupported,
+
+    /// The driver can not be detached from its devices
+    DetachNotSupported,
+
+    /// The device has no driver bound to it
+    NotBound,
+    System(system::Error),
+}
+impl From<system::Error> for Error {
+    fn from(orig: system::Error) -> Self { Self::System(orig) }
+}
+
+/// A legacy device at a fixed location, like the PS/2 controller
+#[derive(Debug)]
+pub struct PlatformDevice {
+    pub name: &'static str,
+}
+
+/// The bus a device was found on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Pci,
+    Acpi,
+    Platform,
+}
+
+/// A device on one of the buses
+#[derive(Debug, Clone, Copy)]
+pub enum Device {
+    Pci(&'static PciDevice),
+    Acpi(&'static acpi_devices::Device),
+    Platform(&'static PlatformDevice),
+}
+impl Device {
+    pub fn bus(&self) -> Bus {
+        match self {
+            Device::Pci(_) => Bus::Pci,
+            Device::Acpi(_) => Bus::Acpi,
+            Device::Platform(_) => Bus::Platform,
+        }
+    }
+
+    /// Gets the PCI address, acpi path or name of the device
+    pub fn name(&self) -> String {
+        match self {
+            Device::Pci(device) => format!("{}", device.address),
+            Device::Acpi(device) => format!("{}", device.path),
+            Device::Platform(device) => String::from(device.name),
+        }
+    }
+
+    /// Checks whether the device has an id
+    pub fn matches(&self, id: &DeviceId) -> bool {
+        match (self, id) {
+            (Device::Pci(device), DeviceId::Pci { vendor_id, device_id }) =>
+                device.vendor_id == *vendor_id && device.device_id == *device_id,
+            (Device::Pci(device), DeviceId::PciClass { class, subclass }) =>
+                device.class == *class && device.subclass == *subclass,
+            (Device::Acpi(device), DeviceId::Acpi(id)) => device.matches(id),
+            (Device::Platform(device), DeviceId::Platform(name)) => device.name == *name,
+            _ => false,
+        }
+    }
+
+    /// Checks whether two devices are the same entry of their registry
+    fn is(&self, other: &Device) -> bool {
+        match (self, other) {
+            (Device::Pci(a), Device::Pci(b)) => core::ptr::eq(*a, *b),
+            (Device::Acpi(a), Device::Acpi(b)) => core::ptr::eq(*a, *b),
+            (Device::Platform(a), Device::Platform(b)) => core::ptr::eq(*a, *b),
+            _ => false,
+        }
+    }
+}
+
+/// An id that a driver can be bound to devices by
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceId {
+    Pci { vendor_id: u16, device_id: u16 },
+    PciClass { class: u8, subclass: u8 },
+
+    /// A hardware or compatible id, like PNP0303
+    Acpi(&'static str),
+
+    /// The name of a platform device
+    Platform(&'static str),
+}
+
+/// A driver for some kind of device
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The ids of the devices the driver can handle
+    fn ids(&self) -> &'static [DeviceId];
+
+    /// Checks more closely whether the driver can handle a device with one
+    /// of its ids, like by looking at the revision
+    fn probe(&self, device: Device) -> bool {
+        let _ = device;
+        true
+    }
+
+    /// Starts using a device
+    fn attach(&self, device: Device) -> Result<(), Error>;
+
+    /// Stops using a device, so it can be bound to another driver
+    fn detach(&self, device: Device) -> Result<(), Error> {
+        let _ = device;
+        Err(Error::DetachNotSupported)
+    }
+
+    /// Sets a device up again after the system woke from sleep, which
+    /// resets it
+    fn resume(&self, device: Device) -> Result<(), Error> {
+        let _ = device;
+        Ok(())
+    }
+}
+
+/// A device and the driver bound to it
+struct Binding {
+    device: Device,
+    driver: &'static dyn Driver,
+}
+
+static DRIVERS: Mutex<Vec<&'static dyn Driver>> = Mutex::new(Vec::new());
+static BINDINGS: Mutex<Vec<Binding>> = Mutex::new(Vec::new());
+
+/// Registers the built in drivers, binding them to the devices the buses
+/// have found. The PCI and acpi devices have to be enumerated first.
+pub fn init() {
+    for &driver in BUILTIN_DRIVERS.iter() {
+        register(driver);
+    }
+}
+
+/// Adds a driver and binds it to every device it matches that has no
+/// driver yet. Legacy devices like the PS/2 controller can show up both in
+/// the acpi namespace and as a platform device, so a driver is only bound
+/// to platform devices if the firmware reports none of its acpi ids.
+pub fn register(driver: &'static dyn Driver) {
+    debug!("Registering the {} driver", driver.name());
+    DRIVERS.lock().push(driver);
+
+    let reported = acpi_devices::devices().iter()
+        .any(|device| driver.ids().iter().any(|id| Device::Acpi(device).matches(id)));
+    for device in all_devices() {
+        if (reported && device.bus() == Bus::Platform)
+            || driver_of(device).is_some()
+            || !driver.ids().iter().any(|id| device.matches(id))
+            || !driver.probe(device)
+        {
+            continue;
+        }
+
+        debug!("Attaching {} to {}", driver.name(), device.name());
+        match driver.attach(device) {
+            Ok(()) => BINDINGS.lock().push(Binding { device, driver }),
+            Err(err) => debug!("Could not attach {} to {}: {:?}",
+                driver.name(), device.name(), err),
+        }
+    }
+}
+
+/// Detaches the driver of a device
+pub fn detach(device: Device) -> Result<(), Error> {
+    let driver = driver_of(device).ok_or(Error::NotBound)?;
+    debug!("Detaching {} from {}", driver.name(), device.name());
+    driver.detach(device)?;
+    BINDINGS.lock().retain(|binding| !binding.device.is(&device));
+    Ok(())
+}
+
+/// Sets every bound device up again after the system woke from sleep
+pub fn resume() {
+    let bindings = BINDINGS.lock().iter()
+        .map(|binding| (binding.device, binding.driver))
+        .collect::<Vec<_>>();
+    for (device, driver) in bindings {
+        debug!("Resuming {} on {}", driver.name(), device.name());
+        if let Err(err) = driver.resume(device) {
+            debug!("Could not resume {} on {}: {:?}", driver.name(), device.name(), err);
+        }
+    }
+}
+
+/// Gets the driver bound to a device
+pub fn driver_of(device: Device) -> Option<&'static dyn Driver> {
+    BINDINGS.lock().iter()
+        .find(|binding| binding.device.is(&device))
+        .map(|binding| binding.driver)
+}
+
+/// Gets every device on every bus
+pub fn all_devices() -> Vec<Device> {
+    let mut devices = Vec::new();
+    devices.extend(pci_express::devices().iter().map(Device::Pci));
+    devices.extend(acpi_devices::devices().iter().map(Device::Acpi));
+    devices.extend(PLATFORM_DEVICES.iter().map(Device::Platform));
+    devices
+}
+
+/// Formats every device as a tree, with PCI devices below their bridges
+/// and acpi devices below their parents, along with their drivers
+pub struct DeviceTree;
+impl fmt::Display for DeviceTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bus = None;
+        for device in all_devices() {
+            if bus != Some(device.bus()) {
+                bus = Some(device.bus());
+                writeln!(f, "{:?}", device.bus())?;
+            }
+
+            let depth = match device {
+                Device::Pci(device) => pci_depth(device),
+                Device::Acpi(device) => device.path.as_string().matches('.').count(),
+                Device::Platform(_) => 1,
+            };
+            write!(f, "{:width$}{}", "", device.name(), width = depth * 2)?;
+            match device {
+                Device::Pci(device) => write!(f, " {:04x}:{:04x} class {:02x}:{:02x}",
+                    device.vendor_id, device.device_id, device.class, device.subclass)?,
+                Device::Acpi(device) => if let Some(hid) = &device.hid {
+                    write!(f, " {}", hid)?;
+                },
+                Device::Platform(_) => (),
+            }
+            if let Some(driver) = driver_of(device) {
+                write!(f, " [{}]", driver.name())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the bridges above a PCI device, plus one for the bus itself
+fn pci_depth(device: &PciDevice) -> usize {
+    let mut depth = 1;
+    let mut parent = device.parent;
+    while let Some(address) = parent {
+        depth += 1;
+        parent = pci_express::devices().iter()
+            .find(|device| device.address == address)
+            .and_then(|device| device.parent);
+    }
+    depth
+}
@@ -0,0 +1,122 @@
+use x86_64::instructions::port::Port;
+
+use crate::system::{apic, interrupts};
+use crate::system::acpi_resources::Resource;
+use crate::task;
+use super::{Device, DeviceId, Driver, Error};
+
+/// The ISA irq of the keyboard port of the PS/2 controller
+const KEYBOARD_IRQ: u8 = 1;
+
+const PS2_DATA: u16 = 0x60;
+
+/// The status register when read, and the command register when written
+const PS2_COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_ENABLE_KEYBOARD: u8 = 0xAE;
+
+const CONFIG_KEYBOARD_INTERRUPT: u8 = 1 << 0;
+const CONFIG_KEYBOARD_CLOCK_DISABLED: u8 = 1 << 4;
+
+/// Makes the controller turn scan code set 2 into set 1, which is what
+/// `task::keyboard` decodes
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// The keyboard command that starts sending scancodes
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xF4;
+
+/// How many times the status is read before giving up on the controller
+const TIMEOUT: usize = 100_000;
+
+/// Routes the keyboard port's irq to the scancode queue in
+/// `task::keyboard`
+pub struct Ps2Keyboard;
+impl Driver for Ps2Keyboard {
+    fn name(&self) -> &'static str { "ps2-keyboard" }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &[
+            DeviceId::Acpi("PNP0303"),
+            DeviceId::Acpi("PNP030B"),
+            DeviceId::Platform("i8042"),
+        ]
+    }
+
+    fn attach(&self, device: Device) -> Result<(), Error> {
+        // Use the irq from the device's resources if the firmware gave one
+        let irq = match device {
+            Device::Acpi(device) => device.resources.iter()
+                .find_map(|resource| match resource {
+                    Resource::Irq(irq) => irq.irqs.first().map(|&irq| irq as u8),
+                    _ => None,
+                })
+                .unwrap_or(KEYBOARD_IRQ),
+            _ => KEYBOARD_IRQ,
+        };
+
+        let pin = apic::enable_irq(irq);
+        interrupts::set_irq_handler(pin, task::keyboard::handle_interrupt);
+        Ok(())
+    }
+
+    /// Sets the controller up like the firmware did before booting, since
+    /// it is reset while the system sleeps. Needs interrupts to be
+    /// disabled, so the keyboard's answer is not taken for a scancode.
+    fn resume(&self, _device: Device) -> Result<(), Error> {
+        unsafe {
+            // Throw away whatever the keyboard sent while waking up
+            for _ in 0..TIMEOUT {
+                if Port::<u8>::new(PS2_COMMAND).read() & STATUS_OUTPUT_FULL == 0 {
+                    break;
+                }
+                Port::<u8>::new(PS2_DATA).read();
+            }
+
+            command(COMMAND_READ_CONFIG)?;
+            let config = read()?;
+            let config = (config | CONFIG_KEYBOARD_INTERRUPT | CONFIG_TRANSLATION)
+                & !CONFIG_KEYBOARD_CLOCK_DISABLED;
+            command(COMMAND_WRITE_CONFIG)?;
+            write(config)?;
+            command(COMMAND_ENABLE_KEYBOARD)?;
+
+            // The keyboard acknowledges the command
+            write(KEYBOARD_ENABLE_SCANNING)?;
+            read()?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits until the status register has `bit` set to `set`
+unsafe fn wait(bit: u8, set: bool) -> Result<(), Error> {
+    let mut status = Port::<u8>::new(PS2_COMMAND);
+    for _ in 0..TIMEOUT {
+        if (status.read() & bit != 0) == set {
+            return Ok(());
+        }
+    }
+    Err(Error::Unsupported)
+}
+
+unsafe fn command(command: u8) -> Result<(), Error> {
+    wait(STATUS_INPUT_FULL, false)?;
+    Port::new(PS2_COMMAND).write(command);
+    Ok(())
+}
+
+unsafe fn write(data: u8) -> Result<(), Error> {
+    wait(STATUS_INPUT_FULL, false)?;
+    Port::new(PS2_DATA).write(data);
+    Ok(())
+}
+
+unsafe fn read() -> Result<u8, Error> {
+    wait(STATUS_OUTPUT_FULL, true)?;
+    Ok(Port::new(PS2_DATA).read())
+}
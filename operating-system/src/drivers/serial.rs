@@ -0,0 +1,303 @@
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::task::{Context, Poll};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::port::{PortRead, PortWrite};
+
+use crate::log_buffer;
+use crate::system::{apic, interrupts as idt};
+use crate::system::acpi_resources::Resource;
+use crate::task::AtomicWaker;
+use super::{Device, DeviceId, Driver, Error};
+
+/// The io port and ISA irq of the first serial port on PCs
+pub const COM1: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+
+/// The registers of a 16550, as offsets from its base port. The divisor
+/// latch replaces the data and interrupt enable registers while DLAB is
+/// set in the line control register.
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+/// 8 data bits, no parity and one stop bit, and the divisor latch bit
+const LINE_8N1: u8 = 0x03;
+const LINE_DLAB: u8 = 0x80;
+
+/// Enables and clears the FIFOs, with an interrupt every 14 bytes
+const FIFO_ENABLE: u8 = 0xC7;
+
+/// DTR and RTS, plus OUT2, which connects the uart's interrupt line on PCs
+const MODEM_READY: u8 = 0x03;
+const MODEM_OUT2: u8 = 0x08;
+
+/// Interrupt when received data is available
+const RECEIVED_DATA_INTERRUPT: u8 = 0x01;
+
+/// Line status bits
+const DATA_READY: u8 = 0x01;
+const TRANSMIT_EMPTY: u8 = 0x20;
+
+/// Divides the 115200 baud base clock
+const BAUD_DIVISOR: u16 = 1;
+
+/// How many times the line status is polled before a byte is dropped, so
+/// that a stuck or missing uart does not hang logging
+const TRANSMIT_TRIES: usize = 100_000;
+
+/// The number of received bytes that can be buffered before they are
+/// dropped
+const QUEUE_SIZE: usize = 256;
+
+/// The base port of the uart that output goes to
+static PORT: AtomicU16 = AtomicU16::new(COM1);
+
+/// Whether a uart answered at `PORT`
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Whether the driver is already attached, since it only drives one port
+/// even if the firmware reports more
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Held while writing, so that lines from different log calls do not mix
+static OUTPUT: Mutex<()> = Mutex::new(());
+
+static RECEIVED: Mutex<ByteQueue> = Mutex::new(ByteQueue::new());
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// A fixed size ring buffer so that the interrupt handler never allocates
+struct ByteQueue {
+    buffer: [u8; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+impl ByteQueue {
+    const fn new() -> ByteQueue {
+        ByteQueue {
+            buffer: [0; QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == QUEUE_SIZE {
+            return false;
+        }
+        self.buffer[(self.head + self.len) % QUEUE_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buffer[self.head];
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// Programs the uart at `base` for 115200 baud 8N1 with polled output
+/// and moves output to it. Returns false if no uart answers there.
+pub fn init_port(base: u16) -> bool {
+    unsafe {
+        // A uart keeps what is written to its scratch register
+        u8::write_to_port(base + SCRATCH, 0xAE);
+        if u8::read_from_port(base + SCRATCH) != 0xAE {
+            return false;
+        }
+
+        u8::write_to_port(base + INTERRUPT_ENABLE, 0);
+        u8::write_to_port(base + LINE_CONTROL, LINE_DLAB);
+        u8::write_to_port(base + DIVISOR_LOW, BAUD_DIVISOR as u8);
+        u8::write_to_port(base + DIVISOR_HIGH, (BAUD_DIVISOR >> 8) as u8);
+        u8::write_to_port(base + LINE_CONTROL, LINE_8N1);
+        u8::write_to_port(base + FIFO_CONTROL, FIFO_ENABLE);
+        u8::write_to_port(base + MODEM_CONTROL, MODEM_READY);
+    }
+    PORT.store(base, Ordering::SeqCst);
+    PRESENT.store(true, Ordering::SeqCst);
+    true
+}
+
+/// Programs the uart that output goes to again after the system woke
+/// from sleep. Returns false if there is none or it no longer answers.
+pub fn resume_port() -> bool {
+    PRESENT.load(Ordering::SeqCst) && init_port(PORT.load(Ordering::SeqCst))
+}
+
+/// Writes formatted text to the serial port, turning line feeds into
+/// carriage return line feed pairs
+pub fn write_fmt(args: fmt::Arguments) {
+    if !PRESENT.load(Ordering::SeqCst) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let _lock = OUTPUT.lock();
+        let _ = fmt::Write::write_fmt(&mut Writer, args);
+    });
+}
+
+/// Writes formatted text to the serial port unless another write holds
+/// it, for when waiting could hang, like in the panic handler
+pub fn try_write_fmt(args: fmt::Arguments) {
+    if !PRESENT.load(Ordering::SeqCst) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        if let Some(_lock) = OUTPUT.try_lock() {
+            let _ = fmt::Write::write_fmt(&mut Writer, args);
+        }
+    });
+}
+
+struct Writer;
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                write_byte(b'\r');
+            }
+            write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+fn write_byte(byte: u8) {
+    let base = PORT.load(Ordering::Relaxed);
+    for _ in 0..TRANSMIT_TRIES {
+        if unsafe { u8::read_from_port(base + LINE_STATUS) } & TRANSMIT_EMPTY != 0 {
+            unsafe { u8::write_to_port(base + DATA, byte) };
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Called by the uart's interrupt handler. Drains the receive FIFO into
+/// the queue.
+fn handle_interrupt() {
+    let base = PORT.load(Ordering::Relaxed);
+    let mut received = false;
+    if let Some(mut queue) = RECEIVED.try_lock() {
+        while unsafe { u8::read_from_port(base + LINE_STATUS) } & DATA_READY != 0 {
+            received |= queue.push(unsafe { u8::read_from_port(base + DATA) });
+        }
+    }
+    if received {
+        WAKER.wake();
+    }
+}
+
+/// Waits for the next byte received on the serial port
+pub fn next_byte() -> NextByte { NextByte }
+
+/// Takes the next byte received on the serial port without waiting
+pub fn try_byte() -> Option<u8> {
+    interrupts::without_interrupts(|| RECEIVED.lock().pop())
+}
+
+pub struct NextByte;
+impl Future for NextByte {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<u8> {
+        if let Some(byte) = try_byte() {
+            return Poll::Ready(byte);
+        }
+
+        WAKER.register(cx.waker());
+        match try_byte() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Drives the first serial port, taking its port and irq from the acpi
+/// namespace when the firmware reports it or using COM1 otherwise, and
+/// turns on interrupt driven receiving. Output moves to that port.
+pub struct Serial;
+impl Driver for Serial {
+    fn name(&self) -> &'static str { "serial" }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &[
+            DeviceId::Acpi("PNP0501"),
+            DeviceId::Platform("com1"),
+        ]
+    }
+
+    fn probe(&self, _device: Device) -> bool {
+        !ATTACHED.load(Ordering::SeqCst)
+    }
+
+    fn attach(&self, device: Device) -> Result<(), Error> {
+        let (base, irq) = match device {
+            Device::Acpi(device) => (
+                device.resources.iter()
+                    .find_map(|resource| match resource {
+                        Resource::Io(io) => Some(io.min),
+                        _ => None,
+                    })
+                    .unwrap_or(COM1),
+                device.resources.iter()
+                    .find_map(|resource| match resource {
+                        Resource::Irq(irq) => irq.irqs.first().map(|&irq| irq as u8),
+                        _ => None,
+                    })
+                    .unwrap_or(COM1_IRQ),
+            ),
+            _ => (COM1, COM1_IRQ),
+        };
+
+        let was_present = PRESENT.load(Ordering::SeqCst);
+        if !init_port(base) {
+            return Err(Error::Unsupported);
+        }
+
+        // Nothing was written before if COM1 was not where the port is
+        if !was_present {
+            log_buffer::replay(|entry| write_fmt(format_args!("{}\n", entry)));
+        }
+
+        let pin = apic::enable_irq(irq);
+        idt::set_irq_handler(pin, handle_interrupt);
+        enable_interrupts(base);
+        ATTACHED.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// The port itself is programmed again by `resume_port` before the
+    /// drivers are resumed, and the irq is routed again by the apic
+    fn resume(&self, _device: Device) -> Result<(), Error> {
+        if !PRESENT.load(Ordering::SeqCst) {
+            return Err(Error::Unsupported);
+        }
+        enable_interrupts(PORT.load(Ordering::SeqCst));
+        Ok(())
+    }
+}
+
+/// Makes the uart raise its irq when a byte is received
+fn enable_interrupts(base: u16) {
+    unsafe {
+        u8::write_to_port(base + MODEM_CONTROL, MODEM_READY | MODEM_OUT2);
+        u8::write_to_port(base + INTERRUPT_ENABLE, RECEIVED_DATA_INTERRUPT);
+    }
+}
@@ -0,0 +1,166 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use uefi::proto::media::file::{
+    Directory, File as _, FileAttribute, FileMode, FileType, RegularFile,
+};
+use uefi::proto::media::fs::SimpleFileSystem;
+
+use super::Error;
+use crate::ST;
+
+/// The maximum number of files that can be open in a single FileTable
+const MAX_OPEN_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Read,
+    ReadWrite,
+    Create,
+}
+
+/// A file on the volume that the os was booted from
+pub struct File {
+    inner: RegularFile,
+}
+impl File {
+    /// Reads from the current position into a buffer, returning the
+    /// number of bytes read
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.inner.read(buf).map(|c| c.split().1).map_err(|_| Error::Io)
+    }
+
+    /// Writes a buffer at the current position
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.inner
+            .write(buf)
+            .map(|_| buf.len())
+            .map_err(|_| Error::Io)
+    }
+
+    /// Writes buffered data to the volume
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().map(|_| ()).map_err(|_| Error::Io)
+    }
+
+    /// Deletes the file from the volume
+    pub fn delete(self) -> Result<(), Error> {
+        self.inner.delete().map(|_| ()).map_err(|_| Error::Io)
+    }
+
+    /// Moves the current position to an offset from the start of the file
+    pub fn seek(&mut self, position: u64) -> Result<(), Error> {
+        self.inner
+            .set_position(position)
+            .map(|_| ())
+            .map_err(|_| Error::Io)
+    }
+}
+
+/// Opens the root directory of the boot volume
+fn root() -> Result<Directory, Error> {
+    let st = unsafe { ST.as_ref() }.ok_or(Error::NoBootVolume)?;
+
+    // Get the simple file system protocol
+    let fs = st
+        .boot_services()
+        .locate_protocol::<SimpleFileSystem>()
+        .map_err(|_| Error::NoBootVolume)?
+        .split()
+        .1;
+    let fs = unsafe { &mut *fs.get() };
+
+    fs.open_volume()
+        .map(|c| c.split().1)
+        .map_err(|_| Error::NoBootVolume)
+}
+
+/// Opens a file on the boot volume. Paths use '\' or '/' as separators.
+pub fn open(path: &str, mode: OpenMode) -> Result<File, Error> {
+    // UEFI only understands backslashes
+    let path: alloc::string::String = path
+        .trim_start_matches(|c| c == '/' || c == '\\')
+        .chars()
+        .map(|c| if c == '/' { '\\' } else { c })
+        .collect();
+
+    let mode = match mode {
+        OpenMode::Read => FileMode::Read,
+        OpenMode::ReadWrite => FileMode::ReadWrite,
+        OpenMode::Create => FileMode::CreateReadWrite,
+    };
+
+    let handle = root()?
+        .open(&path, mode, FileAttribute::empty())
+        .map_err(|_| Error::NotFound)?
+        .split()
+        .1;
+
+    match handle.into_type().map_err(|_| Error::Io)?.split().1 {
+        FileType::Regular(inner) => Ok(File { inner }),
+        FileType::Dir(_) => Err(Error::NotAFile),
+    }
+}
+
+/// Reads the entire contents of a file on the boot volume
+pub fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    let mut file = open(path, OpenMode::Read)?;
+    let mut contents = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        contents.extend_from_slice(&chunk[..read]);
+    }
+    Ok(contents)
+}
+
+/// A table of open files indexed by file descriptor
+pub struct FileTable {
+    files: Vec<Option<File>>,
+}
+impl FileTable {
+    /// The first descriptor handed out for files. Lower descriptors are
+    /// reserved for the console.
+    pub const FIRST_FILE: usize = 3;
+
+    /// Creates an empty FileTable object
+    pub const fn new() -> FileTable { FileTable { files: Vec::new() } }
+
+    /// Adds a file to the table and returns its descriptor
+    pub fn insert(&mut self, file: File) -> Result<usize, Error> {
+        // Reuse a closed slot if there is one
+        if let Some(index) = self.files.iter().position(Option::is_none) {
+            self.files[index] = Some(file);
+            return Ok(index + Self::FIRST_FILE);
+        }
+
+        if self.files.len() == MAX_OPEN_FILES {
+            return Err(Error::TooManyFiles);
+        }
+        self.files.push(Some(file));
+        Ok(self.files.len() - 1 + Self::FIRST_FILE)
+    }
+
+    /// Gets an open file by its descriptor
+    pub fn get(&mut self, fd: usize) -> Result<&mut File, Error> {
+        fd.checked_sub(Self::FIRST_FILE)
+            .and_then(|index| self.files.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(Error::BadDescriptor)
+    }
+
+    /// Closes a file by its descriptor
+    pub fn close(&mut self, fd: usize) -> Result<(), Error> {
+        fd.checked_sub(Self::FIRST_FILE)
+            .and_then(|index| self.files.get_mut(index))
+            .and_then(Option::take)
+            .map(drop)
+            .ok_or(Error::BadDescriptor)
+    }
+
+    /// Closes every file in the table
+    pub fn clear(&mut self) { self.files.clear(); }
+}
@@ -1,2 +1,15 @@
-pub mod ntfs;
-pub mod fat;
+// pub mod ntfs;
+// pub mod fat;
+mod boot_volume;
+
+pub use boot_volume::{open, read_file, File, FileTable, OpenMode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    NoBootVolume,
+    NotFound,
+    NotAFile,
+    BadDescriptor,
+    TooManyFiles,
+    Io,
+}
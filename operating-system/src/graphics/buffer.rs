@@ -1,15 +1,27 @@
 extern crate alloc;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::ops::Range;
 
 use rusttype::{point, Font, Scale};
 
-use super::{Color, Location, Pixel, PixelFormat, Size};
+use super::{glyphs, Color, Location, Pixel, PixelFormat, Size};
+use crate::ipc::{self, SharedMemory};
+
+/// Where the pixels of a buffer are stored
+#[derive(Debug)]
+enum Pixels {
+    Owned(Vec<Pixel>),
+
+    /// A shared memory region that a process can map and draw to
+    Shared(Arc<SharedMemory>),
+}
 
 #[derive(Debug)]
 pub struct Buffer {
     /// The pixels making up the buffer
-    pixels: Vec<Pixel>,
+    pixels: Pixels,
 
     /// The size of the buffer
     size: Size,
@@ -26,16 +38,41 @@ impl Buffer {
 
         // Creates the buffer object
         Buffer {
-            pixels: vec![pixel; size.width * size.height],
+            pixels: Pixels::Owned(vec![pixel; size.width * size.height]),
             size,
             fmt,
         }
     }
+
+    /// Creates a black buffer whose pixels are stored in shared memory
+    pub fn shared(size: Size, fmt: PixelFormat) -> Result<Buffer, ipc::Error> {
+        let len = size.width * size.height * core::mem::size_of::<Pixel>();
+        let region = SharedMemory::new(len)?;
+        Ok(Buffer {
+            pixels: Pixels::Shared(Arc::new(region)),
+            size,
+            fmt,
+        })
+    }
+
+    /// Gets the shared memory region that holds the pixels, if there
+    /// is one
+    pub fn shared_memory(&self) -> Option<&Arc<SharedMemory>> {
+        match &self.pixels {
+            Pixels::Shared(region) => Some(region),
+            Pixels::Owned(_) => None,
+        }
+    }
 }
 impl BufferTrait for Buffer {
     fn size(&self) -> Size { self.size }
 
-    fn ptr(&mut self) -> *mut Pixel { self.pixels.as_mut_ptr() }
+    fn ptr(&mut self) -> *mut Pixel {
+        match &mut self.pixels {
+            Pixels::Owned(pixels) => pixels.as_mut_ptr(),
+            Pixels::Shared(region) => region.as_mut_ptr() as *mut Pixel,
+        }
+    }
 
     fn fmt(&self) -> PixelFormat { self.fmt }
 }
@@ -44,6 +81,10 @@ pub trait BufferTrait {
     fn ptr(&mut self) -> *mut Pixel;
     fn fmt(&self) -> PixelFormat;
 
+    /// Called after drawing to a rectangle of the buffer. Buffers that
+    /// have to copy what changed elsewhere keep track of it.
+    fn damage(&mut self, _loc: Location, _size: Size) {}
+
     /// Creates a new color that follows the format of the buffer
     fn new_pixel(&self, color: Color) -> Pixel { Pixel::new(color, self.fmt()) }
 
@@ -73,67 +114,84 @@ pub trait BufferTrait {
                 core::ptr::write_volatile(ptr.offset(i as isize), pixel);
             }
         }
+        self.damage(Location { x: 0, y: 0 }, size);
+    }
+
+    /// Fills a rectangle with a color, clipped to the buffer
+    fn fill_rect(&mut self, loc: Location, size: Size, color: Color) {
+        let pixel = self.new_pixel(color);
+        let (width, height) = self.size().tuple();
+        let ptr = self.ptr();
+        let (x0, x1) = clip(loc.x, size.width, width);
+        let (y0, y1) = clip(loc.y, size.height, height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                unsafe { core::ptr::write_volatile(ptr.add(y * width + x), pixel) };
+            }
+        }
+        self.damage(loc, size);
     }
 
-    /// Write a string to a buffer
+    /// Draws a coverage mask to a rectangle, where 0 is `bg` and 255 is
+    /// `fg`. The background is given rather than read back from the
+    /// buffer, since reading a framebuffer is slow.
+    fn draw_mask(&mut self, loc: Location, size: Size, mask: &[u8], fg: Color, bg: Color) {
+        let (width, height) = self.size().tuple();
+        let ptr = self.ptr();
+        let (x0, x1) = clip(loc.x, size.width, width);
+        let (y0, y1) = clip(loc.y, size.height, height);
+        for y in y0..y1 {
+            let row = (y as isize - loc.y) as usize * size.width;
+            for x in x0..x1 {
+                let alpha = mask[row + (x as isize - loc.x) as usize];
+                let pixel = self.new_pixel(blend(fg, bg, alpha));
+                unsafe { core::ptr::write_volatile(ptr.add(y * width + x), pixel) };
+            }
+        }
+        self.damage(loc, size);
+    }
+
+    /// Write a string to a buffer, with the baseline at `loc`. Glyphs
+    /// come from the glyph cache and are blended with what is already in
+    /// the buffer.
     fn write_text(
         &mut self,
         string: &str,
         loc: Location,
-        font: &Font,
+        font: &Font<'static>,
         height: f32,
         c: Color,
     ) {
-        let (buffer_width, _h) = self.size().tuple();
-        let buffer_width = buffer_width as isize;
-
-        let ptr = unsafe { self.ptr().offset(loc.y * buffer_width + loc.x) };
-
-        let scale = Scale {
-            x: height,
-            y: height,
-        };
-
-        let offset = point(0.0, 0.0);
-        let glyphs = font.layout(string, scale, offset);
+        let (buffer_width, buffer_height) = self.size().tuple();
+        let ptr = self.ptr();
+        let pixel = self.new_pixel(c);
 
-        // Go through each glyph in the string
+        // Glyphs are drawn at whole pixels so that each one is only
+        // rasterized once
+        let glyphs = font.layout(string, Scale::uniform(height), point(0.0, 0.0));
         for g in glyphs {
-            if let Some(bb) = g.pixel_bounding_box() {
-                // Draw the glyph
-                g.draw(|x, y, v| {
-                    let x = (x as i32 + bb.min.x) as isize;
-                    let y = (y as i32 + bb.min.y) as isize;
-
-                    // Gets the pixel that was previously in this location
-                    let old_p = unsafe {
-                        core::ptr::read_volatile(ptr.offset(x + y * buffer_width))
-                    };
-                    let old_c = self.color_from_pixel(old_p);
-
-                    // Gets the old colors from the pixel
-                    let or = old_c.red as f32;
-                    let og = old_c.green as f32;
-                    let ob = old_c.blue as f32;
-
-                    // Gets the font-color
-                    let r = c.red as f32;
-                    let g = c.green as f32;
-                    let b = c.blue as f32;
-
-                    // Calculates the new color values
-                    let nr = ((or * (1.0 - v)) + (r * v)) as u8;
-                    let ng = ((og * (1.0 - v)) + (g * v)) as u8;
-                    let nb = ((ob * (1.0 - v)) + (b * v)) as u8;
-
-                    unsafe {
-                        core::ptr::write_volatile(
-                            ptr.offset(x + y * buffer_width),
-                            self.new_pixel(Color::new(nr, ng, nb)),
-                        );
+            let glyph = glyphs::glyph(font, Scale::uniform(height), g.id());
+            let x = loc.x + libm::roundf(g.position().x) as isize + glyph.offset.x;
+            let y = loc.y + glyph.offset.y;
+            let (x0, x1) = clip(x, glyph.size.width, buffer_width);
+            let (y0, y1) = clip(y, glyph.size.height, buffer_height);
+
+            for py in y0..y1 {
+                let row = (py as isize - y) as usize * glyph.size.width;
+                for px in x0..x1 {
+                    let alpha = glyph.mask[row + (px as isize - x) as usize];
+                    let dst = unsafe { ptr.add(py * buffer_width + px) };
+                    match alpha {
+                        0 => (),
+                        255 => unsafe { *dst = pixel },
+                        alpha => unsafe {
+                            let old = self.color_from_pixel(*dst);
+                            *dst = self.new_pixel(blend(c, old, alpha));
+                        },
                     }
-                })
+                }
             }
+            self.damage(Location { x, y }, glyph.size);
         }
     }
 
@@ -186,17 +244,89 @@ pub trait BufferTrait {
             block_width = dst_width - x;
         }
 
+        // Rgb and Bgr pixels only differ in the order of red and blue
+        let swap = self.fmt() != src_buffer.fmt();
+
         let mut dst_ptr = unsafe { self.ptr().offset(y * dst_width + x) };
         let mut src_ptr = src_buffer.ptr();
         for _ in 0..block_height {
             for i in 0..block_width {
                 // Write a pixel from the destination buffer to the source buffer
                 unsafe {
-                    core::ptr::write_volatile(dst_ptr.offset(i), *src_ptr.offset(i));
+                    let mut pixel = *src_ptr.offset(i);
+                    if swap {
+                        pixel.inner.swap(0, 2);
+                    }
+                    core::ptr::write_volatile(dst_ptr.offset(i), pixel);
                 }
             }
             dst_ptr = unsafe { dst_ptr.offset(dst_width) };
             src_ptr = unsafe { src_ptr.offset(block_width) };
         }
+        self.damage(
+            Location { x, y },
+            Size { width: block_width as usize, height: block_height as usize },
+        );
+    }
+
+    /// Moves rows of pixels `by..end` up to the top of the buffer, which
+    /// scrolls text without drawing it again
+    fn scroll_up(&mut self, end: usize, by: usize) {
+        let (width, height) = self.size().tuple();
+        let end = end.min(height);
+        if by >= end {
+            return;
+        }
+        let ptr = self.ptr();
+        unsafe { core::ptr::copy(ptr.add(by * width), ptr, (end - by) * width) };
+        self.damage(Location { x: 0, y: 0 }, Size { width, height: end - by });
+    }
+
+    /// Copies some rows of src_buffer to self, where the top-left corner
+    /// of src_buffer is at `loc`
+    fn transfer_rows(&mut self, src_buffer: &mut dyn BufferTrait, rows: Range<usize>, loc: Location) {
+        let (dst_width, dst_height) = self.size().tuple();
+        let (src_width, src_height) = src_buffer.size().tuple();
+        let height = rows.end.min(src_height).saturating_sub(rows.start);
+        let (x0, x1) = clip(loc.x, src_width, dst_width);
+        let (y0, y1) = clip(loc.y + rows.start as isize, height, dst_height);
+
+        // Rgb and Bgr pixels only differ in the order of red and blue
+        let swap = self.fmt() != src_buffer.fmt();
+
+        let dst_ptr = self.ptr();
+        let src_ptr = src_buffer.ptr();
+        for y in y0..y1 {
+            let src_row = (y as isize - loc.y) as usize * src_width;
+            for x in x0..x1 {
+                unsafe {
+                    let mut pixel = *src_ptr.add(src_row + (x as isize - loc.x) as usize);
+                    if swap {
+                        pixel.inner.swap(0, 2);
+                    }
+                    core::ptr::write_volatile(dst_ptr.add(y * dst_width + x), pixel);
+                }
+            }
+        }
+        self.damage(
+            Location { x: x0 as isize, y: y0 as isize },
+            Size { width: x1 - x0, height: y1 - y0 },
+        );
     }
 }
+
+/// Gets the part of a span starting at `start` that lies within
+/// `0..limit`
+fn clip(start: isize, len: usize, limit: usize) -> (usize, usize) {
+    let end = (start + len as isize).min(limit as isize).max(0) as usize;
+    (start.max(0).min(end as isize) as usize, end)
+}
+
+/// Mixes two colors, with an alpha of 255 giving `fg`
+fn blend(fg: Color, bg: Color, alpha: u8) -> Color {
+    let mix = |fg: u8, bg: u8| {
+        let alpha = alpha as u16;
+        ((fg as u16 * alpha + bg as u16 * (255 - alpha) + 127) / 255) as u8
+    };
+    Color::new(mix(fg.red, bg.red), mix(fg.green, bg.green), mix(fg.blue, bg.blue))
+}
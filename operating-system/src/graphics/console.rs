@@ -0,0 +1,184 @@
+//! A text console drawn on the framebuffer, which kernel logs go to once
+//! the graphics mode is set, since the UEFI console stops working then.
+//! The text is kept in a `TextGrid`, which only draws the cells that
+//! changed.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::Level;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::text_grid::{self, Cell, TextGrid};
+use super::{fonts, try_with_display, with_display, BufferTrait, Color, MonospaceFont, Size};
+
+/// The height of a line of the console in pixels
+const FONT_HEIGHT: f32 = 18.0;
+
+const BACKGROUND: Color = Color { red: 0, green: 0, blue: 0 };
+const FOREGROUND: Color = Color { red: 220, green: 220, blue: 220 };
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+
+/// Whether the console has been set up and output should go to it
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+const BLANK: Cell = Cell { c: ' ', fg: FOREGROUND, bg: BACKGROUND };
+
+pub struct Console {
+    /// The text of the console, which does not keep a scrollback
+    grid: TextGrid,
+    columns: usize,
+    rows: usize,
+
+    /// The position of the cursor, which can be one past the last column
+    /// until the next character wraps the line
+    column: usize,
+    row: usize,
+
+    /// The color of the text that is written next
+    color: Color,
+}
+impl Console {
+    /// Creates a console that fills an area of `size` pixels
+    pub fn new(font: MonospaceFont, size: Size) -> Console {
+        let grid = TextGrid::new(font, size, BLANK, 0);
+        let (columns, rows) = (grid.columns(), grid.rows());
+        Console {
+            grid,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            color: FOREGROUND,
+        }
+    }
+
+    /// Sets the color of the text that is written next
+    pub fn set_color(&mut self, color: Color) { self.color = color; }
+
+    /// Forgets what is on the screen, so that the next flush draws every
+    /// cell
+    pub fn invalidate(&mut self) { self.grid.invalidate(); }
+
+    fn put(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\u{8}' => if self.column > 0 {
+                self.column -= 1;
+                self.grid.line(self.row)[self.column] = BLANK;
+            },
+            '\t' => {
+                let column = text_grid::next_tab(self.column);
+                while self.column < column.min(self.columns) {
+                    self.put(' ');
+                }
+            },
+            c if c.is_control() => (),
+            c => {
+                if self.column == self.columns {
+                    self.new_line();
+                }
+                self.grid.line(self.row)[self.column] = Cell { c, fg: self.color, bg: BACKGROUND };
+                self.column += 1;
+            },
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.grid.scroll_up(0, self.rows - 1, 1, BLANK);
+        }
+    }
+
+    /// Draws the cells that changed since the last flush, and the cursor
+    pub fn flush(&mut self, buffer: &mut dyn BufferTrait) {
+        self.grid.draw(buffer, Some((self.column, self.row)));
+    }
+}
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|c| self.put(c));
+        Ok(())
+    }
+}
+
+/// Sets the graphics mode and starts the console. Returns false if there
+/// is no graphics output or the system font is missing.
+pub fn init() -> bool {
+    let size = match with_display(|screen, _| screen.size()) {
+        Some(size) => size,
+        None => return false,
+    };
+    let font = match fonts::init() {
+        Some(font) => font,
+        None => return false,
+    };
+
+    let console = Console::new(MonospaceFont::new(font, FONT_HEIGHT), size);
+    with_display(|screen, _| {
+        screen.fill(BACKGROUND);
+        screen.present();
+    });
+    interrupts::without_interrupts(|| *CONSOLE.lock() = Some(console));
+    ACTIVE.store(true, Ordering::SeqCst);
+    true
+}
+
+/// Checks whether output goes to the console
+pub fn is_active() -> bool { ACTIVE.load(Ordering::SeqCst) }
+
+/// Writes text in a color and draws it, unless windows cover the console.
+/// The text is dropped if the console is already in use on this cpu,
+/// like when something logs while it draws, and is drawn with the next
+/// text if only the display is.
+pub fn write_fmt(color: Color, args: fmt::Arguments) {
+    with_console(|console| {
+        console.set_color(color);
+        let _ = fmt::Write::write_fmt(console, args);
+        try_with_display(|screen, wm| {
+            if !wm.has_visible_windows() {
+                console.flush(screen);
+                screen.present();
+            }
+        });
+    });
+}
+
+/// Draws the whole console again. Called by the window manager when no
+/// windows are left on top of it.
+pub fn redraw(buffer: &mut dyn BufferTrait) {
+    with_console(|console| {
+        console.invalidate();
+        console.flush(buffer);
+    });
+}
+
+/// Gets the color that logs of a level are written in
+pub fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::new(255, 85, 85),
+        Level::Warn => Color::new(255, 200, 60),
+        Level::Info => FOREGROUND,
+        Level::Debug => Color::new(110, 170, 255),
+        Level::Trace => Color::new(130, 130, 130),
+    }
+}
+
+fn with_console(f: impl FnOnce(&mut Console)) {
+    if !is_active() {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if let Some(console) = console.as_mut() {
+                f(console);
+            }
+        }
+    });
+}
@@ -0,0 +1,43 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{Screen, WindowManager};
+use crate::ST;
+
+/// The screen and the windows drawn to it
+static DISPLAY: Mutex<Option<(Screen, WindowManager)>> = Mutex::new(None);
+
+/// Calls a function with the screen and window manager, setting the
+/// graphics mode the first time. Returns None if there is no graphics
+/// output.
+pub fn with_display<R>(f: impl FnOnce(&mut Screen, &mut WindowManager) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut display = DISPLAY.lock();
+        if display.is_none() {
+            let st = unsafe { ST.as_ref() }?;
+            *display = Some((Screen::init(st.boot_services()), WindowManager::new()));
+        }
+        let (screen, wm) = display.as_mut()?;
+        Some(f(screen, wm))
+    })
+}
+
+/// Calls a function with the screen and window manager if the graphics
+/// mode has already been set
+pub fn with_initialized_display<R>(f: impl FnOnce(&mut Screen, &mut WindowManager) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut display = DISPLAY.lock();
+        let (screen, wm) = display.as_mut()?;
+        Some(f(screen, wm))
+    })
+}
+
+/// Like `with_initialized_display`, but gives up if the display is in
+/// use, like when something logs while it is being drawn
+pub fn try_with_display<R>(f: impl FnOnce(&mut Screen, &mut WindowManager) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut display = DISPLAY.try_lock()?;
+        let (screen, wm) = display.as_mut()?;
+        Some(f(screen, wm))
+    })
+}
@@ -0,0 +1,197 @@
+//! The glyph cache, which keeps every glyph that has been drawn as its
+//! own coverage mask, and the monospace font drawn from it. Glyphs are
+//! not packed into an atlas, since they are only ever copied to buffers
+//! in memory.
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use lazy_static::lazy_static;
+use rusttype::{point, Font, GlyphId, Scale};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{BufferTrait, Color, Location, Size};
+
+/// The character whose advance sets the width of every cell. Digits have
+/// the same width in most fonts.
+const CELL_CHAR: char = '0';
+
+/// The most glyphs kept in the glyph cache before it is emptied
+const MAX_CACHED_GLYPHS: usize = 4096;
+
+lazy_static! {
+    static ref CACHE: Mutex<GlyphCache> = Mutex::new(GlyphCache {
+        fonts: Vec::new(),
+        glyphs: BTreeMap::new(),
+    });
+}
+
+/// A glyph rasterized at one size, as a coverage mask
+pub struct CachedGlyph {
+    /// Where the top-left corner of the mask goes, relative to the point
+    /// on the baseline that the glyph is drawn at
+    pub offset: Location,
+    pub size: Size,
+    pub mask: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    /// The address of the font's data, which its clones share
+    font: usize,
+
+    /// The bits of the horizontal and vertical scale in pixels
+    scale: (u32, u32),
+    glyph: GlyphId,
+}
+
+/// Every glyph drawn by `BufferTrait::write_text` and `MonospaceFont`, so
+/// that each glyph of a font and scale is only rasterized once
+struct GlyphCache {
+    /// The fonts that have glyphs in the cache. Keeping them means their
+    /// data is not freed, so its address can not be reused by another
+    /// font while it is part of a key.
+    fonts: Vec<Font<'static>>,
+    glyphs: BTreeMap<Key, Arc<CachedGlyph>>,
+}
+
+/// Gets a glyph of a font at a scale in pixels, rasterizing it if it is
+/// not in the cache
+pub fn glyph(font: &Font<'static>, scale: Scale, id: GlyphId) -> Arc<CachedGlyph> {
+    let key = Key {
+        font: font_address(font),
+        scale: (scale.x.to_bits(), scale.y.to_bits()),
+        glyph: id,
+    };
+    if let Some(glyph) = interrupts::without_interrupts(|| CACHE.lock().glyphs.get(&key).cloned()) {
+        return glyph;
+    }
+
+    // Rasterize without holding the lock, since it allocates
+    let glyph = Arc::new(rasterize_glyph(font, scale, id));
+    interrupts::without_interrupts(|| {
+        let mut cache = CACHE.lock();
+        if cache.glyphs.len() == MAX_CACHED_GLYPHS {
+            cache.glyphs.clear();
+            cache.fonts.clear();
+        }
+        if !cache.fonts.iter().any(|f| font_address(f) == key.font) {
+            cache.fonts.push(font.clone());
+        }
+        cache.glyphs.insert(key, glyph.clone());
+    });
+    glyph
+}
+
+fn font_address(font: &Font) -> usize {
+    match font {
+        Font::Ref(data) => Arc::as_ptr(data) as *const u8 as usize,
+        Font::Owned(data) => Arc::as_ptr(data) as *const u8 as usize,
+    }
+}
+
+/// Renders a glyph with its pen position at the origin
+fn rasterize_glyph(font: &Font, scale: Scale, id: GlyphId) -> CachedGlyph {
+    let glyph = font.glyph(id).scaled(scale).positioned(point(0.0, 0.0));
+    let bb = match glyph.pixel_bounding_box() {
+        Some(bb) => bb,
+        None => return CachedGlyph {
+            offset: Location { x: 0, y: 0 },
+            size: Size { width: 0, height: 0 },
+            mask: Vec::new(),
+        },
+    };
+
+    let size = Size { width: bb.width() as usize, height: bb.height() as usize };
+    let mut mask = vec![0; size.width * size.height];
+    glyph.draw(|x, y, v| {
+        mask[y as usize * size.width + x as usize] = (v * 255.0) as u8;
+    });
+    CachedGlyph {
+        offset: Location { x: bb.min.x as isize, y: bb.min.y as isize },
+        size,
+        mask,
+    }
+}
+
+/// Draws a proportional font on a grid of equal cells. Each glyph is
+/// centered in its cell, and squeezed if it is too wide.
+pub struct MonospaceFont {
+    font: Font<'static>,
+    scale: Scale,
+    cell: Size,
+
+    /// The distance from the top of a cell to the baseline
+    ascent: f32,
+
+    /// The coverage of the cell being drawn, kept so that drawing does
+    /// not allocate
+    mask: Vec<u8>,
+}
+impl MonospaceFont {
+    pub fn new(font: Font<'static>, height: f32) -> MonospaceFont {
+        let scale = Scale::uniform(height);
+        let metrics = font.v_metrics(scale);
+        let width = font.glyph(CELL_CHAR).scaled(scale).h_metrics().advance_width;
+        let cell = Size {
+            width: libm::ceilf(width) as usize,
+            height: libm::ceilf(metrics.ascent - metrics.descent + metrics.line_gap) as usize,
+        };
+        MonospaceFont {
+            font,
+            scale,
+            cell,
+            ascent: metrics.ascent,
+            mask: vec![0; cell.width * cell.height],
+        }
+    }
+
+    /// Gets the size of a cell in pixels
+    pub fn cell(&self) -> Size { self.cell }
+
+    /// Draws a character into the cell whose top-left corner is at `loc`
+    pub fn draw(
+        &mut self,
+        buffer: &mut dyn BufferTrait,
+        c: char,
+        loc: Location,
+        fg: Color,
+        bg: Color,
+    ) {
+        let cell = self.cell;
+        let width = cell.width as f32;
+        let id = self.font.glyph(c).id();
+        let advance = self.font.glyph(id).scaled(self.scale).h_metrics().advance_width;
+        let scale = if advance > width {
+            Scale { x: self.scale.x * width / advance, y: self.scale.y }
+        } else {
+            self.scale
+        };
+        let glyph = glyph(&self.font, scale, id);
+
+        // Where the top-left corner of the glyph's mask is in the cell
+        let x = libm::roundf((width - advance.min(width)) / 2.0) as isize + glyph.offset.x;
+        let y = libm::roundf(self.ascent) as isize + glyph.offset.y;
+
+        // Parts of the glyph outside the cell are cut off
+        self.mask.iter_mut().for_each(|alpha| *alpha = 0);
+        for gy in 0..glyph.size.height {
+            let cy = y + gy as isize;
+            if cy < 0 || cy >= cell.height as isize {
+                continue;
+            }
+            for gx in 0..glyph.size.width {
+                let cx = x + gx as isize;
+                if cx >= 0 && cx < cell.width as isize {
+                    let alpha = glyph.mask[gy * glyph.size.width + gx];
+                    self.mask[cy as usize * cell.width + cx as usize] = alpha;
+                }
+            }
+        }
+        buffer.draw_mask(loc, cell, &self.mask, fg, bg);
+    }
+}
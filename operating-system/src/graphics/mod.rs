@@ -1,11 +1,21 @@
+pub mod console;
 pub mod fonts;
+pub mod terminal;
+
+mod glyphs;
+pub use glyphs::MonospaceFont;
+
+mod text_grid;
 
 mod window;
-pub use window::{Window, WindowManager};
+pub use window::{Window, WindowId, WindowManager};
 
 mod screen;
 pub use screen::Screen;
 
+mod display;
+pub use display::{try_with_display, with_display, with_initialized_display};
+
 mod primitives;
 pub use primitives::{Color, Location, Pixel, PixelFormat, Size};
 
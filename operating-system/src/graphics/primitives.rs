@@ -18,7 +18,7 @@ impl Size {
     pub fn tuple(&self) -> (usize, usize) { (self.width, self.height) }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
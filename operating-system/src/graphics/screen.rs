@@ -1,16 +1,33 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
 use uefi::proto::console::gop::GraphicsOutput;
 use uefi::table::boot::BootServices;
 use uefi::ResultExt;
 
-use super::{BufferTrait, Pixel, PixelFormat, Size};
+use super::{BufferTrait, Color, Location, Pixel, PixelFormat, Size};
 
 #[derive(Debug)]
 pub struct Screen {
-    ptr: *mut Pixel,
+    /// The framebuffer, which has `stride` pixels per row
+    framebuffer: *mut Pixel,
+    stride: usize,
+
+    /// Everything is drawn here first and copied to the framebuffer by
+    /// `present`, since reading from the framebuffer is slow
+    back: Vec<Pixel>,
     size: Size,
     fmt: PixelFormat,
+
+    /// The rectangle drawn to since the last present, as the first and
+    /// one past the last column and row
+    damage: Option<(usize, usize, usize, usize)>,
 }
 
+// The framebuffer is only reached through the display's lock
+unsafe impl Send for Screen {}
+
 impl Screen {
     pub fn init(bs: &BootServices) -> Screen {
         // Get the graphics output protocol
@@ -57,17 +74,54 @@ impl Screen {
         let size = Size { width, height };
 
         // Make a structure out of the information
+        let fmt = best_mode.info().pixel_format();
         Screen {
-            ptr: graphics_output.frame_buffer().as_mut_ptr() as *mut Pixel,
+            framebuffer: graphics_output.frame_buffer().as_mut_ptr() as *mut Pixel,
+            stride: best_mode.info().stride(),
+            back: vec![Pixel::new(Color::new(0, 0, 0), fmt); width * height],
             size,
-            fmt: best_mode.info().pixel_format(),
+            fmt,
+            damage: None,
+        }
+    }
+
+    /// Copies what was drawn since the last present to the framebuffer
+    pub fn present(&mut self) {
+        let (x0, y0, x1, y1) = match self.damage.take() {
+            Some(damage) => damage,
+            None => return,
+        };
+        for y in y0..y1 {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.back.as_ptr().add(y * self.size.width + x0),
+                    self.framebuffer.add(y * self.stride + x0),
+                    x1 - x0,
+                );
+            }
         }
     }
 }
 impl BufferTrait for Screen {
     fn size(&self) -> Size { self.size }
 
-    fn ptr(&mut self) -> *mut Pixel { self.ptr }
+    fn ptr(&mut self) -> *mut Pixel { self.back.as_mut_ptr() }
 
     fn fmt(&self) -> PixelFormat { self.fmt }
+
+    fn damage(&mut self, loc: Location, size: Size) {
+        let clip = |start: isize, len: usize, limit: usize| {
+            let start = start.max(0).min(limit as isize) as usize;
+            (start, (start + len).min(limit))
+        };
+        let (x0, x1) = clip(loc.x, size.width, self.size.width);
+        let (y0, y1) = clip(loc.y, size.height, self.size.height);
+        if x0 == x1 || y0 == y1 {
+            return;
+        }
+        self.damage = Some(match self.damage {
+            Some((a0, b0, a1, b1)) => (a0.min(x0), b0.min(y0), a1.max(x1), b1.max(y1)),
+            None => (x0, y0, x1, y1),
+        });
+    }
 }
@@ -0,0 +1,617 @@
+//! A terminal emulator that draws into a window. It understands the
+//! common ANSI/VT100 escape sequences (cursor movement, SGR colors,
+//! erasing, insertion and scroll regions) and keeps the lines scrolled
+//! off the top so they can be looked back at. A process is connected to
+//! it through its standard input and output, which its children inherit.
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use spin::Mutex;
+
+use super::text_grid::{self, Cell, TextGrid};
+use super::{fonts, with_initialized_display, BufferTrait, Color, Location, MonospaceFont, Size, WindowId};
+use crate::process::Pid;
+
+/// The height of a line of the terminal in pixels
+const FONT_HEIGHT: f32 = 16.0;
+
+/// The most lines kept after they scroll off the top
+const SCROLLBACK_LINES: usize = 1000;
+
+/// The most parameters of a control sequence, the rest are ignored
+const MAX_PARAMS: usize = 16;
+
+/// The colors of SGR 30-37 and their bright versions, as xterm has them
+#[rustfmt::skip]
+const PALETTE: [Color; 16] = [
+    Color { red: 0, green: 0, blue: 0 },
+    Color { red: 205, green: 0, blue: 0 },
+    Color { red: 0, green: 205, blue: 0 },
+    Color { red: 205, green: 205, blue: 0 },
+    Color { red: 0, green: 0, blue: 238 },
+    Color { red: 205, green: 0, blue: 205 },
+    Color { red: 0, green: 205, blue: 205 },
+    Color { red: 229, green: 229, blue: 229 },
+    Color { red: 127, green: 127, blue: 127 },
+    Color { red: 255, green: 0, blue: 0 },
+    Color { red: 0, green: 255, blue: 0 },
+    Color { red: 255, green: 255, blue: 0 },
+    Color { red: 92, green: 92, blue: 255 },
+    Color { red: 255, green: 0, blue: 255 },
+    Color { red: 0, green: 255, blue: 255 },
+    Color { red: 255, green: 255, blue: 255 },
+];
+
+const DEFAULT_FG: Color = PALETTE[7];
+const DEFAULT_BG: Color = PALETTE[0];
+
+/// A color as set by SGR, which is only turned into a `Color` when a
+/// character is written, since bold changes what the first eight mean
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ink {
+    Default,
+    Indexed(u8),
+    Rgb(Color),
+}
+
+/// The rendition set by SGR
+#[derive(Debug, Clone, Copy)]
+struct Attributes {
+    fg: Ink,
+    bg: Ink,
+    bold: bool,
+    inverse: bool,
+}
+impl Attributes {
+    const DEFAULT: Attributes = Attributes {
+        fg: Ink::Default,
+        bg: Ink::Default,
+        bold: false,
+        inverse: false,
+    };
+
+    /// Gets the foreground and background colors of new characters
+    fn colors(&self) -> (Color, Color) {
+        let fg = match self.fg {
+            Ink::Indexed(n) if self.bold && n < 8 => indexed(n + 8),
+            ink => resolve(ink, DEFAULT_FG),
+        };
+        let bg = resolve(self.bg, DEFAULT_BG);
+        if self.inverse { (bg, fg) } else { (fg, bg) }
+    }
+}
+
+/// Where the parser is in an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+
+    /// After ESC
+    Escape,
+
+    /// After ESC [, collecting parameters
+    Csi,
+}
+
+pub struct Terminal {
+    /// The window the terminal draws into
+    window: WindowId,
+
+    /// The text on the screen and in the scrollback
+    grid: TextGrid,
+    columns: usize,
+    rows: usize,
+
+    /// The position of the cursor, where the column can be one past the
+    /// last until the next character wraps the line
+    column: usize,
+    row: usize,
+    cursor_visible: bool,
+    attributes: Attributes,
+
+    /// The cursor and attributes stored by ESC 7 or CSI s
+    saved: (usize, usize, Attributes),
+
+    /// The first and last rows that scroll, inclusive
+    top: usize,
+    bottom: usize,
+
+    state: State,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+
+    /// Whether the control sequence started with `?`
+    private: bool,
+
+    /// The bytes of a character that has not been completely written
+    utf8: [u8; 4],
+    utf8_len: usize,
+
+    /// Bytes typed into the terminal that have not been read
+    input: VecDeque<u8>,
+}
+impl Terminal {
+    /// Creates a terminal that fills a window of `size` pixels
+    pub fn new(window: WindowId, font: MonospaceFont, size: Size) -> Terminal {
+        let blank = Cell { c: ' ', fg: DEFAULT_FG, bg: DEFAULT_BG };
+        let grid = TextGrid::new(font, size, blank, SCROLLBACK_LINES);
+        let (columns, rows) = (grid.columns(), grid.rows());
+        Terminal {
+            window,
+            grid,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            cursor_visible: true,
+            attributes: Attributes::DEFAULT,
+            saved: (0, 0, Attributes::DEFAULT),
+            top: 0,
+            bottom: rows - 1,
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+            utf8: [0; 4],
+            utf8_len: 0,
+            input: VecDeque::new(),
+        }
+    }
+
+    /// Runs output from a program through the terminal and shows the
+    /// result
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.byte(byte);
+        }
+        self.grid.reset_view();
+        self.present();
+    }
+
+    /// Adds a character typed on the keyboard to the input
+    pub fn key(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.input.extend(c.encode_utf8(&mut buf).bytes());
+    }
+
+    /// Takes as much typed input as fits in a buffer
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.input.len());
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+
+    /// Moves the view up into the scrollback by a number of lines, or
+    /// down towards the screen if it is negative
+    pub fn scroll_view(&mut self, lines: isize) {
+        self.grid.scroll_view(lines);
+        self.present();
+    }
+
+    /// Moves the view up into the scrollback by a number of screens, or
+    /// down if it is negative
+    pub fn scroll_page(&mut self, pages: isize) {
+        self.scroll_view(pages * self.rows as isize);
+    }
+
+    /// Draws the terminal into its window and copies the rows that
+    /// changed to the screen
+    pub fn present(&mut self) {
+        with_initialized_display(|screen, wm| {
+            if let Some(window) = wm.window(self.window) {
+                let rows = self.draw(window.buffer());
+                wm.draw_rows(screen, self.window, rows);
+            }
+        });
+    }
+
+    /// Draws the cells that changed since the last draw, and the cursor.
+    /// Returns the rows of pixels that were drawn to.
+    pub fn draw(&mut self, buffer: &mut dyn BufferTrait) -> Range<usize> {
+        let cursor = match self.cursor_visible {
+            true => Some((self.column, self.row)),
+            false => None,
+        };
+        self.grid.draw(buffer, cursor)
+    }
+
+    /// Collects the bytes of a UTF-8 character before passing it on
+    fn byte(&mut self, byte: u8) {
+        if byte < 0x80 {
+            self.utf8_len = 0;
+            self.handle_char(byte as char);
+            return;
+        }
+
+        // A new leading byte drops an unfinished character
+        if byte & 0xC0 != 0x80 {
+            self.utf8_len = 0;
+        }
+        if self.utf8_len == self.utf8.len() {
+            self.utf8_len = 0;
+            return;
+        }
+        self.utf8[self.utf8_len] = byte;
+        self.utf8_len += 1;
+
+        let needed = match self.utf8[0] {
+            b if b & 0xE0 == 0xC0 => 2,
+            b if b & 0xF0 == 0xE0 => 3,
+            b if b & 0xF8 == 0xF0 => 4,
+            _ => 1,
+        };
+        if self.utf8_len == needed {
+            let c = core::str::from_utf8(&self.utf8[..needed])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.utf8_len = 0;
+            self.handle_char(c);
+        }
+    }
+
+    fn handle_char(&mut self, c: char) {
+        match self.state {
+            State::Ground => self.ground(c),
+            State::Escape => self.escape(c),
+            State::Csi => self.csi(c),
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\u{1b}' => self.state = State::Escape,
+
+            // Programs write bare line feeds, so they also return the
+            // carriage like a terminal in newline mode
+            '\n' | '\u{b}' | '\u{c}' => {
+                self.column = 0;
+                self.index();
+            },
+            '\r' => self.column = 0,
+            '\u{8}' => self.column = self.column.min(self.columns - 1).saturating_sub(1),
+            '\t' => self.column = text_grid::next_tab(self.column).min(self.columns - 1),
+            c if c.is_control() => (),
+            c => self.put(c),
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        self.state = State::Ground;
+        match c {
+            '[' => {
+                self.state = State::Csi;
+                self.params = [0; MAX_PARAMS];
+                self.param_count = 0;
+                self.private = false;
+            },
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.index(),
+            'E' => {
+                self.column = 0;
+                self.index();
+            },
+            'M' => self.reverse_index(),
+            'c' => self.reset(),
+            _ => (),
+        }
+    }
+
+    fn csi(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+                if let Some(param) = self.params.get_mut(self.param_count - 1) {
+                    let digit = c as u16 - '0' as u16;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+            },
+            ';' => self.param_count = (self.param_count.max(1) + 1).min(MAX_PARAMS + 1),
+            '?' => self.private = true,
+            '\u{40}'..='\u{7e}' => {
+                self.state = State::Ground;
+                self.execute(c);
+            },
+            // Intermediate bytes are not used by any sequence supported
+            _ => (),
+        }
+    }
+
+    /// Gets a parameter of the control sequence, or a default if it is
+    /// missing or 0
+    fn param(&self, i: usize, default: usize) -> usize {
+        match self.params.get(i) {
+            Some(&param) if i < self.param_count && param != 0 => param as usize,
+            _ => default,
+        }
+    }
+
+    fn execute(&mut self, c: char) {
+        let n = self.param(0, 1);
+        let last_column = self.columns - 1;
+        let last_row = self.rows - 1;
+        match c {
+            'A' => self.row = self.row.saturating_sub(n).max(self.margin_top()),
+            'B' => self.row = (self.row + n).min(self.margin_bottom()),
+            'C' => self.column = (self.column + n).min(last_column),
+            'D' => self.column = self.column.min(last_column).saturating_sub(n),
+            'E' => {
+                self.row = (self.row + n).min(self.margin_bottom());
+                self.column = 0;
+            },
+            'F' => {
+                self.row = self.row.saturating_sub(n).max(self.margin_top());
+                self.column = 0;
+            },
+            'G' => self.column = (n - 1).min(last_column),
+            'd' => self.row = (n - 1).min(last_row),
+            'H' | 'f' => {
+                self.row = (self.param(0, 1) - 1).min(last_row);
+                self.column = (self.param(1, 1) - 1).min(last_column);
+            },
+            'J' => match self.param(0, 0) {
+                0 => {
+                    self.erase_line(self.row, self.column, self.columns);
+                    (self.row + 1..self.rows).for_each(|row| self.erase_line(row, 0, self.columns));
+                },
+                1 => {
+                    (0..self.row).for_each(|row| self.erase_line(row, 0, self.columns));
+                    self.erase_line(self.row, 0, self.column + 1);
+                },
+                2 => (0..self.rows).for_each(|row| self.erase_line(row, 0, self.columns)),
+                3 => self.grid.clear_scrollback(),
+                _ => (),
+            },
+            'K' => match self.param(0, 0) {
+                0 => self.erase_line(self.row, self.column, self.columns),
+                1 => self.erase_line(self.row, 0, self.column + 1),
+                2 => self.erase_line(self.row, 0, self.columns),
+                _ => (),
+            },
+            'X' => self.erase_line(self.row, self.column, self.column + n),
+            '@' => {
+                let (row, column, blank) = (self.row, self.column.min(last_column), self.blank());
+                let n = n.min(self.columns - column);
+                let line = &mut self.grid.line(row)[column..];
+                line.rotate_right(n);
+                line[..n].iter_mut().for_each(|cell| *cell = blank);
+            },
+            'P' => {
+                let (row, column, blank) = (self.row, self.column.min(last_column), self.blank());
+                let n = n.min(self.columns - column);
+                let line = &mut self.grid.line(row)[column..];
+                line.rotate_left(n);
+                let len = line.len();
+                line[len - n..].iter_mut().for_each(|cell| *cell = blank);
+            },
+            'L' if (self.top..=self.bottom).contains(&self.row) => {
+                let top = self.top;
+                self.top = self.row;
+                self.scroll_down(n);
+                self.top = top;
+                self.column = 0;
+            },
+            'M' if (self.top..=self.bottom).contains(&self.row) => {
+                let top = self.top;
+                self.top = self.row;
+                self.scroll_up(n);
+                self.top = top;
+                self.column = 0;
+            },
+            'S' => self.scroll_up(n),
+            'T' => self.scroll_down(n),
+            'm' => self.select_graphic_rendition(),
+            'r' => {
+                let top = self.param(0, 1) - 1;
+                let bottom = self.param(1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.row = 0;
+                    self.column = 0;
+                }
+            },
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            'h' | 'l' if self.private && self.param(0, 0) == 25 => self.cursor_visible = c == 'h',
+            _ => (),
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        if self.param_count == 0 {
+            self.attributes = Attributes::DEFAULT;
+            return;
+        }
+
+        let mut i = 0;
+        while i < self.param_count.min(MAX_PARAMS) {
+            let param = self.params[i];
+            match param {
+                0 => self.attributes = Attributes::DEFAULT,
+                1 => self.attributes.bold = true,
+                22 => self.attributes.bold = false,
+                7 => self.attributes.inverse = true,
+                27 => self.attributes.inverse = false,
+                30..=37 => self.attributes.fg = Ink::Indexed(param as u8 - 30),
+                39 => self.attributes.fg = Ink::Default,
+                40..=47 => self.attributes.bg = Ink::Indexed(param as u8 - 40),
+                49 => self.attributes.bg = Ink::Default,
+                90..=97 => self.attributes.fg = Ink::Indexed(param as u8 - 90 + 8),
+                100..=107 => self.attributes.bg = Ink::Indexed(param as u8 - 100 + 8),
+                38 | 48 => {
+                    // 5;n picks from the 256 color palette and 2;r;g;b
+                    // gives the color directly
+                    let (ink, used) = match self.params.get(i + 1) {
+                        Some(5) => (Ink::Indexed(self.param(i + 2, 0) as u8), 2),
+                        Some(2) => (Ink::Rgb(Color::new(
+                            self.param(i + 2, 0) as u8,
+                            self.param(i + 3, 0) as u8,
+                            self.param(i + 4, 0) as u8,
+                        )), 4),
+                        _ => (Ink::Default, 0),
+                    };
+                    if param == 38 {
+                        self.attributes.fg = ink;
+                    } else {
+                        self.attributes.bg = ink;
+                    }
+                    i += used;
+                },
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+
+    /// Writes a character at the cursor, wrapping first if the cursor is
+    /// past the last column
+    fn put(&mut self, c: char) {
+        if self.column == self.columns {
+            self.column = 0;
+            self.index();
+        }
+        let (fg, bg) = self.attributes.colors();
+        self.grid.line(self.row)[self.column] = Cell { c, fg, bg };
+        self.column += 1;
+    }
+
+    /// Moves the cursor down a row, scrolling if it is on the last row of
+    /// the scroll region
+    fn index(&mut self) {
+        if self.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.row < self.rows - 1 {
+            self.row += 1;
+        }
+    }
+
+    /// Moves the cursor up a row, scrolling if it is on the first row of
+    /// the scroll region
+    fn reverse_index(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    /// Moves the rows of the scroll region up, keeping the ones that leave
+    /// the top of the screen in the scrollback
+    fn scroll_up(&mut self, n: usize) {
+        let blank = self.blank();
+        self.grid.scroll_up(self.top, self.bottom, n, blank);
+    }
+
+    /// Moves the rows of the scroll region down
+    fn scroll_down(&mut self, n: usize) {
+        let blank = self.blank();
+        self.grid.scroll_down(self.top, self.bottom, n, blank);
+    }
+
+    /// Clears the cells of a row from `start` up to `end`
+    fn erase_line(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
+        let end = end.min(self.columns);
+        if start < end {
+            self.grid.line(row)[start..end].iter_mut().for_each(|cell| *cell = blank);
+        }
+    }
+
+    /// Gets an empty cell in the current background color
+    fn blank(&self) -> Cell {
+        let bg = resolve(self.attributes.bg, DEFAULT_BG);
+        Cell { c: ' ', fg: DEFAULT_FG, bg }
+    }
+
+    /// The rows the cursor can move between with the cursor movement
+    /// sequences, which stop at the scroll region if it is inside it
+    fn margin_top(&self) -> usize {
+        if self.row >= self.top { self.top } else { 0 }
+    }
+
+    fn margin_bottom(&self) -> usize {
+        if self.row <= self.bottom { self.bottom } else { self.rows - 1 }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.column, self.row, self.attributes);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (column, row, attributes) = self.saved;
+        self.column = column.min(self.columns - 1);
+        self.row = row.min(self.rows - 1);
+        self.attributes = attributes;
+    }
+
+    /// Clears the screen and scrollback and puts every setting back
+    fn reset(&mut self) {
+        self.attributes = Attributes::DEFAULT;
+        self.saved = (0, 0, Attributes::DEFAULT);
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.cursor_visible = true;
+        self.column = 0;
+        self.row = 0;
+        self.grid.clear_scrollback();
+        (0..self.rows).for_each(|row| self.erase_line(row, 0, self.columns));
+    }
+}
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        with_initialized_display(|screen, wm| {
+            wm.close_window(self.window);
+            wm.draw(screen);
+        });
+    }
+}
+
+/// Opens a terminal in a window that covers the screen, owned by a
+/// process. Returns None if the graphics mode has not been set.
+pub fn open(pid: Pid) -> Option<Arc<Mutex<Terminal>>> {
+    let font = MonospaceFont::new(fonts::init()?, FONT_HEIGHT);
+    let (id, size) = with_initialized_display(|screen, wm| {
+        let size = screen.size();
+        let id = wm.create_window(pid, size, Location { x: 0, y: 0 }, screen.fmt());
+        wm.window(id)?.buffer().fill(DEFAULT_BG);
+        Some((id, size))
+    })??;
+
+    let mut terminal = Terminal::new(id, font, size);
+    terminal.present();
+    Some(Arc::new(Mutex::new(terminal)))
+}
+
+fn resolve(ink: Ink, default: Color) -> Color {
+    match ink {
+        Ink::Default => default,
+        Ink::Indexed(n) => indexed(n),
+        Ink::Rgb(color) => color,
+    }
+}
+
+/// Gets a color of the 256 color palette: the 16 basic colors, then a
+/// 6x6x6 cube, then 24 shades of grey
+fn indexed(n: u8) -> Color {
+    match n {
+        0..=15 => PALETTE[n as usize],
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            Color::new(level(n / 36), level(n / 6 % 6), level(n % 6))
+        },
+        _ => {
+            let grey = 8 + (n - 232) * 10;
+            Color::new(grey, grey, grey)
+        },
+    }
+}
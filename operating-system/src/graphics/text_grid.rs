@@ -0,0 +1,184 @@
+//! The grid of character cells that the console and the terminal keep
+//! their text in. It remembers what each cell on the screen shows, and
+//! only the cells that changed are drawn again, because writing to the
+//! framebuffer is slow.
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::{BufferTrait, Color, Location, MonospaceFont, Size};
+
+/// Tabs move the cursor to the next multiple of this many columns
+const TAB_WIDTH: usize = 8;
+
+/// A character and the colors it is drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+pub struct TextGrid {
+    font: MonospaceFont,
+    columns: usize,
+    rows: usize,
+
+    /// The rows on the screen
+    lines: Vec<Vec<Cell>>,
+
+    /// The lines that scrolled off the top, oldest first, and the most
+    /// that are kept
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_lines: usize,
+
+    /// How many lines of the scrollback are shown above the screen
+    view: usize,
+
+    /// What each cell of the buffer shows, or None if it has to be drawn
+    drawn: Vec<Option<Cell>>,
+
+    /// How many rows the whole screen scrolled up since the last draw.
+    /// The next draw moves the pixels of the rows that are still on the
+    /// screen, and only draws the new ones.
+    scrolled: usize,
+}
+impl TextGrid {
+    /// Creates a grid of `blank` cells that fills an area of `size`
+    /// pixels, keeping up to `scrollback_lines` lines that scroll off
+    pub fn new(font: MonospaceFont, size: Size, blank: Cell, scrollback_lines: usize) -> TextGrid {
+        let cell = font.cell();
+        let columns = (size.width / cell.width).max(1);
+        let rows = (size.height / cell.height).max(1);
+        TextGrid {
+            font,
+            columns,
+            rows,
+            lines: vec![vec![blank; columns]; rows],
+            scrollback: VecDeque::new(),
+            scrollback_lines,
+            view: 0,
+            drawn: vec![None; columns * rows],
+            scrolled: 0,
+        }
+    }
+
+    pub fn columns(&self) -> usize { self.columns }
+
+    pub fn rows(&self) -> usize { self.rows }
+
+    /// Gets the cells of a row on the screen
+    pub fn line(&mut self, row: usize) -> &mut [Cell] { &mut self.lines[row] }
+
+    /// Moves rows `top..=bottom` up, filling the bottom with `blank`.
+    /// Rows that leave the top of the screen go to the scrollback. Lines
+    /// are reused rather than allocated where they can be, since the
+    /// console scrolls while logging.
+    pub fn scroll_up(&mut self, top: usize, bottom: usize, n: usize, blank: Cell) {
+        let n = n.min(bottom - top + 1);
+        if top == 0 && bottom == self.rows - 1 {
+            self.scrolled = (self.scrolled + n).min(self.rows);
+        }
+        for _ in 0..n {
+            self.lines[top..=bottom].rotate_left(1);
+            let line = &mut self.lines[bottom];
+            if top == 0 && self.scrollback_lines > 0 {
+                let new = match self.scrollback.len() == self.scrollback_lines {
+                    true => self.scrollback.pop_front().unwrap(),
+                    false => vec![blank; self.columns],
+                };
+                self.scrollback.push_back(core::mem::replace(line, new));
+            }
+            self.lines[bottom].iter_mut().for_each(|cell| *cell = blank);
+        }
+    }
+
+    /// Moves rows `top..=bottom` down, filling the top with `blank`
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, n: usize, blank: Cell) {
+        for _ in 0..n.min(bottom - top + 1) {
+            self.lines[top..=bottom].rotate_right(1);
+            self.lines[top].iter_mut().for_each(|cell| *cell = blank);
+        }
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+        self.view = 0;
+    }
+
+    /// Moves the view up into the scrollback by a number of lines, or
+    /// down towards the screen if it is negative
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self.view as isize + lines;
+        self.view = view.max(0).min(self.scrollback.len() as isize) as usize;
+    }
+
+    /// Shows the screen again rather than the scrollback
+    pub fn reset_view(&mut self) { self.view = 0; }
+
+    /// Forgets what is in the buffer, so that the next draw draws every
+    /// cell
+    pub fn invalidate(&mut self) {
+        self.drawn.iter_mut().for_each(|cell| *cell = None);
+        self.scrolled = 0;
+    }
+
+    /// Draws the cells that changed since the last draw, and the cursor
+    /// if it is given as a column and row. The column can be one past
+    /// the last. Returns the rows of pixels that were drawn to.
+    pub fn draw(&mut self, buffer: &mut dyn BufferTrait, cursor: Option<(usize, usize)>) -> Range<usize> {
+        let size = self.font.cell();
+        let mut changed: Option<Range<usize>> = None;
+        if self.scrolled > 0 {
+            let (scrolled, cells) = (self.scrolled, self.scrolled * self.columns);
+            buffer.scroll_up(self.rows * size.height, scrolled * size.height);
+            self.drawn.copy_within(cells.., 0);
+            let len = self.drawn.len();
+            self.drawn[len - cells..].iter_mut().for_each(|cell| *cell = None);
+            changed = Some(0..self.rows - scrolled);
+            self.scrolled = 0;
+        }
+        let cursor = match cursor {
+            Some((column, row)) if self.view == 0 => Some(row * self.columns + column.min(self.columns - 1)),
+            _ => None,
+        };
+        for row in 0..self.rows {
+            let line = match row.checked_sub(self.view) {
+                Some(row) => &self.lines[row],
+                None => &self.scrollback[self.scrollback.len() - self.view + row],
+            };
+            for (column, &cell) in line.iter().enumerate() {
+                let i = row * self.columns + column;
+                if Some(i) != cursor && self.drawn[i] == Some(cell) {
+                    continue;
+                }
+
+                let loc = Location {
+                    x: (column * size.width) as isize,
+                    y: (row * size.height) as isize,
+                };
+                if Some(i) == cursor {
+                    // The cursor swaps the colors of its cell, which has to
+                    // be drawn again once it moves on
+                    self.font.draw(buffer, cell.c, loc, cell.bg, cell.fg);
+                    self.drawn[i] = None;
+                } else {
+                    self.font.draw(buffer, cell.c, loc, cell.fg, cell.bg);
+                    self.drawn[i] = Some(cell);
+                }
+                let rows = changed.unwrap_or(row..row + 1);
+                changed = Some(rows.start.min(row)..rows.end.max(row + 1));
+            }
+        }
+        match changed {
+            Some(rows) => rows.start * size.height..rows.end * size.height,
+            None => 0..0,
+        }
+    }
+}
+
+/// Gets the column of the next tab stop after `column`
+pub fn next_tab(column: usize) -> usize { (column / TAB_WIDTH + 1) * TAB_WIDTH }
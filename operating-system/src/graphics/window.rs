@@ -1,21 +1,29 @@
 extern crate alloc;
 use alloc::vec::Vec;
+use core::ops::Range;
 
-use super::{Buffer, BufferTrait, Color, Location, PixelFormat, Screen, Size};
+use super::{console, Buffer, BufferTrait, Color, Location, PixelFormat, Screen, Size};
+use crate::process::Pid;
 
 #[derive(Debug)]
 pub struct ProcessInstance {
     /// The Process ID associated with the instance
-    pid: u8,
+    pid: Pid,
 
     /// A vector of the windows owned by the application
     windows: Vec<Window>,
 }
 
+/// A unique identifier given to every window
+pub type WindowId = usize;
+
 #[derive(Debug)]
 pub struct Window {
+    /// The ID of the window
+    id: WindowId,
+
     /// The Process ID of the program that owns the window
-    pid: u8,
+    pid: Pid,
 
     /// The location of the top-left corner of the window
     location: Location,
@@ -34,52 +42,111 @@ pub enum WindowStatus {
     Fullscreen,
 }
 
+impl Window {
+    /// Gets the process ID of the program that owns the window
+    pub fn pid(&self) -> Pid { self.pid }
+
+    /// Gets the buffer that the window draws to
+    pub fn buffer(&mut self) -> &mut Buffer { &mut self.buffer }
+
+    /// Moves the top-left corner of the window
+    pub fn set_location(&mut self, location: Location) { self.location = location; }
+}
+
 #[derive(Debug)]
 pub struct WindowManager {
     windows: Vec<Window>,
+
+    /// The ID that will be given to the next window
+    next_id: WindowId,
 }
 impl WindowManager {
     /// Creates a new WindowManager object
     pub fn new() -> WindowManager {
         WindowManager {
             windows: Vec::<Window>::new(),
+            next_id: 0,
         }
     }
 
     pub fn create_window(
         &mut self,
-        pid: u8,
+        pid: Pid,
         size: Size,
         location: Location,
         fmt: PixelFormat,
-    ) {
-        let mut buffer = Buffer::new(size, fmt);
+    ) -> WindowId {
+        self.add_window(pid, Buffer::new(size, fmt), location)
+    }
+
+    /// Adds a window that draws from an existing buffer, which is
+    /// cleared to white
+    pub fn add_window(
+        &mut self,
+        pid: Pid,
+        mut buffer: Buffer,
+        location: Location,
+    ) -> WindowId {
         buffer.fill(Color::new(255, 255, 255));
+        let id = self.next_id;
+        self.next_id += 1;
         let window = Window {
+            id,
             pid,
             location,
             buffer,
             status: WindowStatus::Open,
         };
         self.windows.push(window);
+        id
     }
 
-    pub fn destroy_window(&mut self, pid: u8) {
-        // Get the index of the specified window
-        let index = self.windows.iter().position(|w| w.pid == pid);
+    /// Gets a window by its ID
+    pub fn window(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.iter_mut().find(|w| w.id == id)
+    }
 
-        // Delete the window from the vector
-        match index {
-            Some(index) => {
-                self.windows.remove(index);
+    /// Removes a window by its ID
+    pub fn close_window(&mut self, id: WindowId) -> Option<Window> {
+        let index = self.windows.iter().position(|w| w.id == id)?;
+        Some(self.windows.remove(index))
+    }
+
+    /// Removes every window owned by a process. Returns whether there
+    /// were any.
+    pub fn destroy_window(&mut self, pid: Pid) -> bool {
+        let count = self.windows.len();
+        self.windows.retain(|w| w.pid != pid);
+        self.windows.len() != count
+    }
+
+    /// Checks whether any window is drawn on the screen
+    pub fn has_visible_windows(&self) -> bool {
+        self.windows.iter().any(|w| w.status != WindowStatus::Minimized)
+    }
+
+    /// Copies some rows of a window to the screen, for when only they
+    /// changed. Everything is drawn again if the window is not on top.
+    pub fn draw_rows(&mut self, screen: &mut Screen, id: WindowId, rows: Range<usize>) {
+        let top = self.windows.iter().rposition(|w| w.status != WindowStatus::Minimized);
+        match top {
+            Some(i) if self.windows[i].id == id => {
+                let window = &mut self.windows[i];
+                screen.transfer_rows(&mut window.buffer, rows, window.location);
+                screen.present();
             },
-            None => (),
+            _ => self.draw(screen),
         }
     }
 
     pub fn draw(&mut self, screen: &mut Screen) {
-        // Draw the gui
+        // Draw the gui, or the console when there are no windows on it
         screen.fill(Color::new(0, 0, 0));
+        if !self.has_visible_windows() {
+            console::redraw(screen);
+            screen.present();
+            return;
+        }
 
         // Draw the windows
         for i in 0..self.windows.len() {
@@ -89,5 +156,6 @@ impl WindowManager {
             let loc = self.windows[i].location;
             screen.block_transfer(&mut self.windows[i].buffer, loc);
         }
+        screen.present();
     }
 }
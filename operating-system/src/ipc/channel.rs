@@ -0,0 +1,116 @@
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use super::{Error, Object};
+
+/// The largest message that can be sent over a channel
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// The most handles that can be sent with one message
+pub const MAX_MESSAGE_HANDLES: usize = 8;
+
+/// The most messages that can be waiting in each direction
+const MAX_QUEUED_MESSAGES: usize = 64;
+
+/// Bytes and objects sent over a channel
+#[derive(Debug)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub objects: Vec<Object>,
+}
+
+/// The messages travelling in one direction of a channel
+#[derive(Debug, Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+
+    /// Set when either end of the channel is closed
+    closed: bool,
+}
+
+/// One end of a bidirectional channel. Closing it (by dropping it)
+/// closes the channel for the other end too, although messages that
+/// were already sent can still be received.
+#[derive(Debug)]
+pub struct Endpoint {
+    inbox: Arc<Mutex<Queue>>,
+    outbox: Arc<Mutex<Queue>>,
+}
+impl Endpoint {
+    /// Checks whether a message of a size, carrying a number of
+    /// objects, could be sent right now
+    pub fn can_send(&self, len: usize, objects: usize) -> Result<(), Error> {
+        if len > MAX_MESSAGE_SIZE || objects > MAX_MESSAGE_HANDLES {
+            return Err(Error::TooLarge);
+        }
+
+        let outbox = self.outbox.lock();
+        if outbox.closed {
+            return Err(Error::PeerClosed);
+        }
+        if outbox.messages.len() == MAX_QUEUED_MESSAGES {
+            return Err(Error::WouldBlock);
+        }
+        Ok(())
+    }
+
+    /// Sends a message to the other end of the channel
+    pub fn send(&mut self, message: Message) -> Result<(), Error> {
+        self.can_send(message.data.len(), message.objects.len())?;
+        self.outbox.lock().messages.push_back(message);
+        Ok(())
+    }
+
+    /// Gets the size and number of objects of the next message without
+    /// receiving it
+    pub fn peek(&self) -> Result<(usize, usize), Error> {
+        let inbox = self.inbox.lock();
+        match inbox.messages.front() {
+            Some(message) => Ok((message.data.len(), message.objects.len())),
+            None if inbox.closed => Err(Error::PeerClosed),
+            None => Err(Error::WouldBlock),
+        }
+    }
+
+    /// Checks whether `other` is the other end of this channel
+    pub fn is_peer(&self, other: &Endpoint) -> bool {
+        Arc::ptr_eq(&self.outbox, &other.inbox)
+    }
+
+    /// Receives the next message sent from the other end of the channel
+    pub fn recv(&mut self) -> Result<Message, Error> {
+        let mut inbox = self.inbox.lock();
+        match inbox.messages.pop_front() {
+            Some(message) => Ok(message),
+            None if inbox.closed => Err(Error::PeerClosed),
+            None => Err(Error::WouldBlock),
+        }
+    }
+}
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.outbox.lock().closed = true;
+
+        // Nobody can receive the messages sent to this end any more,
+        // which also closes any objects sent with them
+        let mut inbox = self.inbox.lock();
+        inbox.closed = true;
+        let messages = core::mem::take(&mut inbox.messages);
+        drop(inbox);
+        drop(messages);
+    }
+}
+
+/// Creates a channel and returns both of its ends
+pub fn channel() -> (Endpoint, Endpoint) {
+    let a = Arc::new(Mutex::new(Queue::default()));
+    let b = Arc::new(Mutex::new(Queue::default()));
+    (
+        Endpoint { inbox: a.clone(), outbox: b.clone() },
+        Endpoint { inbox: b, outbox: a },
+    )
+}
@@ -0,0 +1,148 @@
+//! Kernel objects that let processes talk to each other: message
+//! channels and shared memory regions. Processes refer to them through
+//! handles, which can be sent over channels to other processes.
+
+mod channel;
+mod shared_memory;
+
+pub use channel::{channel, Endpoint, Message, MAX_MESSAGE_HANDLES};
+pub use shared_memory::SharedMemory;
+
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A reference to a kernel object from a process. Handles are numbered
+/// from `HandleTable::FIRST_HANDLE`, so 0 is never a valid handle.
+pub type Handle = u64;
+
+/// The maximum number of objects that a process can hold
+const MAX_HANDLES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The handle is not open, or refers to the wrong type of object
+    BadHandle,
+
+    /// The channel is empty, or full when sending
+    WouldBlock,
+
+    /// The other end of the channel has been closed
+    PeerClosed,
+
+    /// The message, or the number of handles sent with it, is too large
+    TooLarge,
+
+    /// The process has too many handles open
+    TooManyHandles,
+
+    /// There are not enough free frames to create a shared memory region
+    NoMemory,
+}
+
+/// An object that a handle refers to
+#[derive(Debug)]
+pub enum Object {
+    Channel(Endpoint),
+    SharedMemory(Arc<SharedMemory>),
+}
+
+/// The objects held by a process
+#[derive(Debug)]
+pub struct HandleTable {
+    objects: Vec<Option<Object>>,
+}
+impl HandleTable {
+    /// The first handle handed out. Handles passed to a new process
+    /// start from here.
+    pub const FIRST_HANDLE: Handle = 1;
+
+    /// Creates an empty HandleTable object
+    pub const fn new() -> HandleTable { HandleTable { objects: Vec::new() } }
+
+    /// Adds an object to the table and returns its handle
+    pub fn insert(&mut self, object: Object) -> Result<Handle, Error> {
+        // Reuse a closed slot if there is one
+        if let Some(index) = self.objects.iter().position(Option::is_none) {
+            self.objects[index] = Some(object);
+            return Ok(index as Handle + Self::FIRST_HANDLE);
+        }
+
+        if self.objects.len() == MAX_HANDLES {
+            return Err(Error::TooManyHandles);
+        }
+        self.objects.push(Some(object));
+        Ok((self.objects.len() - 1) as Handle + Self::FIRST_HANDLE)
+    }
+
+    /// Checks whether `count` more objects can be added to the table
+    pub fn has_room(&self, count: usize) -> bool {
+        let closed = self.objects.iter().filter(|object| object.is_none()).count();
+        closed + MAX_HANDLES - self.objects.len() >= count
+    }
+
+    /// Gets the object a handle refers to
+    pub fn get(&mut self, handle: Handle) -> Result<&mut Object, Error> {
+        self.slot(handle)
+            .and_then(Option::as_mut)
+            .ok_or(Error::BadHandle)
+    }
+
+    /// Gets the channel endpoint a handle refers to
+    pub fn channel(&mut self, handle: Handle) -> Result<&mut Endpoint, Error> {
+        match self.get(handle)? {
+            Object::Channel(endpoint) => Ok(endpoint),
+            _ => Err(Error::BadHandle),
+        }
+    }
+
+    /// Gets the shared memory region a handle refers to
+    pub fn shared_memory(&mut self, handle: Handle) -> Result<Arc<SharedMemory>, Error> {
+        match self.get(handle)? {
+            Object::SharedMemory(region) => Ok(region.clone()),
+            _ => Err(Error::BadHandle),
+        }
+    }
+
+    /// Checks whether two handles refer to the two ends of one channel
+    pub fn are_peers(&self, a: Handle, b: Handle) -> bool {
+        let channel = |handle: Handle| {
+            let index = handle.checked_sub(Self::FIRST_HANDLE)?;
+            match self.objects.get(index as usize) {
+                Some(Some(Object::Channel(endpoint))) => Some(endpoint),
+                _ => None,
+            }
+        };
+        match (channel(a), channel(b)) {
+            (Some(a), Some(b)) => a.is_peer(b),
+            _ => false,
+        }
+    }
+
+    /// Removes a handle from the table, returning the object it referred
+    /// to. Dropping the object closes it.
+    pub fn remove(&mut self, handle: Handle) -> Result<Object, Error> {
+        self.slot(handle)
+            .and_then(Option::take)
+            .ok_or(Error::BadHandle)
+    }
+
+    /// Removes several handles from the table at once. Nothing is
+    /// removed if any of the handles is not open.
+    pub fn remove_all(&mut self, handles: &[Handle]) -> Result<Vec<Object>, Error> {
+        for (i, &handle) in handles.iter().enumerate() {
+            if self.get(handle).is_err() || handles[..i].contains(&handle) {
+                return Err(Error::BadHandle);
+            }
+        }
+        Ok(handles.iter().map(|&h| self.remove(h).unwrap()).collect())
+    }
+
+    /// Closes every handle in the table
+    pub fn clear(&mut self) { self.objects.clear(); }
+
+    fn slot(&mut self, handle: Handle) -> Option<&mut Option<Object>> {
+        let index = handle.checked_sub(Self::FIRST_HANDLE)?;
+        self.objects.get_mut(index as usize)
+    }
+}
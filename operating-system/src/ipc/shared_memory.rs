@@ -0,0 +1,52 @@
+use x86_64::structures::paging::{PhysFrame, PhysFrameRange, Size4KiB};
+
+use super::Error;
+use crate::memory::paging;
+use crate::memory::uefi_allocator::UefiFrameAllocator;
+
+/// The largest shared memory region that can be created
+const MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Physically contiguous memory that can be mapped into several address
+/// spaces at once. The kernel reaches it through the identity map.
+/// The frames are freed when the last reference to the region is
+/// dropped, so every address space that maps it must hold one.
+#[derive(Debug)]
+pub struct SharedMemory {
+    first: PhysFrame<Size4KiB>,
+    pages: usize,
+}
+impl SharedMemory {
+    /// Allocates a zeroed region of at least `len` bytes
+    pub fn new(len: usize) -> Result<SharedMemory, Error> {
+        if len == 0 || len > MAX_SIZE {
+            return Err(Error::TooLarge);
+        }
+        let pages = (len + 4095) / 4096;
+        let first = UefiFrameAllocator
+            .allocate_contiguous(pages)
+            .ok_or(Error::NoMemory)?;
+
+        let region = SharedMemory { first, pages };
+        unsafe { core::ptr::write_bytes(region.as_mut_ptr(), 0, region.len()) };
+        Ok(region)
+    }
+
+    /// Gets the size of the region in bytes
+    pub fn len(&self) -> usize { self.pages * 4096 }
+
+    /// Gets the frames that make up the region
+    pub fn frames(&self) -> PhysFrameRange<Size4KiB> {
+        PhysFrame::range(self.first, self.first + self.pages as u64)
+    }
+
+    /// Gets a pointer to the region in the kernel's address space
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        paging::phys_to_virt(self.first.start_address()).as_mut_ptr()
+    }
+}
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe { UefiFrameAllocator.deallocate_contiguous(self.first, self.pages) };
+    }
+}
@@ -1,10 +1,25 @@
-use crate::system::SystemHandles;
+use crate::system::{Event, SystemHandles};
+use crate::system::{apic, gdt, interrupts, syscall, usermode};
 use crate::system;
-use log::{debug, trace};
+use crate::drivers;
+use crate::graphics;
+use crate::loader;
+use crate::logging;
+use crate::process;
+use crate::shell;
+use crate::task;
+use acpi::InterruptModel;
+use log::{debug, info};
 
 // use graphics::{fonts, Color, BufferTrait, Size,
 //    Location, WindowManager, PixelFormat};
 
+/// The first user program run by the kernel
+const INIT_PATH: &str = "/apps/init";
+
+/// How often the thermal zones are checked for critical temperatures
+const THERMAL_POLL_MS: u64 = 10_000;
+
 pub fn start(h: SystemHandles) -> ! {
     
     // fa.reclaim(map, MemoryType::BOOT_SERVICES_CODE)
@@ -27,26 +42,117 @@ pub fn start(h: SystemHandles) -> ! {
     // Location {x:100, y:100}, gb.fmt());
     // wm.draw(&mut gb);
     
+    debug!("Starting the framebuffer console");
+    logging::start_console();
+
     debug!("Initializing ACPI methods");
     system::init_acpi(&h).expect("Could not initialize ACPI methods");
 
-    debug!("Shutting down the system");
-    match system::shutdown(3) {
-        Ok(_) => debug!("Successfully shut down system"),
-        Err(err) => {
-            trace!("{:?}", err);
-            panic!("Could not shutdown system");
-        },
+    debug!("Loading the global descriptor table");
+    gdt::init();
+
+    debug!("Loading the interrupt descriptor table");
+    interrupts::enable();
+
+    debug!("Enabling system calls");
+    syscall::init();
+
+    debug!("Initializing the APIC");
+    match system::interrupt_model() {
+        Some(InterruptModel::Apic(apic)) => apic::init(apic),
+        _ => panic!("Could not find an APIC"),
     }
+    x86_64::instructions::interrupts::enable();
 
-    match system::wakeup(3) {
-        Ok(_) => debug!("Successfully shut down system"),
-        Err(err) => {
-            trace!("{:?}", err);
-            panic!("Could not wakeup system");
-        },
+    debug!("Enumerating ACPI devices");
+    if let Err(err) = system::acpi_devices::enumerate_devices() {
+        debug!("Could not enumerate ACPI devices: {:?}", err);
     }
 
-    loop {}
+    debug!("Enumerating PCI devices");
+    if let Err(err) = system::pci_express::enumerate() {
+        debug!("Could not enumerate PCI devices: {:?}", err);
+    }
+
+    debug!("Binding drivers");
+    drivers::init();
+
+    debug!("Enabling ACPI events");
+    if let Err(err) = system::init_events() {
+        debug!("Could not enable ACPI events: {:?}", err);
+    }
+
+    debug!("Testing user mode");
+    match usermode::test() {
+        Ok(code) => debug!("User mode test exited with {}", code),
+        Err(err) => debug!("Could not run the user mode test: {:?}", err),
+    }
+
+    debug!("Loading the initial ramdisk");
+    loader::initrd::init();
+
+    // These run while init waits for input or sleeps
+    task::spawn(acpi_events());
+    task::spawn(thermal_monitor());
+
+    debug!("Running the init program");
+    let terminal = graphics::terminal::open(process::KERNEL_PID);
+    process::with_current(|p| p.terminal = terminal);
+    match process::exec(INIT_PATH, &[INIT_PATH], &[]) {
+        Ok(code) => debug!("{} exited with {}", INIT_PATH, code),
+        Err(err) => debug!("Could not run {}: {:?}", INIT_PATH, err),
+    }
+
+    // Closing the terminal shows the console again
+    process::with_current(|p| p.terminal = None);
+
+    // The keyboard belongs to init until it exits
+    debug!("Starting the executor");
+    task::spawn(shell::run());
+    task::executor::run();
 }
 
+/// Handles the events signalled by the firmware through the SCI. The
+/// power button shuts the system down and the sleep button suspends it.
+async fn acpi_events() {
+    loop {
+        match system::next_event().await {
+            Event::PowerButton => {
+                info!("Power button pressed, shutting down");
+                if let Err(err) = system::shutdown(5) {
+                    info!("Could not shut down: {:?}", err);
+                }
+            },
+            Event::SleepButton => {
+                info!("Sleep button pressed, suspending");
+                if let Err(err) = system::shutdown(3) {
+                    info!("Could not suspend: {:?}", err);
+                }
+            },
+            Event::RtcAlarm => info!("RTC alarm"),
+            Event::Gpe(gpe) => {
+                if let Err(err) = system::handle_gpe(gpe) {
+                    debug!("Could not handle GPE {:#x}: {:?}", gpe, err);
+                }
+            },
+        }
+    }
+}
+
+/// Checks the thermal zones every few seconds and shuts the system down
+/// when one of them reaches its critical temperature
+async fn thermal_monitor() {
+    loop {
+        match system::acpi_power::thermal_zones() {
+            Ok(zones) => for zone in zones.iter().filter(|zone| zone.is_critical()) {
+                info!("{} reached its critical temperature ({}), shutting down",
+                    zone.path, zone.temperature);
+                if let Err(err) = system::shutdown(5) {
+                    info!("Could not shut down: {:?}", err);
+                }
+            },
+            Err(err) => debug!("Could not read the thermal zones: {:?}", err),
+        }
+        task::timer::sleep(THERMAL_POLL_MS).await;
+    }
+}
@@ -0,0 +1,209 @@
+use core::convert::TryInto;
+
+use super::Error;
+
+/// The type of an executable that must be loaded at its linked address
+pub const ET_EXEC: u16 = 2;
+
+/// The type of a position independent executable
+pub const ET_DYN: u16 = 3;
+
+const EM_X86_64: u16 = 62;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+pub const PT_PHDR: u32 = 6;
+
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+
+pub const DT_NULL: u64 = 0;
+pub const DT_RELA: u64 = 7;
+pub const DT_RELASZ: u64 = 8;
+pub const DT_RELAENT: u64 = 9;
+
+pub const R_X86_64_NONE: u32 = 0;
+pub const R_X86_64_RELATIVE: u32 = 8;
+
+/// The size of a program header entry
+pub const PHDR_SIZE: usize = 56;
+
+/// The size of a relocation entry with an addend
+pub const RELA_SIZE: usize = 24;
+
+/// The fields of the ELF64 file header used by the loader
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub ty: u16,
+    pub entry: u64,
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+}
+
+/// An ELF64 program header
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub ty: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+/// An ELF64 relocation with an addend
+#[derive(Debug, Clone, Copy)]
+pub struct Rela {
+    pub offset: u64,
+    pub ty: u32,
+    pub addend: i64,
+}
+
+/// A parsed ELF64 file
+pub struct Elf<'a> {
+    pub data: &'a [u8],
+    pub header: Header,
+}
+impl<'a> Elf<'a> {
+    /// Validates the header of an x86_64 executable
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, Error> {
+        if data.len() < 64 || &data[0..4] != b"\x7fELF" {
+            return Err(Error::NotElf);
+        }
+
+        // 64-bit, little endian, version 1
+        if data[4] != 2 || data[5] != 1 || data[6] != 1 {
+            return Err(Error::Unsupported);
+        }
+
+        let header = Header {
+            ty: read_u16(data, 16)?,
+            entry: read_u64(data, 24)?,
+            phoff: read_u64(data, 32)?,
+            phentsize: read_u16(data, 54)?,
+            phnum: read_u16(data, 56)?,
+        };
+
+        if read_u16(data, 18)? != EM_X86_64 {
+            return Err(Error::Unsupported);
+        }
+        if header.ty != ET_EXEC && header.ty != ET_DYN {
+            return Err(Error::Unsupported);
+        }
+        if header.phentsize as usize != PHDR_SIZE {
+            return Err(Error::InvalidElf);
+        }
+
+        Ok(Elf { data, header })
+    }
+
+    /// Iterates over the program headers
+    pub fn program_headers(&self) -> impl Iterator<Item = Result<ProgramHeader, Error>> + '_ {
+        (0..self.header.phnum as usize).map(move |i| {
+            let base = (self.header.phoff as usize)
+                .checked_add(i * PHDR_SIZE)
+                .ok_or(Error::InvalidElf)?;
+            let end = base.checked_add(PHDR_SIZE).ok_or(Error::InvalidElf)?;
+            let ph = self.data.get(base..end).ok_or(Error::InvalidElf)?;
+            Ok(ProgramHeader {
+                ty: read_u32(ph, 0)?,
+                flags: read_u32(ph, 4)?,
+                offset: read_u64(ph, 8)?,
+                vaddr: read_u64(ph, 16)?,
+                filesz: read_u64(ph, 32)?,
+                memsz: read_u64(ph, 40)?,
+            })
+        })
+    }
+
+    /// Gets the bytes of a segment that are stored in the file
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Result<&'a [u8], Error> {
+        let start = ph.offset as usize;
+        let end = start.checked_add(ph.filesz as usize).ok_or(Error::InvalidElf)?;
+        self.data.get(start..end).ok_or(Error::InvalidElf)
+    }
+
+    /// Gets the relocations listed in the dynamic segment
+    pub fn relocations(&self, dynamic: &ProgramHeader) -> Result<RelaIter<'a>, Error> {
+        let data = self.segment_data(dynamic)?;
+        let (mut rela, mut relasz) = (None, 0);
+
+        // Walk the dynamic table until DT_NULL
+        for entry in data.chunks_exact(16) {
+            let tag = read_u64(entry, 0)?;
+            let value = read_u64(entry, 8)?;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(value),
+                DT_RELASZ => relasz = value,
+                DT_RELAENT if value as usize != RELA_SIZE => return Err(Error::InvalidElf),
+                _ => (),
+            }
+        }
+
+        let table = match rela {
+            Some(addr) => {
+                let offset = self.vaddr_to_offset(addr)?;
+                let end = offset.checked_add(relasz as usize).ok_or(Error::InvalidElf)?;
+                self.data.get(offset..end).ok_or(Error::InvalidElf)?
+            },
+            None => &[],
+        };
+        Ok(RelaIter { table })
+    }
+
+    /// Converts a linked address into an offset in the file
+    fn vaddr_to_offset(&self, vaddr: u64) -> Result<usize, Error> {
+        for ph in self.program_headers() {
+            let ph = ph?;
+            let end = ph.vaddr.checked_add(ph.filesz).ok_or(Error::InvalidElf)?;
+            if ph.ty == PT_LOAD && vaddr >= ph.vaddr && vaddr < end {
+                let offset = (vaddr - ph.vaddr).checked_add(ph.offset).ok_or(Error::InvalidElf)?;
+                return Ok(offset as usize);
+            }
+        }
+        Err(Error::InvalidElf)
+    }
+}
+
+pub struct RelaIter<'a> {
+    table: &'a [u8],
+}
+impl<'a> Iterator for RelaIter<'a> {
+    type Item = Rela;
+
+    fn next(&mut self) -> Option<Rela> {
+        if self.table.len() < RELA_SIZE {
+            return None;
+        }
+        let (entry, rest) = self.table.split_at(RELA_SIZE);
+        self.table = rest;
+
+        let info = read_u64(entry, 8).ok()?;
+        Some(Rela {
+            offset: read_u64(entry, 0).ok()?,
+            ty: info as u32,
+            addend: read_u64(entry, 16).ok()? as i64,
+        })
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    offset.checked_add(2).and_then(|end| data.get(offset..end))
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    offset.checked_add(4).and_then(|end| data.get(offset..end))
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, Error> {
+    offset.checked_add(8).and_then(|end| data.get(offset..end))
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf)
+}
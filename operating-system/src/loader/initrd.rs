@@ -0,0 +1,74 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use log::debug;
+
+use crate::filesystem;
+
+/// Where the initial ramdisk is stored on the boot volume
+const INITRD_PATH: &str = "\\initrd.tar";
+
+/// The initial ramdisk: a ustar archive loaded from the boot volume
+static mut INITRD: Option<Vec<u8>> = None;
+
+/// Loads the initial ramdisk into memory if the boot volume has one
+pub fn init() {
+    match filesystem::read_file(INITRD_PATH) {
+        Ok(archive) => {
+            debug!("Loaded initial ramdisk ({} bytes)", archive.len());
+            unsafe { INITRD = Some(archive) };
+        },
+        Err(err) => debug!("No initial ramdisk: {:?}", err),
+    }
+}
+
+/// Gets the contents of a file in the initial ramdisk
+pub fn get(path: &str) -> Option<&'static [u8]> {
+    let archive = unsafe { INITRD.as_ref() }?;
+    let path = path.trim_start_matches('/');
+
+    // Each file is a 512 byte header followed by its data, padded to
+    // a multiple of 512 bytes
+    let mut offset = 0;
+    while offset + 512 <= archive.len() {
+        let header = &archive[offset..offset + 512];
+
+        // The archive ends with an empty header
+        if header[0] == 0 {
+            break;
+        }
+
+        let name = field(&header[0..100]);
+        let size = parse_octal(&header[124..136])?;
+        let data_start = offset + 512;
+        let data_end = data_start.checked_add(size)?;
+
+        // Regular files have a type of '0' (or NUL in old archives)
+        let regular = header[156] == b'0' || header[156] == 0;
+        if regular && name.trim_start_matches("./") == path {
+            return archive.get(data_start..data_end);
+        }
+
+        offset = data_start + (size + 511) / 512 * 512;
+    }
+    None
+}
+
+/// Gets a NUL terminated string field of a header
+fn field(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+/// Parses a NUL or space terminated octal number
+fn parse_octal(bytes: &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for &b in bytes.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)? + (b - b'0') as usize,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
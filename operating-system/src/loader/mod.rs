@@ -0,0 +1,283 @@
+pub mod elf;
+pub mod initrd;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+
+use crate::filesystem;
+use crate::memory::address_space::{AddressSpace, MapError, USER_START};
+use elf::{Elf, ProgramHeader};
+
+/// Where position independent executables are loaded
+const PIE_BASE: u64 = USER_START + 0x40_0000;
+
+/// The top of the user stack of a new program
+const STACK_TOP: u64 = 0x7FFF_FFFF_F000;
+
+/// The size of the user stack of a new program
+const STACK_SIZE: u64 = 64 * 1024;
+
+// Auxiliary vector entry types
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+#[derive(Debug)]
+pub enum Error {
+    NotElf,
+    InvalidElf,
+    Unsupported,
+    NoMemory,
+    ArgumentsTooLong,
+    Filesystem(filesystem::Error),
+}
+impl From<filesystem::Error> for Error {
+    fn from(orig: filesystem::Error) -> Self { Self::Filesystem(orig) }
+}
+impl From<MapError> for Error {
+    fn from(orig: MapError) -> Self {
+        match orig {
+            MapError::OutsideUserSpace => Self::Unsupported,
+            MapError::NoMemory => Self::NoMemory,
+            MapError::AlreadyMapped => Self::InvalidElf,
+        }
+    }
+}
+
+/// A program that has been loaded into its own address space and is
+/// ready to be started
+#[derive(Debug)]
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+/// Reads an executable from the initial ramdisk, or from the boot
+/// volume if the ramdisk does not have it
+pub fn read(path: &str) -> Result<Vec<u8>, Error> {
+    if let Some(data) = initrd::get(path) {
+        return Ok(data.to_vec());
+    }
+    Ok(filesystem::read_file(path)?)
+}
+
+/// Maps an ELF64 executable into a new address space and sets up its
+/// stack
+pub fn load(data: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, Error> {
+    let elf = Elf::parse(data)?;
+    let mut space = AddressSpace::new().ok_or(Error::NoMemory)?;
+
+    // Position independent executables are relocated to PIE_BASE
+    let base = match elf.header.ty {
+        elf::ET_DYN => PIE_BASE,
+        _ => 0,
+    };
+
+    let mut phdr = None;
+    let mut dynamic = None;
+    for ph in elf.program_headers() {
+        let ph = ph?;
+        match ph.ty {
+            elf::PT_LOAD => load_segment(&elf, &ph, base, &mut space)?,
+            elf::PT_DYNAMIC => dynamic = Some(ph),
+            elf::PT_PHDR => phdr = Some(base.checked_add(ph.vaddr).ok_or(Error::InvalidElf)?),
+            // There is no dynamic linker
+            elf::PT_INTERP => return Err(Error::Unsupported),
+            _ => (),
+        }
+    }
+
+    if let Some(dynamic) = dynamic {
+        relocate(&elf, &dynamic, base, &mut space)?;
+    }
+
+    // Find the program headers in memory if there was no PT_PHDR
+    let phdr = match phdr {
+        Some(phdr) => phdr,
+        None => find_phdr(&elf, base)?,
+    };
+
+    let entry = base.checked_add(elf.header.entry)
+        .and_then(|entry| VirtAddr::try_new(entry).ok())
+        .ok_or(Error::InvalidElf)?;
+    let auxv = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, elf::PHDR_SIZE as u64),
+        (AT_PHNUM, elf.header.phnum as u64),
+        (AT_PAGESZ, 4096),
+        (AT_BASE, 0),
+        (AT_ENTRY, entry.as_u64()),
+    ];
+    let stack_pointer = setup_stack(&mut space, argv, envp, &auxv)?;
+
+    Ok(Program {
+        address_space: space,
+        entry,
+        stack_pointer,
+    })
+}
+
+/// Maps a PT_LOAD segment with its permissions and copies its data
+fn load_segment(
+    elf: &Elf,
+    ph: &ProgramHeader,
+    base: u64,
+    space: &mut AddressSpace,
+) -> Result<(), Error> {
+    if ph.memsz == 0 {
+        return Ok(());
+    }
+    if ph.filesz > ph.memsz {
+        return Err(Error::InvalidElf);
+    }
+
+    let start = base.checked_add(ph.vaddr).ok_or(Error::InvalidElf)?;
+    let end = start.checked_add(ph.memsz).ok_or(Error::InvalidElf)?;
+
+    let mut flags = PageTableFlags::empty();
+    if ph.flags & elf::PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if ph.flags & elf::PF_X == 0 && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    // Map every page of the segment. The rest of the segment past the
+    // file data is left zeroed.
+    map_range(space, start, end, flags)?;
+    if !space.write(VirtAddr::new(start), elf.segment_data(ph)?) {
+        return Err(Error::InvalidElf);
+    }
+    Ok(())
+}
+
+/// Applies the relocations of a static position independent executable
+fn relocate(
+    elf: &Elf,
+    dynamic: &ProgramHeader,
+    base: u64,
+    space: &mut AddressSpace,
+) -> Result<(), Error> {
+    for rela in elf.relocations(dynamic)? {
+        match rela.ty {
+            elf::R_X86_64_NONE => (),
+            elf::R_X86_64_RELATIVE => {
+                let value = base.wrapping_add(rela.addend as u64);
+                let addr = base.checked_add(rela.offset)
+                    .and_then(|addr| VirtAddr::try_new(addr).ok())
+                    .ok_or(Error::InvalidElf)?;
+                if !space.write(addr, &value.to_le_bytes()) {
+                    return Err(Error::InvalidElf);
+                }
+            },
+            // Symbol relocations need a dynamic linker
+            _ => return Err(Error::Unsupported),
+        }
+    }
+    Ok(())
+}
+
+/// Finds where the program headers were loaded from the PT_LOAD segment
+/// that contains them
+fn find_phdr(elf: &Elf, base: u64) -> Result<u64, Error> {
+    let phoff = elf.header.phoff;
+    for ph in elf.program_headers() {
+        let ph = ph?;
+        let end = ph.offset.checked_add(ph.filesz).ok_or(Error::InvalidElf)?;
+        if ph.ty == elf::PT_LOAD && phoff >= ph.offset && phoff < end {
+            return base.checked_add(ph.vaddr)
+                .and_then(|vaddr| vaddr.checked_add(phoff - ph.offset))
+                .ok_or(Error::InvalidElf);
+        }
+    }
+    Ok(0)
+}
+
+/// Maps the user stack and pushes argc, argv, envp and the auxiliary
+/// vector as described by the System V ABI. Returns the initial stack
+/// pointer.
+fn setup_stack(
+    space: &mut AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, Error> {
+    let bottom = STACK_TOP - STACK_SIZE;
+    map_range(space, bottom, STACK_TOP, PageTableFlags::WRITABLE)?;
+
+    let mut sp = STACK_TOP;
+    let mut push = |space: &mut AddressSpace, data: &[u8]| -> Result<u64, Error> {
+        sp = sp
+            .checked_sub(data.len() as u64)
+            .filter(|&sp| sp >= bottom + 4096)
+            .ok_or(Error::ArgumentsTooLong)?;
+        space.write(VirtAddr::new(sp), data);
+        Ok(sp)
+    };
+
+    // Copy the strings to the top of the stack
+    let mut argv_ptrs = Vec::new();
+    for arg in argv {
+        push(space, &[0])?;
+        argv_ptrs.push(push(space, arg.as_bytes())?);
+    }
+    let mut envp_ptrs = Vec::new();
+    for env in envp {
+        push(space, &[0])?;
+        envp_ptrs.push(push(space, env.as_bytes())?);
+    }
+
+    // 16 bytes of (weak) randomness for the program's stack protector
+    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+    let random = push(space, &[tsc.to_le_bytes(), tsc.rotate_left(32).to_le_bytes()].concat())?;
+
+    // argc, argv, NULL, envp, NULL, auxv, AT_NULL
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend_from_slice(&argv_ptrs);
+    words.push(0);
+    words.extend_from_slice(&envp_ptrs);
+    words.push(0);
+    for &(ty, value) in auxv.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        words.push(ty);
+        words.push(value);
+    }
+
+    // The stack pointer must be 16 byte aligned when the program starts
+    let size = (words.len() * 8) as u64;
+    let sp = sp.checked_sub(size).ok_or(Error::ArgumentsTooLong)? & !0xF;
+    if sp < bottom + 4096 {
+        return Err(Error::ArgumentsTooLong);
+    }
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    space.write(VirtAddr::new(sp), &bytes);
+
+    Ok(VirtAddr::new(sp))
+}
+
+/// Maps every page that overlaps a range of addresses
+fn map_range(
+    space: &mut AddressSpace,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+) -> Result<(), Error> {
+    let start = VirtAddr::try_new(start).map_err(|_| Error::InvalidElf)?;
+    let end = VirtAddr::try_new(end - 1).map_err(|_| Error::InvalidElf)?;
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(end);
+    for page in Page::range_inclusive(first, last) {
+        space.map_user_page(page, flags)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! A ring buffer of the most recent log records, kept in static memory
+//! so that it works from the first log call on, before any output device
+//! is ready. Writers never lock: each claims a slot with an atomic
+//! counter, and readers use the slot's sequence number to skip records
+//! that are being written or were overwritten while they read them.
+
+extern crate alloc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+use log::{Level, Record};
+
+use crate::system::apic;
+use crate::task::timer;
+
+/// The number of records kept
+const SLOTS: usize = 512;
+
+/// How much of a module path and a message is kept
+const MODULE_LEN: usize = 48;
+const MESSAGE_LEN: usize = 160;
+
+/// A log record as kept in the buffer
+#[derive(Clone, Copy)]
+pub struct Entry {
+    /// Counts up from 0 across every record logged
+    pub sequence: u64,
+    pub level: Level,
+
+    /// The local apic id of the cpu that logged the record
+    pub cpu: u32,
+
+    /// Milliseconds since the apic timer started
+    pub time_ms: u64,
+    module: [u8; MODULE_LEN],
+    module_len: usize,
+    message: [u8; MESSAGE_LEN],
+    message_len: usize,
+}
+impl Entry {
+    const EMPTY: Entry = Entry {
+        sequence: 0,
+        level: Level::Error,
+        cpu: 0,
+        time_ms: 0,
+        module: [0; MODULE_LEN],
+        module_len: 0,
+        message: [0; MESSAGE_LEN],
+        message_len: 0,
+    };
+
+    pub fn module(&self) -> &str {
+        core::str::from_utf8(&self.module[..self.module_len]).unwrap_or("")
+    }
+
+    /// The formatted message, cut short if it did not fit
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len]).unwrap_or("")
+    }
+}
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:5}.{:03}] [cpu{}] [{}] {}: {}",
+            self.time_ms / 1000, self.time_ms % 1000, self.cpu,
+            self.level, self.module(), self.message())
+    }
+}
+
+/// A slot of the ring. Its state is 0 while it has never been written,
+/// odd while record `state / 2` is being written to it, and even once
+/// record `state / 2 - 1` is complete.
+struct Slot {
+    state: AtomicU64,
+    entry: UnsafeCell<Entry>,
+}
+unsafe impl Sync for Slot {}
+
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU64::new(0),
+    entry: UnsafeCell::new(Entry::EMPTY),
+};
+
+static RING: [Slot; SLOTS] = [EMPTY_SLOT; SLOTS];
+
+/// The sequence number of the next record
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Adds a log record to the buffer
+pub fn record(record: &Record) {
+    let sequence = NEXT.fetch_add(1, Ordering::Relaxed);
+    let slot = &RING[sequence as usize % SLOTS];
+    slot.state.store(sequence * 2 + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    let mut entry = Entry::EMPTY;
+    entry.sequence = sequence;
+    entry.level = record.level();
+    entry.cpu = apic::id();
+    entry.time_ms = timer::uptime_ms();
+    entry.module_len = truncate(&mut entry.module, record.target());
+    let mut message = Truncate::new(&mut entry.message);
+    let _ = write!(message, "{}", record.args());
+    entry.message_len = message.len();
+
+    unsafe { core::ptr::write_volatile(slot.entry.get(), entry) };
+    slot.state.store(sequence * 2 + 2, Ordering::Release);
+}
+
+/// Calls `f` with every complete record still in the buffer, oldest
+/// first. Used to replay early logs onto an output device once it is
+/// ready.
+pub fn replay(f: impl FnMut(&Entry)) {
+    replay_recent(SLOTS, f)
+}
+
+/// Calls `f` with the last `count` records, oldest first. This does not
+/// allocate, so it can be used while panicking.
+pub fn replay_recent(count: usize, mut f: impl FnMut(&Entry)) {
+    let next = NEXT.load(Ordering::Acquire);
+    for sequence in next.saturating_sub(count.min(SLOTS) as u64)..next {
+        if let Some(entry) = read(sequence) {
+            f(&entry);
+        }
+    }
+}
+
+/// Copies every complete record still in the buffer, oldest first
+pub fn entries() -> Vec<Entry> {
+    let mut entries = Vec::new();
+    replay(|entry| entries.push(*entry));
+    entries
+}
+
+/// Reads a record if it is complete and still in the buffer
+fn read(sequence: u64) -> Option<Entry> {
+    let slot = &RING[sequence as usize % SLOTS];
+    let state = slot.state.load(Ordering::Acquire);
+    if state != sequence * 2 + 2 {
+        return None;
+    }
+    let entry = unsafe { core::ptr::read_volatile(slot.entry.get()) };
+    fence(Ordering::Acquire);
+
+    // A writer may have taken over the slot while it was being copied
+    if slot.state.load(Ordering::Relaxed) != state {
+        return None;
+    }
+    Some(entry)
+}
+
+/// Copies as much of `s` into `buffer` as fits without splitting a
+/// character
+fn truncate(buffer: &mut [u8], s: &str) -> usize {
+    let mut len = s.len().min(buffer.len());
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    buffer[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len
+}
+
+/// Formats into a fixed buffer, dropping everything from the first
+/// piece that does not fit
+pub(crate) struct Truncate<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    full: bool,
+}
+impl<'a> Truncate<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Truncate<'a> {
+        Truncate { buffer, len: 0, full: false }
+    }
+
+    /// The number of bytes written
+    pub(crate) fn len(&self) -> usize { self.len }
+}
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.full {
+            let copied = truncate(&mut self.buffer[self.len..], s);
+            self.len += copied;
+            self.full = copied < s.len();
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,216 @@
-use log::{Record, Level, Metadata, LevelFilter};
-use core::fmt::{Write, Debug};
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use log::{Level, Record, Metadata, LevelFilter};
+use core::fmt::{self, Write, Debug};
+use core::str::FromStr;
+use spin::Mutex;
 use super::ST;
+use crate::drivers::serial;
+use crate::filesystem;
+use crate::graphics::console;
+use crate::log_buffer;
+use crate::task::timer;
+use uefi::Handle;
 use uefi::ResultExt;
+use uefi::proto::loaded_image::LoadedImage;
+use x86_64::instructions::interrupts;
 
 pub static UEFI_LOGGER: UefiLogger = UefiLogger;
+pub static SERIAL_LOGGER: SerialLogger = SerialLogger;
+pub static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
+static KERNEL_LOGGER: KernelLogger = KernelLogger;
+
+/// The file on the boot volume that holds the log filter used at boot
+const LOG_CONFIG_PATH: &str = "/log.cfg";
+
+/// The load option that sets the log filter, like `log=debug`. It takes
+/// precedence over the config file.
+const LOG_OPTION: &str = "log=";
+
+/// The level of modules that no filter directive names
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Records down to this level are kept in the log buffer even when the
+/// filter does not show them
+const RECORD_LEVEL: LevelFilter = LevelFilter::Debug;
+
+static FILTER: Mutex<Filter> = Mutex::new(Filter::new(DEFAULT_LEVEL));
+
+/// Sets up the UEFI console and COM1, and sends every log record to both
+pub fn init() {
+    serial::init_port(serial::COM1);
+    UefiLogger::init();
+    log::set_logger(&KERNEL_LOGGER)
+        .map(|()| log::set_max_level(DEFAULT_LEVEL.max(RECORD_LEVEL)))
+        .expect("Failed to setup logging");
+}
+
+/// Reads the log filter from the config file and the load options of
+/// the kernel image. Needs boot services.
+pub fn configure(image: Handle) {
+    if let Ok(config) = filesystem::read_file(LOG_CONFIG_PATH) {
+        let spec = String::from_utf8_lossy(&config);
+        if let Some(spec) = spec.lines().map(str::trim).find(|line| !line.is_empty()) {
+            apply(spec);
+        }
+    }
+
+    let st = match unsafe { ST.as_ref() } {
+        Some(st) => st,
+        None => return,
+    };
+    let image = match st.boot_services().handle_protocol::<LoadedImage>(image) {
+        Ok(image) => unsafe { &*image.split().1.get() },
+        Err(_) => return,
+    };
+    let mut buffer = [0u8; 512];
+    if let Ok(options) = image.load_options(&mut buffer) {
+        let spec = options.split_whitespace()
+            .find_map(|option| option.strip_prefix(LOG_OPTION));
+        if let Some(spec) = spec {
+            apply(spec);
+        }
+    }
+}
+
+/// Moves screen output from the UEFI console to the framebuffer console,
+/// and shows the logs from before it started that the filter lets through
+pub fn start_console() {
+    if !console::init() {
+        log::warn!("Could not start the framebuffer console");
+        return;
+    }
+    log_buffer::replay(|entry| {
+        if shown(entry.level, entry.module()) {
+            CONSOLE_LOGGER.write(entry.level, entry.time_ms, entry.module(),
+                format_args!("{}", entry.message()));
+        }
+    });
+}
+
+/// Sets a filter from boot configuration, keeping the old one if it is
+/// not valid
+fn apply(spec: &str) {
+    match set_filter(spec) {
+        Ok(()) => log::info!("Log filter set to {}", filter()),
+        Err(err) => log::warn!("Ignoring the log filter {:?}: {:?}", spec, err),
+    }
+}
+
+/// Replaces the log filter. `spec` is a list of directives separated by
+/// commas, each either a level for every module, like `info`, or a
+/// module and its level, like `system::acpi_methods=trace`.
+pub fn set_filter(spec: &str) -> Result<(), InvalidFilter> {
+    let filter = Filter::parse(spec)?;
+    log::set_max_level(filter.max_level().max(RECORD_LEVEL));
+    interrupts::without_interrupts(|| *FILTER.lock() = filter);
+    Ok(())
+}
+
+/// Gets the current log filter in the form `set_filter` takes
+pub fn filter() -> String {
+    interrupts::without_interrupts(|| FILTER.lock().to_string())
+}
+
+/// Checks whether the filter lets a record through
+fn shown(level: Level, target: &str) -> bool {
+    level <= interrupts::without_interrupts(|| FILTER.lock().level(target))
+}
+
+/// A log filter directive that could not be parsed
+#[derive(Debug)]
+pub struct InvalidFilter(pub String);
+
+/// The level of every module and the levels of modules that differ
+pub struct Filter {
+    default: LevelFilter,
+
+    /// Module paths and their levels
+    modules: Vec<(String, LevelFilter)>,
+}
+impl Filter {
+    pub const fn new(default: LevelFilter) -> Filter {
+        Filter { default, modules: Vec::new() }
+    }
+
+    pub fn parse(spec: &str) -> Result<Filter, InvalidFilter> {
+        let level = |level: &str| LevelFilter::from_str(level.trim())
+            .map_err(|_| InvalidFilter(level.to_string()));
+
+        let mut filter = Filter::new(DEFAULT_LEVEL);
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level_name)) => {
+                    let module = module.trim();
+                    if module.split("::").any(str::is_empty) {
+                        return Err(InvalidFilter(directive.to_string()));
+                    }
+                    filter.modules.push((module.to_string(), level(level_name)?));
+                },
+                None => filter.default = level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Gets the level of a module. A directive applies to every module
+    /// whose path contains it, like `acpi_methods` for
+    /// `operating_system::system::acpi_methods`, and the longest one
+    /// wins. This does not allocate, since the allocator logs too.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.modules.iter()
+            .filter(|(module, _)| contains_path(target, module))
+            .max_by_key(|(module, _)| module.matches("::").count())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// Gets the most verbose level of any module
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|&(_, level)| level).fold(self.default, LevelFilter::max)
+    }
+}
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default.to_string().to_lowercase())?;
+        for (module, level) in self.modules.iter() {
+            write!(f, ",{}={}", module, level.to_string().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether a module path contains `module` as whole components
+fn contains_path(target: &str, module: &str) -> bool {
+    target.match_indices(module).any(|(i, _)| {
+        let end = i + module.len();
+        (i == 0 || target[..i].ends_with("::"))
+            && (end == target.len() || target[end..].starts_with("::"))
+    })
+}
+
+/// Keeps every log record in the log buffer, and passes the ones that
+/// the filter lets through on to the serial port and the screen, which is
+/// the UEFI console until the framebuffer console starts
+pub struct KernelLogger;
+impl log::Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        shown(metadata.level(), metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        log_buffer::record(record);
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        SERIAL_LOGGER.log(record);
+        if console::is_active() {
+            CONSOLE_LOGGER.log(record);
+        } else {
+            UEFI_LOGGER.log(record);
+        }
+    }
+    fn flush(&self) {}
+}
 
 pub struct UefiLogger;
 impl UefiLogger {
@@ -13,31 +220,94 @@ impl UefiLogger {
             st.stdout()
                 .reset(false)
                 .expect_success("Failed to reset output buffer");
-            
-            // Set the default logger
-            log::set_logger(&UEFI_LOGGER)
-                .map(|()| log::set_max_level(LevelFilter::Trace))
-                .expect("Failed to setup logging using UEFI");
         }
     }
 } impl log::Log for UefiLogger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
-    }
+    fn enabled(&self, _metadata: &Metadata) -> bool { true }
     fn log(&self, record: &Record) {
         if let Some(st) = unsafe { ST.as_ref() } {
-            writeln!(
+            // A full or broken console is no reason to stop
+            let _ = writeln!(
                 st.stdout(),
                 "[{}] {}",
                 record.level(),
                 record.args()
-            ).unwrap();
+            );
         }
-        if record.level() == Level::Error { loop {} }
     }
     fn flush(&self) {}
 }
 
+/// Mirrors every log record to the serial port with the time since the
+/// apic timer started, so boot logs can be captured without a screen
+pub struct SerialLogger;
+impl log::Log for SerialLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool { true }
+    fn log(&self, record: &Record) {
+        let ms = timer::uptime_ms();
+        serial::write_fmt(format_args!(
+            "[{:5}.{:03}] [{}] {}: {}\n",
+            ms / 1000,
+            ms % 1000,
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+    fn flush(&self) {}
+}
+
+/// Writes log records to the framebuffer console, colored by level
+pub struct ConsoleLogger;
+impl ConsoleLogger {
+    fn write(&self, level: Level, ms: u64, target: &str, args: fmt::Arguments) {
+        console::write_fmt(console::level_color(level), format_args!(
+            "[{:5}.{:03}] [{}] {}: {}\n",
+            ms / 1000,
+            ms % 1000,
+            level,
+            target,
+            args
+        ));
+    }
+} impl log::Log for ConsoleLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool { true }
+    fn log(&self, record: &Record) {
+        self.write(record.level(), timer::uptime_ms(), record.target(), *record.args());
+    }
+    fn flush(&self) {}
+}
+
+/// Writes text to the screen and the serial port as it is, for output
+/// that is not a log record
+pub fn print(args: fmt::Arguments) {
+    serial::write_fmt(args);
+    if console::is_active() {
+        console::write_fmt(console::level_color(Level::Info), args);
+    } else if let Some(st) = unsafe { ST.as_ref() } {
+        let _ = st.stdout().write_fmt(args);
+    }
+}
+
+/// Logs a panic without waiting for any lock, since the panic may have
+/// happened while one was held. Outputs that are in use are skipped.
+pub fn log_panic(args: fmt::Arguments) {
+    let record = Record::builder()
+        .args(args)
+        .level(Level::Error)
+        .target("panic")
+        .build();
+    log_buffer::record(&record);
+
+    let ms = timer::uptime_ms();
+    serial::try_write_fmt(format_args!("[{:5}.{:03}] [ERROR] panic: {}\n", ms / 1000, ms % 1000, args));
+    if console::is_active() {
+        CONSOLE_LOGGER.log(&record);
+    } else {
+        UEFI_LOGGER.log(&record);
+    }
+}
+
 pub fn _crash(string: &dyn Debug) -> ! {
     if let Some(st) = unsafe { ST.as_ref() } {
         writeln!(st.stdout(), "FATAL ERROR: {:?}", string).unwrap();
@@ -2,6 +2,7 @@
 #![feature(abi_efiapi)]
 #![no_std]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(abi_x86_interrupt)]
 #![feature(allocator_api)]
 #![feature(alloc_error_handler)]
@@ -11,26 +12,39 @@
 #![allow(unreachable_code)]
 extern crate alloc;
 
+mod crash;
+mod drivers;
+mod filesystem;
+mod graphics;
+mod ipc;
 mod kernel;
+mod loader;
+mod log_buffer;
 mod logging;
 mod memory;
+mod process;
+mod shell;
+mod syscalls;
 mod system;
-// mod graphics;
-// mod filesystem;
-// mod system;
+mod task;
 
 use core::alloc::Layout;
 use core::panic::PanicInfo;
-use log::{info, debug, error};
+use log::{info, debug};
 use uefi::prelude::*;
-use logging::UefiLogger;
 use system::SystemHandles;
 
 pub static mut ST: Option<SystemTable<Boot>> = None;
 
 #[panic_handler]
 fn panic(i: &PanicInfo) -> ! {
-    error!("{:?}", i.message());
+    // The report comes first, since logging can hang on a lock that was
+    // held when the panic happened
+    crash::save(i);
+    match i.message() {
+        Some(message) => logging::log_panic(format_args!("{}", message)),
+        None => logging::log_panic(format_args!("{}", i)),
+    }
     loop{}
 }
 
@@ -41,13 +55,17 @@ fn alloc_error_handler(layout: Layout) -> ! {
 }
 
 #[entry]
-fn efi_main(_image: uefi::Handle, st: SystemTable<Boot>) -> Status {
+fn efi_main(image: uefi::Handle, st: SystemTable<Boot>) -> Status {
     
     // Save the system table as a global variable
     unsafe { ST = Some(st) };
 
-    // Initialize UEFI text services and logging
-    UefiLogger::init();
+    // Initialize UEFI text services, the serial port and logging
+    logging::init();
+    logging::configure(image);
+
+    // Print the report of the last crash, if there is one
+    crash::init(image);
     
     info!("Loading OS...");
 
@@ -0,0 +1,285 @@
+use x86_64::VirtAddr;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+    PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult};
+
+use super::paging::{self, PHYSICAL_MEMORY_OFFSET};
+use super::uefi_allocator::UefiFrameAllocator;
+
+/// The first level 4 entry used for user mappings. Entries below this
+/// hold the firmware's identity map and are shared with the kernel.
+const FIRST_USER_ENTRY: usize = 128;
+
+/// The first level 4 entry past the end of the lower half
+const LAST_USER_ENTRY: usize = 256;
+
+/// The lowest address that can be mapped into an address space
+pub const USER_START: u64 = (FIRST_USER_ENTRY as u64) << 39;
+
+/// Marks a page whose frame is owned by something else (like a shared
+/// memory region), so it is not freed with the address space
+const SHARED: PageTableFlags = PageTableFlags::BIT_9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapError {
+    /// The page is below USER_START or in the upper half
+    OutsideUserSpace,
+
+    /// There are no free frames left
+    NoMemory,
+
+    /// The page is already mapped to another frame
+    AlreadyMapped,
+}
+impl From<MapToError<Size4KiB>> for MapError {
+    fn from(orig: MapToError<Size4KiB>) -> Self {
+        match orig {
+            MapToError::FrameAllocationFailed => Self::NoMemory,
+            MapToError::PageAlreadyMapped(_) | MapToError::ParentEntryHugePage => {
+                Self::AlreadyMapped
+            },
+        }
+    }
+}
+
+/// A set of page tables that share the kernel's mappings but have
+/// their own user mappings
+#[derive(Debug)]
+pub struct AddressSpace {
+    l4_frame: PhysFrame<Size4KiB>,
+}
+impl AddressSpace {
+    /// Creates a new address space with no user mappings
+    pub fn new() -> Option<AddressSpace> {
+        let l4_frame = UefiFrameAllocator.allocate_frame()?;
+        let (active, _) = Cr3::read();
+
+        unsafe {
+            let table = &mut *table_ptr(l4_frame);
+            let kernel = &*table_ptr(active);
+            table.zero();
+
+            // Share every kernel entry
+            for i in 0..FIRST_USER_ENTRY {
+                table[i] = kernel[i].clone();
+            }
+            for i in LAST_USER_ENTRY..512 {
+                table[i] = kernel[i].clone();
+            }
+        }
+
+        Some(AddressSpace { l4_frame })
+    }
+
+    /// Gets a mapper for the page tables of the address space
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe {
+            OffsetPageTable::new(
+                &mut *table_ptr(self.l4_frame),
+                VirtAddr::new(PHYSICAL_MEMORY_OFFSET),
+            )
+        }
+    }
+
+    /// Loads the address space into cr3
+    pub fn activate(&self) {
+        unsafe { Cr3::write(self.l4_frame, Cr3Flags::empty()) };
+    }
+
+    /// Checks whether this address space is the one loaded in cr3
+    pub fn is_active(&self) -> bool { Cr3::read().0 == self.l4_frame }
+
+    /// Maps a zeroed frame to a page that is accessible from ring 3. If
+    /// the page is already mapped, its flags are extended instead.
+    pub fn map_user_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        check_user_page(page)?;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        // Merge the flags of segments that share a page
+        if let TranslateResult::Mapped { flags: old, .. } =
+            self.mapper().translate(page.start_address())
+        {
+            let mut merged = old | flags;
+            if !(old & flags).contains(PageTableFlags::NO_EXECUTE) {
+                merged.remove(PageTableFlags::NO_EXECUTE);
+            }
+            unsafe {
+                self.mapper()
+                    .update_flags(page, merged)
+                    .map(|flush| flush.flush())
+                    .ok();
+            }
+            return Ok(());
+        }
+
+        // Get and zero a new frame
+        let frame = UefiFrameAllocator
+            .allocate_frame()
+            .ok_or(MapError::NoMemory)?;
+        unsafe {
+            core::ptr::write_bytes(
+                paging::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                0,
+                page.size() as usize,
+            );
+        }
+
+        self.map_frame(page, frame, flags)
+    }
+
+    /// Maps a frame that is owned by something else (like a shared
+    /// memory region) to a page that is accessible from ring 3. The
+    /// frame is not freed with the address space.
+    pub fn map_shared_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame: PhysFrame<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        check_user_page(page)?;
+        let flags = flags
+            | PageTableFlags::PRESENT
+            | PageTableFlags::USER_ACCESSIBLE
+            | SHARED;
+        self.map_frame(page, frame, flags)
+    }
+
+    fn map_frame(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame: PhysFrame<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        let parent_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE;
+        unsafe {
+            self.mapper()
+                .map_to_with_table_flags(
+                    page,
+                    frame,
+                    flags,
+                    parent_flags,
+                    &mut UefiFrameAllocator,
+                )
+                .map(|flush| flush.flush())
+                .map_err(MapError::from)
+        }
+    }
+
+    /// Unmaps a page mapped with `map_user_page` or `map_shared_page`,
+    /// and frees its frame unless it is shared. The page tables leading
+    /// to it are kept until the address space is dropped.
+    pub fn unmap_user_page(&mut self, page: Page<Size4KiB>) {
+        let mut mapper = self.mapper();
+        let shared = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags.contains(SHARED),
+            _ => return,
+        };
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            if !shared {
+                unsafe { UefiFrameAllocator.deallocate_frame(frame) };
+            }
+        }
+    }
+
+    /// Checks whether a page is mapped
+    pub fn is_mapped(&mut self, page: Page<Size4KiB>) -> bool {
+        self.mapper().translate_page(page).is_ok()
+    }
+
+    /// Copies data into memory mapped in this address space, even if
+    /// it is not active or the pages are read only. Returns false if
+    /// any part of the range is not mapped.
+    pub fn write(&mut self, addr: VirtAddr, data: &[u8]) -> bool {
+        let mut written = 0;
+        while written < data.len() {
+            let addr = addr + written;
+            let phys = match self.mapper().translate_addr(addr) {
+                Some(phys) => phys,
+                None => return false,
+            };
+
+            // Copy up to the end of the page
+            let len = (4096 - addr.page_offset() as usize).min(data.len() - written);
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    paging::phys_to_virt(phys).as_mut_ptr::<u8>(),
+                    len,
+                );
+            }
+            written += len;
+        }
+        true
+    }
+
+    /// Frees every user page and page table of the address space
+    fn free_user_mappings(&mut self) {
+        let mut allocator = UefiFrameAllocator;
+        let l4 = unsafe { &mut *table_ptr(self.l4_frame) };
+
+        for i in FIRST_USER_ENTRY..LAST_USER_ENTRY {
+            if l4[i].is_unused() {
+                continue;
+            }
+            unsafe { free_table(l4[i].frame().unwrap(), 3, &mut allocator) };
+            l4[i].set_unused();
+        }
+    }
+}
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // The address space can not be freed while it is in use
+        assert!(!self.is_active(), "Dropped the active address space");
+
+        self.free_user_mappings();
+        unsafe { UefiFrameAllocator.deallocate_frame(self.l4_frame) };
+    }
+}
+
+/// Checks that a page lies in the part of an address space that
+/// belongs to the user
+fn check_user_page(page: Page<Size4KiB>) -> Result<(), MapError> {
+    let addr = page.start_address().as_u64();
+    if addr < USER_START || addr >= (LAST_USER_ENTRY as u64) << 39 {
+        return Err(MapError::OutsideUserSpace);
+    }
+    Ok(())
+}
+
+/// Gets a pointer to the page table stored in a frame
+fn table_ptr(frame: PhysFrame<Size4KiB>) -> *mut PageTable {
+    paging::phys_to_virt(frame.start_address()).as_mut_ptr()
+}
+
+/// Recursively frees a page table, the tables below it and the frames
+/// they map, except for shared frames. `level` is 1 for a table that
+/// maps frames directly.
+unsafe fn free_table(
+    frame: PhysFrame<Size4KiB>,
+    level: u8,
+    allocator: &mut UefiFrameAllocator,
+) {
+    let table = &*table_ptr(frame);
+    for entry in table.iter().filter(|entry| !entry.is_unused()) {
+        if let Ok(child) = entry.frame() {
+            if level == 1 {
+                if !entry.flags().contains(SHARED) {
+                    allocator.deallocate_frame(child);
+                }
+            } else {
+                free_table(child, level - 1, allocator);
+            }
+        }
+    }
+    allocator.deallocate_frame(frame);
+}
@@ -1,3 +1,5 @@
 pub mod memory_map;
 pub mod uefi_allocator;
 pub mod global_allocator;
+pub mod paging;
+pub mod address_space;
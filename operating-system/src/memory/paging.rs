@@ -0,0 +1,91 @@
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+    PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::structures::paging::mapper::MapToError;
+
+use super::uefi_allocator::UefiFrameAllocator;
+
+/// UEFI identity maps all of physical memory, so physical addresses
+/// can be used directly as virtual addresses
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0;
+
+/// Gets a virtual address that can be used to access a physical address
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(addr.as_u64() + PHYSICAL_MEMORY_OFFSET)
+}
+
+/// Gets a mapper for the page table that is currently loaded in cr3
+///
+/// This is unsafe because the caller must make sure that only one
+/// mapper for the active page table exists at a time
+pub unsafe fn active_page_table() -> OffsetPageTable<'static> {
+    let (frame, _) = Cr3::read();
+    let table = phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>();
+    OffsetPageTable::new(&mut *table, VirtAddr::new(PHYSICAL_MEMORY_OFFSET))
+}
+
+/// Runs a closure with the write protect bit of cr0 cleared.
+/// Some firmware marks its page tables as read only, so they can only
+/// be modified with write protection disabled.
+pub fn without_write_protect<F, R>(f: F) -> R
+where F: FnOnce() -> R {
+    let flags = Cr0::read();
+    unsafe { Cr0::write(flags - Cr0Flags::WRITE_PROTECT) };
+    let ret = f();
+    unsafe { Cr0::write(flags) };
+    ret
+}
+
+/// Maps a zeroed frame to a page of the active page table that can be
+/// accessed from ring 3
+pub fn map_user_page(
+    page: Page<Size4KiB>,
+    flags: PageTableFlags,
+) -> Result<PhysFrame<Size4KiB>, MapToError<Size4KiB>> {
+    let mut frame_allocator = UefiFrameAllocator;
+
+    // Get and zero a new frame
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    unsafe {
+        core::ptr::write_bytes(
+            phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+            0,
+            page.size() as usize,
+        );
+    }
+
+    // Every table leading up to the page must also be user accessible
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let parent_flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE;
+
+    without_write_protect(|| unsafe {
+        active_page_table()
+            .map_to_with_table_flags(page, frame, flags, parent_flags, &mut frame_allocator)
+            .map(|flush| flush.flush())
+    })?;
+
+    Ok(frame)
+}
+
+/// Unmaps a page of the active page table that was mapped with
+/// `map_user_page`, and frees its frame
+pub fn unmap_user_page(page: Page<Size4KiB>) {
+    without_write_protect(|| unsafe {
+        if let Ok((frame, flush)) = active_page_table().unmap(page) {
+            flush.flush();
+            UefiFrameAllocator.deallocate_frame(frame);
+        }
+    });
+}
+
+/// Checks whether a page is mapped in the active page table
+pub fn is_mapped(page: Page<Size4KiB>) -> bool {
+    unsafe { active_page_table() }.translate_page(page).is_ok()
+}
@@ -3,7 +3,9 @@ use core::alloc::Allocator;
 use core::alloc::Layout;
 use core::slice;
 use core::ptr::NonNull;
-use uefi::table::boot::MemoryType;
+use uefi::table::boot::{AllocateType, MemoryType};
+use x86_64::PhysAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
 use crate::ST;
 
 pub struct UefiAllocator;
@@ -44,3 +46,51 @@ unsafe impl Allocator for UefiAllocator {
         }
     }
 }
+
+/// Allocates physical frames using UEFI boot services so that frames
+/// handed out to page tables never overlap memory that UEFI gives to
+/// the pool allocator
+pub struct UefiFrameAllocator;
+unsafe impl FrameAllocator<Size4KiB> for UefiFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        // Get the system table
+        let st = unsafe { ST.as_ref() }?;
+
+        // Allocate a single page
+        let addr = st.boot_services()
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1)
+            .ok()?;
+        let (_, addr) = addr.split();
+
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+impl UefiFrameAllocator {
+    /// Allocates physically contiguous frames and returns the first one
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame<Size4KiB>> {
+        let st = unsafe { ST.as_ref() }?;
+        let addr = st.boot_services()
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, count)
+            .ok()?;
+        let (_, addr) = addr.split();
+
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Frees frames allocated by `allocate_contiguous`
+    #[allow(unused_must_use)]
+    pub unsafe fn deallocate_contiguous(&mut self, first: PhysFrame<Size4KiB>, count: usize) {
+        if let Some(st) = ST.as_ref() {
+            st.boot_services().free_pages(first.start_address().as_u64(), count);
+        }
+    }
+}
+impl FrameDeallocator<Size4KiB> for UefiFrameAllocator {
+    #[allow(unused_must_use)]
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        // Get the system table
+        if let Some(st) = ST.as_ref() {
+            st.boot_services().free_pages(frame.start_address().as_u64(), 1);
+        }
+    }
+}
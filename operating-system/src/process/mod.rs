@@ -0,0 +1,395 @@
+//! Processes: programs running in their own address space, along with
+//! the files, memory and windows they own.
+//!
+//! There is no scheduler yet, so a process runs until it exits as soon
+//! as it is spawned, while its parent waits inside the spawn system
+//! call. Each process has its own kernel stack so that it can make
+//! system calls (including spawning its own children) while its
+//! parent's system call is still in progress. The exit code of a
+//! process is kept until its parent collects it with `wait`.
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use log::debug;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+
+use crate::filesystem::FileTable;
+use crate::graphics;
+use crate::graphics::terminal::Terminal;
+use crate::ipc::{HandleTable, Object, SharedMemory};
+use crate::loader;
+use crate::memory::address_space::{AddressSpace, MapError};
+use crate::memory::paging;
+use crate::system::{gdt, syscall, usermode};
+
+/// A process ID
+pub type Pid = u64;
+
+/// The process ID of the kernel, which owns anything created before
+/// the first program runs
+pub const KERNEL_PID: Pid = 0;
+
+/// The size of the kernel stack given to each process
+const KERNEL_STACK_SIZE: usize = 16 * 1024;
+
+/// Where anonymous mappings are placed when the caller does not ask for
+/// a specific address
+const MMAP_START: u64 = 0x6000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// The process is running in ring 3 or in a system call
+    Running,
+
+    /// The process is waiting for a child process to exit
+    Waiting(Pid),
+
+    /// The process has exited and its parent has not collected its exit
+    /// code yet
+    Zombie(u64),
+}
+
+/// A program and the resources it owns
+pub struct Process {
+    pid: Pid,
+    parent: Pid,
+
+    /// The path the program was loaded from
+    name: String,
+    state: State,
+
+    /// The page tables of the program. The kernel uses the page tables
+    /// set up by the firmware instead.
+    address_space: Option<AddressSpace>,
+
+    /// The stack used by system calls and interrupts from the process,
+    /// and a pointer to its top
+    kernel_stack: Option<Box<[u8]>>,
+    kernel_stack_top: VirtAddr,
+
+    /// The files opened by the process
+    pub files: FileTable,
+
+    /// The channels and shared memory regions held by the process
+    pub handles: HandleTable,
+
+    /// The terminal that standard input and output go to, inherited from
+    /// the parent. They use the UEFI console if there is none.
+    pub terminal: Option<Arc<Mutex<Terminal>>>,
+
+    /// The shared memory regions mapped into the address space, which
+    /// must outlive it
+    shared: Vec<Arc<SharedMemory>>,
+
+    /// Where the next anonymous mapping will be placed
+    pub mmap_next: u64,
+}
+impl Process {
+    /// Creates the entry of the kernel itself, which runs on the
+    /// stacks set up in the GDT
+    fn kernel() -> Process {
+        Process {
+            pid: KERNEL_PID,
+            parent: KERNEL_PID,
+            name: String::from("kernel"),
+            state: State::Running,
+            address_space: None,
+            kernel_stack: None,
+            kernel_stack_top: gdt::kernel_stack(),
+            files: FileTable::new(),
+            handles: HandleTable::new(),
+            terminal: None,
+            shared: Vec::new(),
+            mmap_next: MMAP_START,
+        }
+    }
+
+    /// Creates a process for a program that has been loaded into an
+    /// address space
+    fn new(pid: Pid, parent: Pid, name: &str, address_space: AddressSpace) -> Process {
+        // Build the stack directly on the heap, the current kernel stack
+        // is not large enough to hold it
+        let kernel_stack = alloc::vec![0; KERNEL_STACK_SIZE].into_boxed_slice();
+        let end = VirtAddr::from_ptr(kernel_stack.as_ptr()) + KERNEL_STACK_SIZE;
+
+        Process {
+            pid,
+            parent,
+            name: String::from(name),
+            state: State::Running,
+            address_space: Some(address_space),
+            kernel_stack: Some(kernel_stack),
+            kernel_stack_top: end.align_down(16u64),
+            files: FileTable::new(),
+            handles: HandleTable::new(),
+            terminal: None,
+            shared: Vec::new(),
+            mmap_next: MMAP_START,
+        }
+    }
+
+    pub fn pid(&self) -> Pid { self.pid }
+
+    pub fn parent(&self) -> Pid { self.parent }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn state(&self) -> State { self.state }
+
+    /// Maps a zeroed page that is accessible from ring 3 into the
+    /// address space of the process
+    pub fn map_user_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        match self.address_space.as_mut() {
+            Some(space) => space.map_user_page(page, flags),
+            None => paging::map_user_page(page, flags)
+                .map(drop)
+                .map_err(MapError::from),
+        }
+    }
+
+    /// Unmaps a page mapped with `map_user_page` or `map_shared`, and
+    /// frees its frame unless it belongs to a shared memory region
+    pub fn unmap_user_page(&mut self, page: Page<Size4KiB>) {
+        match self.address_space.as_mut() {
+            Some(space) => space.unmap_user_page(page),
+            None => paging::unmap_user_page(page),
+        }
+    }
+
+    /// Checks whether a page is mapped in the address space of the
+    /// process
+    pub fn is_mapped(&mut self, page: Page<Size4KiB>) -> bool {
+        match self.address_space.as_mut() {
+            Some(space) => space.is_mapped(page),
+            None => paging::is_mapped(page),
+        }
+    }
+
+    /// Maps a shared memory region into the address space of the
+    /// process starting at `addr`
+    pub fn map_shared(
+        &mut self,
+        region: Arc<SharedMemory>,
+        addr: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        // The kernel reaches shared memory through the identity map
+        let space = self.address_space.as_mut().ok_or(MapError::OutsideUserSpace)?;
+
+        let first = Page::<Size4KiB>::from_start_address(addr)
+            .map_err(|_| MapError::OutsideUserSpace)?;
+
+        // Keep the frames alive while they are mapped
+        let frames = region.frames();
+        self.shared.push(region);
+        for (i, frame) in frames.enumerate() {
+            if let Err(err) = space.map_shared_page(first + i as u64, frame, flags) {
+                // Leave nothing of a failed mapping behind
+                for j in 0..i {
+                    space.unmap_user_page(first + j as u64);
+                }
+                self.shared.pop();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees everything owned by a process that has exited. The
+    /// process's address space and kernel stack must not be in use.
+    fn release(&mut self) {
+        self.files.clear();
+        self.handles.clear();
+        self.terminal = None;
+        self.address_space = None;
+        self.shared.clear();
+        self.kernel_stack = None;
+
+        // Only touch the display if something could have drawn to it
+        graphics::with_initialized_display(|screen, wm| {
+            if wm.destroy_window(self.pid) {
+                wm.draw(screen);
+            }
+        });
+    }
+}
+
+/// A summary of a process, as returned by `list`
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Pid,
+    pub name: String,
+    pub state: State,
+}
+
+struct ProcessTable {
+    processes: BTreeMap<Pid, Process>,
+
+    /// The process that is running
+    current: Pid,
+
+    /// The ID that will be given to the next process
+    next_pid: Pid,
+}
+impl ProcessTable {
+    fn new() -> ProcessTable {
+        let mut processes = BTreeMap::new();
+        processes.insert(KERNEL_PID, Process::kernel());
+        ProcessTable {
+            processes,
+            current: KERNEL_PID,
+            next_pid: KERNEL_PID + 1,
+        }
+    }
+
+    fn get(&mut self, pid: Pid) -> &mut Process {
+        self.processes
+            .get_mut(&pid)
+            .expect("A running process is missing from the process table")
+    }
+}
+
+/// Every process that is running or has not been waited for
+static mut PROCESSES: Option<ProcessTable> = None;
+
+fn table() -> &'static mut ProcessTable {
+    unsafe { PROCESSES.get_or_insert_with(ProcessTable::new) }
+}
+
+/// Gets the ID of the process that is running
+pub fn current() -> Pid { table().current }
+
+/// Runs a function with the process that is running
+pub fn with_current<T>(f: impl FnOnce(&mut Process) -> T) -> T {
+    let table = table();
+    f(table.get(table.current))
+}
+
+/// Lists every process in the process table
+pub fn list() -> Vec<ProcessInfo> {
+    table()
+        .processes
+        .values()
+        .map(|p| ProcessInfo {
+            pid: p.pid,
+            parent: p.parent,
+            name: p.name.clone(),
+            state: p.state,
+        })
+        .collect()
+}
+
+/// Loads a program as a child of the current process and runs it until
+/// it exits. The exit code is kept until the parent calls `wait`. The
+/// objects are given to the child as handles numbered from
+/// `HandleTable::FIRST_HANDLE`.
+pub fn spawn(
+    path: &str,
+    argv: &[&str],
+    envp: &[&str],
+    objects: Vec<Object>,
+) -> Result<Pid, loader::Error> {
+    debug!("Loading {}", path);
+    let program = loader::load(&loader::read(path)?, argv, envp)?;
+
+    let table = table();
+    let pid = table.next_pid;
+    table.next_pid += 1;
+    let parent = table.current;
+
+    let mut process = Process::new(pid, parent, path, program.address_space);
+    process.terminal = table.get(parent).terminal.clone();
+    for object in objects {
+        // A new table has room for every object a message can carry
+        process.handles.insert(object).ok();
+    }
+    table.processes.insert(pid, process);
+
+    debug!("Starting process {} ({})", pid, path);
+    let code = unsafe { run(pid, program.entry, program.stack_pointer) };
+    debug!("Process {} exited with {}", pid, code);
+
+    exited(pid, code);
+    Ok(pid)
+}
+
+/// Switches to a process's address space and kernel stack and runs it
+/// until it exits, then switches back to its parent
+unsafe fn run(pid: Pid, entry: VirtAddr, stack: VirtAddr) -> u64 {
+    let table = table();
+    let parent = table.current;
+    let (parent_table, flags) = Cr3::read();
+
+    table.get(parent).state = State::Waiting(pid);
+    let child = table.get(pid);
+    if let Some(space) = child.address_space.as_ref() {
+        space.activate();
+    }
+    syscall::set_kernel_stack(child.kernel_stack_top);
+    table.current = pid;
+
+    let code = usermode::run(entry, stack);
+
+    // usermode::run returns on the parent's kernel stack
+    let table = self::table();
+    table.current = parent;
+    let parent = table.get(parent);
+    parent.state = State::Running;
+    syscall::set_kernel_stack(parent.kernel_stack_top);
+    Cr3::write(parent_table, flags);
+    code
+}
+
+/// Frees the resources of a process that has exited and makes it a
+/// zombie until its parent waits for it
+fn exited(pid: Pid, code: u64) {
+    let table = table();
+    let process = table.get(pid);
+    process.release();
+    process.state = State::Zombie(code);
+
+    // Nobody is left to wait for the children of the process. Children
+    // have always exited by the time their parent does, so they can be
+    // removed.
+    table.processes.retain(|_, p| p.parent != pid || p.pid == KERNEL_PID);
+}
+
+/// Waits for a child of the current process to exit, then removes it
+/// from the process table and returns its exit code. Returns None if
+/// the process is not a child of the current process.
+pub fn wait(pid: Pid) -> Option<u64> {
+    let table = table();
+    let child = table.processes.get(&pid)?;
+    if child.parent != table.current || pid == KERNEL_PID {
+        return None;
+    }
+
+    // Children run to completion when they are spawned
+    match child.state {
+        State::Zombie(code) => {
+            table.processes.remove(&pid);
+            Some(code)
+        },
+        _ => None,
+    }
+}
+
+/// Runs a program as a child of the current process and returns its
+/// exit code
+pub fn exec(path: &str, argv: &[&str], envp: &[&str]) -> Result<u64, loader::Error> {
+    let pid = spawn(path, argv, envp, Vec::new())?;
+    Ok(wait(pid).expect("A spawned process was not a child of its parent"))
+}
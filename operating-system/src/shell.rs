@@ -0,0 +1,249 @@
+//! A command shell on the kernel console for looking at and controlling
+//! the machine. It reads the keyboard once `/apps/init` has exited, and
+//! its output goes to the screen and the serial port without passing
+//! through the log.
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use aml::AmlValue;
+
+use crate::drivers;
+use crate::graphics;
+use crate::log_buffer;
+use crate::logging;
+use crate::process;
+use crate::system;
+use crate::task;
+use crate::task::keyboard::Keyboard;
+
+/// Shown before every command
+const PROMPT: &str = "> ";
+
+macro_rules! print {
+    ($($arg:tt)*) => (logging::print(format_args!($($arg)*)));
+}
+
+macro_rules! println {
+    () => (print!("\n"));
+    ($($arg:tt)*) => (print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Reads commands from the keyboard and runs them, forever
+pub async fn run() {
+    let mut keyboard = Keyboard::new();
+    let mut line = String::new();
+    print!("{}", PROMPT);
+    loop {
+        match keyboard.read_char().await {
+            '\n' => {
+                println!();
+                execute(&line.split_whitespace().collect::<Vec<_>>()).await;
+                line.clear();
+                print!("{}", PROMPT);
+            },
+            '\u{8}' => if line.pop().is_some() {
+                print!("\u{8}");
+            },
+            c if c.is_control() => (),
+            c => {
+                line.push(c);
+                print!("{}", c);
+            },
+        }
+    }
+}
+
+async fn execute(args: &[&str]) {
+    match args {
+        [] => (),
+        ["help"] => help(),
+        ["load", path] => load(path).await,
+        ["ps"] => ps(),
+        ["shutdown"] => shutdown(),
+        ["suspend"] => suspend(),
+        ["reboot"] => reboot(),
+        ["acpi", "ls"] => acpi_ls("\\"),
+        ["acpi", "ls", path] => acpi_ls(path),
+        ["acpi", "eval", path, args @ ..] => acpi_eval(path, args),
+        ["acpi", "devices"] => acpi_devices(),
+        ["lsdev"] => lsdev(),
+        ["power"] => power(),
+        ["lspci"] => lspci(),
+        ["devtree"] => devtree(),
+        ["log"] => log_filter(None),
+        ["log", spec] => log_filter(Some(*spec)),
+        ["dmesg"] => dmesg(),
+        [command, ..] => println!("Command not found: {}", command),
+    }
+}
+
+fn help() {
+    println!("load <path>      run a program until it exits");
+    println!("ps               list the processes");
+    println!("shutdown         turn the machine off");
+    println!("suspend          suspend to RAM until the machine is woken up");
+    println!("reboot           restart the machine");
+    println!("acpi ls [path]   list the acpi namespace below a path");
+    println!("acpi eval <path> evaluate an acpi object, or call a method with the arguments after it");
+    println!("acpi devices     list the devices in the acpi namespace");
+    println!("lsdev            list the acpi devices and their resources");
+    println!("power            show the thermal zones and batteries");
+    println!("lspci            list the PCI devices and their BARs");
+    println!("devtree          show every device and the driver bound to it");
+    println!("log [filter]     show or change the log filter, like debug,system::acpi_methods=trace");
+    println!("dmesg            show the most recent log records, including hidden ones");
+}
+
+async fn load(path: &str) {
+    // The program runs from the main loop rather than from this task, so
+    // that the kernel's tasks keep running while it waits for input
+    let path = path.to_string();
+    task::run_on_main_loop(move || {
+        // The program gets a terminal window of its own
+        let terminal = graphics::terminal::open(process::KERNEL_PID);
+        process::with_current(|p| p.terminal = terminal);
+        match process::exec(&path, &[path.as_str()], &[]) {
+            Ok(code) => println!("{} exited with {}", path, code),
+            Err(err) => println!("Could not load {}: {:?}", path, err),
+        }
+        process::with_current(|p| p.terminal = None);
+    }).await;
+}
+
+fn ps() {
+    for p in process::list() {
+        println!("{}\t{}\t{:?}\t{}", p.pid, p.parent, p.state, p.name);
+    }
+}
+
+fn shutdown() {
+    // Enter S5 (soft-off), which only returns if it failed
+    if let Err(err) = system::shutdown(5) {
+        println!("Could not shut down: {:?}", err);
+    }
+}
+
+fn suspend() {
+    // Enter S3, which returns once the system has woken up
+    match system::shutdown(3) {
+        Ok(()) => println!("Resumed from suspend"),
+        Err(err) => println!("Could not suspend: {:?}", err),
+    }
+}
+
+fn reboot() {
+    // Falls back to a triple fault, so this never returns
+    system::reboot();
+}
+
+fn acpi_ls(path: &str) {
+    use system::acpi_namespace::Node;
+    match system::acpi_namespace::list(path) {
+        Ok(nodes) => for node in nodes {
+            match node {
+                Node::Level(name, kind) => println!("{}\t{:?}", name, kind),
+                Node::Value(name, typ) => println!("{}\t{:?}", name, typ),
+            }
+        },
+        Err(err) => println!("Could not list {}: {:?}", path, err),
+    }
+}
+
+fn acpi_eval(path: &str, args: &[&str]) {
+    use system::acpi_namespace::{self, Pretty};
+
+    // Numbers (decimal or 0x prefixed hex) are passed as integers and
+    // anything else as strings
+    let args = args.iter().map(|arg| {
+        let number = match arg.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => arg.parse(),
+        };
+        match number {
+            Ok(number) => AmlValue::Integer(number),
+            Err(_) => AmlValue::String(String::from(*arg)),
+        }
+    }).collect();
+    match acpi_namespace::eval(path, args) {
+        Ok(value) => println!("{}", Pretty(&value)),
+        Err(err) => println!("Could not evaluate {}: {:?}", path, err),
+    }
+}
+
+fn acpi_devices() {
+    match system::acpi_namespace::devices() {
+        Ok(devices) => for device in devices {
+            println!("{}\thid={:?} cid={:?} uid={:?} adr={:x?}",
+                device.path, device.hid, device.cid, device.uid, device.adr);
+        },
+        Err(err) => println!("Could not list devices: {:?}", err),
+    }
+}
+
+fn lsdev() {
+    for device in system::acpi_devices::devices() {
+        println!("{}\t{:?}", device.path, device.hid);
+        for resource in device.resources.iter() {
+            println!("\t{:x?}", resource);
+        }
+    }
+}
+
+fn power() {
+    use system::acpi_power;
+    match acpi_power::thermal_zones() {
+        Ok(zones) => for zone in zones {
+            println!("{}\t{}", zone.path, zone.temperature);
+        },
+        Err(err) => println!("Could not read the thermal zones: {:?}", err),
+    }
+    match acpi_power::batteries() {
+        Ok(batteries) => for battery in batteries {
+            let state = if battery.charging {
+                "charging"
+            } else if battery.discharging {
+                "discharging"
+            } else {
+                "idle"
+            };
+            match battery.percent() {
+                Some(percent) => println!("{}\t{}% {}", battery.path, percent, state),
+                None => println!("{}\tunknown charge {}", battery.path, state),
+            }
+        },
+        Err(err) => println!("Could not read the batteries: {:?}", err),
+    }
+}
+
+fn lspci() {
+    for device in system::pci_express::devices() {
+        println!("{}\t{:04x}:{:04x}\tclass {:02x}:{:02x}:{:02x}",
+            device.address, device.vendor_id, device.device_id,
+            device.class, device.subclass, device.prog_if);
+        for bar in device.bars.iter().flatten() {
+            println!("\t{:x?}", bar);
+        }
+    }
+}
+
+fn devtree() {
+    print!("{}", drivers::DeviceTree);
+}
+
+fn log_filter(spec: Option<&str>) {
+    if let Some(spec) = spec {
+        if let Err(err) = logging::set_filter(spec) {
+            println!("Invalid log filter: {:?}", err);
+        }
+    }
+    println!("Log filter: {}", logging::filter());
+}
+
+fn dmesg() {
+    // Printed rather than logged, which would add them to the buffer again
+    for entry in log_buffer::entries() {
+        println!("{}", entry);
+    }
+}
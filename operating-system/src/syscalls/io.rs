@@ -0,0 +1,120 @@
+use core::fmt::Write;
+
+use spin::Mutex;
+
+use super::{user, would_block, Args, Error};
+use crate::filesystem::{self, OpenMode};
+use crate::graphics::terminal::Terminal;
+use crate::process;
+use crate::task::keyboard::{self, Key, Keyboard};
+use crate::ST;
+
+const STDIN: u64 = 0;
+const STDOUT: u64 = 1;
+const STDERR: u64 = 2;
+
+/// The modifier state of the keyboard as seen by user programs
+static mut KEYBOARD: Keyboard = Keyboard::new();
+
+/// write(fd, buf, len) -> bytes written
+pub fn write(args: Args) -> Result<u64, Error> {
+    let [fd, buf, len, ..] = args.0;
+    let buf = user::slice(buf, len as usize)?;
+
+    match fd {
+        STDOUT | STDERR => {
+            if let Some(terminal) = process::with_current(|p| p.terminal.clone()) {
+                terminal.lock().write(buf);
+                return Ok(buf.len() as u64);
+            }
+            let st = unsafe { ST.as_ref() }.ok_or(Error::Io)?;
+            let text = core::str::from_utf8(buf).map_err(|_| Error::InvalidArgument)?;
+            st.stdout().write_str(text).map_err(|_| Error::Io)?;
+            Ok(buf.len() as u64)
+        },
+        STDIN => Err(Error::BadDescriptor),
+        fd => process::with_current(|p| -> Result<u64, Error> {
+            let file = p.files.get(fd as usize)?;
+            Ok(file.write(buf)? as u64)
+        }),
+    }
+}
+
+/// read(fd, buf, len) -> bytes read
+pub fn read(args: Args) -> Result<u64, Error> {
+    let [fd, buf, len, ..] = args.0;
+    let buf = user::slice_mut(buf, len as usize)?;
+
+    match fd {
+        STDIN => {
+            let read = match process::with_current(|p| p.terminal.clone()) {
+                Some(terminal) => read_terminal(&terminal, buf),
+                None => read_keyboard(buf),
+            };
+            match read {
+                0 if !buf.is_empty() => would_block(),
+                read => Ok(read as u64),
+            }
+        },
+        STDOUT | STDERR => Err(Error::BadDescriptor),
+        fd => process::with_current(|p| -> Result<u64, Error> {
+            let file = p.files.get(fd as usize)?;
+            Ok(file.read(buf)? as u64)
+        }),
+    }
+}
+
+/// open(path, path_len, mode) -> fd
+pub fn open(args: Args) -> Result<u64, Error> {
+    let [path, path_len, mode, ..] = args.0;
+    let path = user::str(path, path_len as usize)?;
+    let mode = match mode {
+        0 => OpenMode::Read,
+        1 => OpenMode::ReadWrite,
+        2 => OpenMode::Create,
+        _ => return Err(Error::InvalidArgument),
+    };
+
+    let file = filesystem::open(path, mode)?;
+    Ok(process::with_current(|p| p.files.insert(file))? as u64)
+}
+
+/// close(fd) -> 0
+pub fn close(args: Args) -> Result<u64, Error> {
+    let [fd, ..] = args.0;
+    process::with_current(|p| p.files.close(fd as usize))?;
+    Ok(0)
+}
+
+/// Returns as many of the characters typed so far as fit in the buffer
+fn read_keyboard(buf: &mut [u8]) -> usize {
+    let mut read = 0;
+    let keyboard = unsafe { &mut KEYBOARD };
+
+    // Drain every scancode that has already arrived
+    while let Some(scancode) = keyboard::try_scancode() {
+        if let Some(c) = keyboard.decode(scancode) {
+            if read + c.len_utf8() > buf.len() {
+                return read;
+            }
+            read += c.encode_utf8(&mut buf[read..]).len();
+        }
+    }
+    read
+}
+
+/// Passes the characters typed so far to a terminal, then returns as
+/// much of its input as fits in the buffer
+fn read_terminal(terminal: &Mutex<Terminal>, buf: &mut [u8]) -> usize {
+    let keyboard = unsafe { &mut KEYBOARD };
+    while let Some(scancode) = keyboard::try_scancode() {
+        match keyboard.decode_key(scancode) {
+            // Shift+PgUp and Shift+PgDn page through the scrollback
+            Some(Key::PageUp) if keyboard.shift() => terminal.lock().scroll_page(1),
+            Some(Key::PageDown) if keyboard.shift() => terminal.lock().scroll_page(-1),
+            Some(Key::Char(c)) => terminal.lock().key(c),
+            _ => (),
+        }
+    }
+    terminal.lock().read(buf)
+}
@@ -0,0 +1,151 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::PageTableFlags;
+
+use super::memory::PROT_WRITE;
+use super::user::{self, USER_END};
+use super::{Args, Error};
+use crate::ipc::{self, Message, Object, SharedMemory, MAX_MESSAGE_HANDLES};
+use crate::process;
+
+/// channel_create(handles) -> 0
+pub fn channel_create(args: Args) -> Result<u64, Error> {
+    let [out, ..] = args.0;
+    let out = user::slice_mut(out, 16)?;
+
+    let (a, b) = ipc::channel();
+    let handles = process::with_current(|p| -> Result<[u64; 2], Error> {
+        let a = p.handles.insert(Object::Channel(a))?;
+        match p.handles.insert(Object::Channel(b)) {
+            Ok(b) => Ok([a, b]),
+            Err(err) => {
+                p.handles.remove(a).ok();
+                Err(err.into())
+            },
+        }
+    })?;
+
+    out[..8].copy_from_slice(&handles[0].to_le_bytes());
+    out[8..].copy_from_slice(&handles[1].to_le_bytes());
+    Ok(0)
+}
+
+/// channel_send(handle, buf, len, handles, handles_len) -> 0
+pub fn channel_send(args: Args) -> Result<u64, Error> {
+    let [handle, buf, len, handles, handles_len, ..] = args.0;
+    if handles_len as usize > MAX_MESSAGE_HANDLES {
+        return Err(Error::TooLarge);
+    }
+    let data = user::slice(buf, len as usize)?.to_vec();
+    let handles = read_handles(handles, handles_len as usize)?;
+
+    process::with_current(|p| -> Result<u64, Error> {
+        // A channel can not be sent over itself, and neither can its
+        // other end, which would then be queued to itself and never close
+        if handles.iter().any(|&h| h == handle || p.handles.are_peers(handle, h)) {
+            return Err(Error::InvalidArgument);
+        }
+
+        // Only move the handles once the message is sure to be sent
+        p.handles.channel(handle)?.can_send(data.len(), handles.len())?;
+        let objects = p.handles.remove_all(&handles)?;
+        let message = Message { data, objects };
+        p.handles.channel(handle)?.send(message)?;
+        Ok(0)
+    })
+}
+
+/// channel_recv(handle, buf, len, handles, handles_len) -> bytes | handles << 32
+pub fn channel_recv(args: Args) -> Result<u64, Error> {
+    let [handle, buf, len, handles, handles_len, ..] = args.0;
+    let handles_len = handles_len.min(MAX_MESSAGE_HANDLES as u64) as usize;
+    let buf = user::slice_mut(buf, len as usize)?;
+    let handles_out = user::slice_mut(handles, handles_len * 8)?;
+
+    process::with_current(|p| -> Result<u64, Error> {
+        // Leave the message queued if it does not fit, or if the caller
+        // can not hold a handle to every object in it
+        let (size, count) = p.handles.channel(handle)?.peek()?;
+        if size > buf.len() || count > handles_len {
+            return Err(Error::TooLarge);
+        }
+        if !p.handles.has_room(count) {
+            return Err(ipc::Error::TooManyHandles.into());
+        }
+        let message = p.handles.channel(handle)?.recv()?;
+        buf[..size].copy_from_slice(&message.data);
+
+        // Give the caller a handle to every object in the message
+        let received = message.objects.into_iter()
+            .map(|object| p.handles.insert(object))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (i, handle) in received.iter().enumerate() {
+            handles_out[i * 8..i * 8 + 8].copy_from_slice(&handle.to_le_bytes());
+        }
+
+        Ok(size as u64 | (count as u64) << 32)
+    })
+}
+
+/// handle_close(handle) -> 0
+pub fn handle_close(args: Args) -> Result<u64, Error> {
+    let [handle, ..] = args.0;
+    process::with_current(|p| p.handles.remove(handle))?;
+    Ok(0)
+}
+
+/// shm_create(len) -> handle
+pub fn shm_create(args: Args) -> Result<u64, Error> {
+    let [len, ..] = args.0;
+    let region = SharedMemory::new(len as usize)?;
+    let object = Object::SharedMemory(Arc::new(region));
+    Ok(process::with_current(|p| p.handles.insert(object))?)
+}
+
+/// shm_map(handle, addr, prot) -> addr
+pub fn shm_map(args: Args) -> Result<u64, Error> {
+    let [handle, addr, prot, ..] = args.0;
+    if prot & !PROT_WRITE != 0 || addr % 4096 != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    // Shared memory holds data, never code
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    process::with_current(|p| -> Result<u64, Error> {
+        let region = p.handles.shared_memory(handle)?;
+        let len = region.len() as u64;
+
+        // Use the requested address if there is one
+        let start = if addr != 0 { addr } else { p.mmap_next };
+        let end = start.checked_add(len).ok_or(Error::InvalidArgument)?;
+        if end > USER_END {
+            return Err(Error::InvalidArgument);
+        }
+
+        p.map_shared(region, VirtAddr::new(start), flags)?;
+        if addr == 0 {
+            p.mmap_next = end;
+        }
+        Ok(start)
+    })
+}
+
+/// Reads an array of handles from the caller's memory
+fn read_handles(addr: u64, count: usize) -> Result<Vec<u64>, Error> {
+    let bytes = user::slice(addr, count * 8)?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .collect())
+}
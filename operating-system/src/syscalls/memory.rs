@@ -0,0 +1,66 @@
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+
+use super::user::USER_END;
+use super::{Args, Error};
+use crate::process;
+
+pub const PROT_WRITE: u64 = 1 << 0;
+const PROT_EXEC: u64 = 1 << 1;
+
+/// mmap(addr, len, prot) -> addr
+pub fn mmap(args: Args) -> Result<u64, Error> {
+    let [addr, len, prot, ..] = args.0;
+    if len == 0 || prot & !(PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    // Round the length up to a whole number of pages
+    let len = len.checked_add(4095).ok_or(Error::InvalidArgument)? & !4095;
+
+    // Use the requested address if there is one
+    let start = if addr != 0 {
+        if addr % 4096 != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        addr
+    } else {
+        process::with_current(|p| p.mmap_next)
+    };
+    let end = start.checked_add(len).ok_or(Error::InvalidArgument)?;
+    if end > USER_END {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    // Map every page of the region into the caller's address space
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    process::with_current(|p| -> Result<u64, Error> {
+        // map_user_page would merge with a page that is already mapped
+        if Page::range_inclusive(first, last).any(|page| p.is_mapped(page)) {
+            return Err(Error::InvalidArgument);
+        }
+        for (i, page) in Page::range_inclusive(first, last).enumerate() {
+            if let Err(err) = p.map_user_page(page, flags) {
+                // Leave nothing of a failed mapping behind
+                for page in Page::range_inclusive(first, last).take(i) {
+                    p.unmap_user_page(page);
+                }
+                return Err(err.into());
+            }
+        }
+        if addr == 0 {
+            p.mmap_next = end;
+        }
+        Ok(start)
+    })
+}
@@ -0,0 +1,287 @@
+//! The system call interface between user programs and the kernel.
+//!
+//! # Calling convention
+//!
+//! System calls are made with the SYSCALL instruction (or `int 0x80` as
+//! a fallback, which uses the same registers):
+//!
+//! | register | use                          |
+//! |----------|------------------------------|
+//! | rax      | system call number           |
+//! | rdi      | argument 0                   |
+//! | rsi      | argument 1                   |
+//! | rdx      | argument 2                   |
+//! | r10      | argument 3                   |
+//! | r8       | argument 4                   |
+//! | r9       | argument 5                   |
+//!
+//! The result is returned in rax. Values from `-4095` to `-1` (when rax
+//! is read as an i64) are negated `Error` codes, every other value is a
+//! successful result. rcx and r11 are clobbered by SYSCALL, every other
+//! register is preserved.
+//!
+//! Pointers passed to the kernel are checked against the caller's
+//! address space: they must lie in `0x4000_0000_0000..0x8000_0000_0000`,
+//! and every page they cover must be mapped and user accessible (and
+//! writable if the kernel writes to it), otherwise the call fails with
+//! `Error::BadAddress`.
+//!
+//! # System calls
+//!
+//! | nr | name           | arguments                         | returns     |
+//! |----|----------------|-----------------------------------|-------------|
+//! | 0  | exit           | code                              | never       |
+//! | 1  | write          | fd, buf, len                      | bytes       |
+//! | 2  | read           | fd, buf, len                      | bytes       |
+//! | 3  | open           | path, path_len, mode              | fd          |
+//! | 4  | close          | fd                                | 0           |
+//! | 5  | mmap           | addr, len, prot                   | addr        |
+//! | 6  | spawn          | path, path_len, handle            | pid         |
+//! | 7  | wait           | pid                               | exit code   |
+//! | 8  | sleep          | deadline in ms since boot         | 0           |
+//! | 9  | time           |                                   | ms uptime   |
+//! | 10 | window_create  | width, height, x, y               | window id   |
+//! | 11 | window_destroy | window id                         | 0           |
+//! | 12 | window_blit    | window id, pixels, len            | 0           |
+//! | 13 | channel_create | handles                           | 0           |
+//! | 14 | channel_send   | handle, buf, len, handles, count  | 0           |
+//! | 15 | channel_recv   | handle, buf, len, handles, count  | see below   |
+//! | 16 | handle_close   | handle                            | 0           |
+//! | 17 | shm_create     | len                               | handle      |
+//! | 18 | shm_map        | handle, addr, prot                | addr        |
+//! | 19 | window_buffer  | window id                         | handle      |
+//! | 20 | window_present | window id                         | 0           |
+//!
+//! File descriptors 0, 1 and 2 are the keyboard, the console and the
+//! console. `open` modes are 0 (read), 1 (read/write) and 2 (create).
+//! `mmap` protection bits are 1 (write) and 2 (execute); pages are
+//! always readable, and a range that overlaps a mapping fails with
+//! `InvalidArgument`. `window_blit` takes `width * height` pixels encoded
+//! as `0x00RRGGBB` u32s.
+//!
+//! Files, mappings, handles and windows belong to the calling process
+//! and are released when it exits. There is no scheduler yet, so
+//! `spawn` runs the child until it exits before returning; `wait` then
+//! collects the child's exit code. A non-zero `handle` passed to `spawn`
+//! is moved to the child, where it is handle 1.
+//!
+//! For the same reason nothing waits inside the kernel. `read` from the
+//! keyboard fails with `WouldBlock` until a key has been typed, and
+//! `sleep` until its deadline has passed, so the caller has to try
+//! again. Before failing they let the kernel's tasks run and wait for
+//! the next interrupt, so trying again right away does not spin. This
+//! is why programs run from the kernel's main loop and never from
+//! inside a task, where the other tasks could not be polled.
+//!
+//! # Inter-process communication
+//!
+//! Channels and shared memory regions are reached through handles,
+//! which start at 1. `channel_create` writes the handles of both ends
+//! of a new channel to `handles` as two u64s. Messages carry up to 64
+//! KiB of data and up to 8 handles (an array of u64s), which are moved
+//! from the sender to the receiver. Sending either end of a channel
+//! over that channel fails with `InvalidArgument`. Neither call blocks:
+//! `channel_send` fails with `WouldBlock` when the channel is full and
+//! `channel_recv` when it is empty, and both fail with `PeerClosed`
+//! once the other end is closed and every message has been received.
+//! `channel_recv` returns the size of the message in the low 32 bits
+//! and the number of handles in the high 32 bits; a message that does
+//! not fit is left in the channel and the call fails with `TooLarge`.
+//!
+//! `shm_map` maps the whole region at `addr` (or at a free address if
+//! `addr` is 0) and only accepts the write protection bit. Like `mmap`,
+//! it fails with `InvalidArgument` if the range overlaps a mapping.
+//! The pixels of a window live in a shared memory region as
+//! `0x00RRGGBB` u32s. `window_buffer` returns a handle to that region,
+//! and after drawing to it `window_present` puts the changes on the
+//! screen.
+
+mod io;
+mod ipc;
+mod memory;
+mod process;
+mod time;
+pub mod user;
+mod window;
+
+use x86_64::instructions::interrupts;
+
+use crate::filesystem;
+use crate::loader;
+use crate::memory::address_space::MapError;
+use crate::system::syscall::SyscallFrame;
+use crate::task;
+
+pub mod number {
+    pub const EXIT: u64 = 0;
+    pub const WRITE: u64 = 1;
+    pub const READ: u64 = 2;
+    pub const OPEN: u64 = 3;
+    pub const CLOSE: u64 = 4;
+    pub const MMAP: u64 = 5;
+    pub const SPAWN: u64 = 6;
+    pub const WAIT: u64 = 7;
+    pub const SLEEP: u64 = 8;
+    pub const TIME: u64 = 9;
+    pub const WINDOW_CREATE: u64 = 10;
+    pub const WINDOW_DESTROY: u64 = 11;
+    pub const WINDOW_BLIT: u64 = 12;
+    pub const CHANNEL_CREATE: u64 = 13;
+    pub const CHANNEL_SEND: u64 = 14;
+    pub const CHANNEL_RECV: u64 = 15;
+    pub const HANDLE_CLOSE: u64 = 16;
+    pub const SHM_CREATE: u64 = 17;
+    pub const SHM_MAP: u64 = 18;
+    pub const WINDOW_BUFFER: u64 = 19;
+    pub const WINDOW_PRESENT: u64 = 20;
+}
+
+/// The errors that a system call can return
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u64)]
+pub enum Error {
+    /// The system call number does not exist
+    NoSys = 1,
+
+    /// A pointer argument is not mapped in the caller's address space
+    BadAddress = 2,
+
+    /// An argument is out of range
+    InvalidArgument = 3,
+
+    /// The file descriptor is not open
+    BadDescriptor = 4,
+
+    /// The file, process or window does not exist
+    NotFound = 5,
+
+    /// There is not enough memory to complete the call
+    NoMemory = 6,
+
+    /// The caller has too many files open
+    TooManyFiles = 7,
+
+    /// A device failed to complete the call
+    Io = 8,
+
+    /// The caller has no child process to wait for
+    NoChild = 9,
+
+    /// The file is not an executable that can be run
+    NotExecutable = 10,
+
+    /// The channel is empty, or full when sending, no key has been
+    /// typed, or the deadline of a sleep has not passed
+    WouldBlock = 11,
+
+    /// The other end of the channel has been closed
+    PeerClosed = 12,
+
+    /// A message or shared memory region is too large
+    TooLarge = 13,
+}
+impl From<filesystem::Error> for Error {
+    fn from(orig: filesystem::Error) -> Self {
+        match orig {
+            filesystem::Error::NotFound | filesystem::Error::NotAFile => {
+                Self::NotFound
+            },
+            filesystem::Error::BadDescriptor => Self::BadDescriptor,
+            filesystem::Error::TooManyFiles => Self::TooManyFiles,
+            filesystem::Error::NoBootVolume | filesystem::Error::Io => Self::Io,
+        }
+    }
+}
+
+impl From<loader::Error> for Error {
+    fn from(orig: loader::Error) -> Self {
+        match orig {
+            loader::Error::NotElf
+            | loader::Error::InvalidElf
+            | loader::Error::Unsupported => Self::NotExecutable,
+            loader::Error::NoMemory => Self::NoMemory,
+            loader::Error::ArgumentsTooLong => Self::InvalidArgument,
+            loader::Error::Filesystem(err) => err.into(),
+        }
+    }
+}
+
+impl From<MapError> for Error {
+    fn from(orig: MapError) -> Self {
+        match orig {
+            MapError::OutsideUserSpace | MapError::AlreadyMapped => Self::InvalidArgument,
+            MapError::NoMemory => Self::NoMemory,
+        }
+    }
+}
+
+impl From<crate::ipc::Error> for Error {
+    fn from(orig: crate::ipc::Error) -> Self {
+        use crate::ipc::Error as IpcError;
+        match orig {
+            IpcError::BadHandle => Self::BadDescriptor,
+            IpcError::WouldBlock => Self::WouldBlock,
+            IpcError::PeerClosed => Self::PeerClosed,
+            IpcError::TooLarge => Self::TooLarge,
+            IpcError::TooManyHandles => Self::TooManyFiles,
+            IpcError::NoMemory => Self::NoMemory,
+        }
+    }
+}
+
+/// Lets the kernel's tasks run and waits for the next interrupt, then
+/// fails with `WouldBlock`. Used by calls that have nothing to return
+/// yet, since there is no scheduler to park the caller on.
+fn would_block<T>() -> Result<T, Error> {
+    task::run_ready();
+    interrupts::enable_and_hlt();
+    interrupts::disable();
+    Err(Error::WouldBlock)
+}
+
+/// The arguments of a system call
+#[derive(Debug, Clone, Copy)]
+pub struct Args(pub [u64; 6]);
+
+type Handler = fn(Args) -> Result<u64, Error>;
+
+/// The handlers of each system call, indexed by system call number
+const TABLE: [Handler; 21] = [
+    process::exit,       // EXIT
+    io::write,           // WRITE
+    io::read,            // READ
+    io::open,            // OPEN
+    io::close,           // CLOSE
+    memory::mmap,        // MMAP
+    process::spawn,      // SPAWN
+    process::wait,       // WAIT
+    time::sleep,         // SLEEP
+    time::time,          // TIME
+    window::create,      // WINDOW_CREATE
+    window::destroy,     // WINDOW_DESTROY
+    window::blit,        // WINDOW_BLIT
+    ipc::channel_create, // CHANNEL_CREATE
+    ipc::channel_send,   // CHANNEL_SEND
+    ipc::channel_recv,   // CHANNEL_RECV
+    ipc::handle_close,   // HANDLE_CLOSE
+    ipc::shm_create,     // SHM_CREATE
+    ipc::shm_map,        // SHM_MAP
+    window::buffer,      // WINDOW_BUFFER
+    window::present,     // WINDOW_PRESENT
+];
+
+/// Runs the system call described by the registers in a frame and
+/// returns the value to be placed in rax
+pub fn dispatch(frame: &SyscallFrame) -> u64 {
+    let args = Args([frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9]);
+
+    let result = match TABLE.get(frame.rax as usize) {
+        Some(handler) => handler(args),
+        None => Err(Error::NoSys),
+    };
+
+    match result {
+        Ok(value) => value,
+        Err(err) => (-(err as i64)) as u64,
+    }
+}
@@ -0,0 +1,34 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{user, Args, Error};
+use crate::process;
+use crate::system::usermode;
+
+/// exit(code) -> never returns
+pub fn exit(args: Args) -> Result<u64, Error> {
+    let [code, ..] = args.0;
+    usermode::exit(code)
+}
+
+/// spawn(path, path_len, handle) -> pid
+pub fn spawn(args: Args) -> Result<u64, Error> {
+    let [path, path_len, handle, ..] = args.0;
+
+    // The caller's memory is not mapped while the child runs
+    let path = String::from(user::str(path, path_len as usize)?);
+
+    // The handle is moved to the child, and closed if it can not start
+    let mut objects = Vec::new();
+    if handle != 0 {
+        objects.push(process::with_current(|p| p.handles.remove(handle))?);
+    }
+    Ok(process::spawn(&path, &[path.as_str()], &[], objects)?)
+}
+
+/// wait(pid) -> exit code
+pub fn wait(args: Args) -> Result<u64, Error> {
+    let [pid, ..] = args.0;
+    process::wait(pid).ok_or(Error::NoChild)
+}
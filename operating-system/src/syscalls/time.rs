@@ -0,0 +1,14 @@
+use super::{would_block, Args, Error};
+use crate::task::timer;
+
+/// sleep(deadline) -> 0
+pub fn sleep(args: Args) -> Result<u64, Error> {
+    let [deadline, ..] = args.0;
+    if timer::uptime_ms() < deadline {
+        return would_block();
+    }
+    Ok(0)
+}
+
+/// time() -> milliseconds since boot
+pub fn time(_args: Args) -> Result<u64, Error> { Ok(timer::uptime_ms()) }
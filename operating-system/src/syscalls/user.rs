@@ -0,0 +1,69 @@
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+
+use super::Error;
+use crate::memory::address_space::USER_START;
+use crate::memory::paging;
+
+/// The end of the lower half of the address space. User pointers must
+/// lie below this address.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// Checks that a range of memory is mapped as user accessible (and
+/// writable if `write` is set) in the caller's address space. The range
+/// has to lie in `USER_START..USER_END`, where the kernel sets up every
+/// page table level itself, so checking the flags of the last level is
+/// enough.
+pub fn check(addr: u64, len: usize, write: bool) -> Result<(), Error> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    // The range must not wrap or leave the user part of the lower half
+    let end = addr.checked_add(len as u64).ok_or(Error::BadAddress)?;
+    if addr < USER_START || end > USER_END {
+        return Err(Error::BadAddress);
+    }
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+
+    // The caller's page table is the active one during a system call
+    let table = unsafe { paging::active_page_table() };
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        match table.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } if flags.contains(required) => (),
+            _ => return Err(Error::BadAddress),
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets a slice of user memory that the kernel will read from
+pub fn slice<'a>(addr: u64, len: usize) -> Result<&'a [u8], Error> {
+    check(addr, len, false)?;
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(addr as *const u8, len) })
+}
+
+/// Gets a slice of user memory that the kernel will write to
+pub fn slice_mut<'a>(addr: u64, len: usize) -> Result<&'a mut [u8], Error> {
+    check(addr, len, true)?;
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// Gets a UTF-8 string from user memory
+pub fn str<'a>(addr: u64, len: usize) -> Result<&'a str, Error> {
+    core::str::from_utf8(slice(addr, len)?).map_err(|_| Error::InvalidArgument)
+}
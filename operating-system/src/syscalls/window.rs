@@ -0,0 +1,97 @@
+use super::{user, Args, Error};
+use crate::graphics::{
+    self, Buffer, BufferTrait, Color, Location, PixelFormat, Size, Window, WindowManager,
+};
+use crate::ipc::Object;
+use crate::process;
+
+/// window_create(width, height, x, y) -> window id
+pub fn create(args: Args) -> Result<u64, Error> {
+    let [width, height, x, y, ..] = args.0;
+    if width == 0 || height == 0 || width > 0x4000 || height > 0x4000 {
+        return Err(Error::InvalidArgument);
+    }
+
+    // The buffer is shared with the process, which sees each pixel as
+    // a 0x00RRGGBB u32
+    let size = Size { width: width as usize, height: height as usize };
+    let buffer = Buffer::shared(size, PixelFormat::Bgr)?;
+
+    let id = graphics::with_display(|screen, wm| {
+        let id = wm.add_window(
+            process::current(),
+            buffer,
+            Location { x: x as i64 as isize, y: y as i64 as isize },
+        );
+        wm.draw(screen);
+        id
+    }).ok_or(Error::Io)?;
+    Ok(id as u64)
+}
+
+/// window_destroy(window id) -> 0
+pub fn destroy(args: Args) -> Result<u64, Error> {
+    let [id, ..] = args.0;
+    graphics::with_display(|screen, wm| -> Result<u64, Error> {
+        owned_window(wm, id)?;
+        wm.close_window(id as usize);
+        wm.draw(screen);
+        Ok(0)
+    }).ok_or(Error::Io)?
+}
+
+/// window_blit(window id, pixels, len) -> 0
+pub fn blit(args: Args) -> Result<u64, Error> {
+    let [id, pixels, len, ..] = args.0;
+    graphics::with_display(|screen, wm| -> Result<u64, Error> {
+        let window = owned_window(wm, id)?;
+        let buffer = window.buffer();
+
+        // The caller must provide exactly one u32 per pixel
+        let (width, height) = buffer.size().tuple();
+        if len as usize != width * height * 4 {
+            return Err(Error::InvalidArgument);
+        }
+        let src = user::slice(pixels, len as usize)?;
+
+        // Convert each 0x00RRGGBB pixel into the format of the buffer
+        let dst = buffer.ptr();
+        for (i, rgb) in src.chunks_exact(4).enumerate() {
+            let color = Color::new(rgb[2], rgb[1], rgb[0]);
+            unsafe { *dst.add(i) = buffer.new_pixel(color) };
+        }
+
+        wm.draw(screen);
+        Ok(0)
+    }).ok_or(Error::Io)?
+}
+
+/// window_buffer(window id) -> handle
+pub fn buffer(args: Args) -> Result<u64, Error> {
+    let [id, ..] = args.0;
+    let region = graphics::with_display(|_, wm| {
+        owned_window(wm, id)?
+            .buffer()
+            .shared_memory()
+            .cloned()
+            .ok_or(Error::NotFound)
+    }).ok_or(Error::Io)??;
+    Ok(process::with_current(|p| p.handles.insert(Object::SharedMemory(region)))?)
+}
+
+/// window_present(window id) -> 0
+pub fn present(args: Args) -> Result<u64, Error> {
+    let [id, ..] = args.0;
+    graphics::with_display(|screen, wm| -> Result<u64, Error> {
+        owned_window(wm, id)?;
+        wm.draw(screen);
+        Ok(0)
+    }).ok_or(Error::Io)?
+}
+
+/// Gets a window if it is owned by the calling process
+fn owned_window(wm: &mut WindowManager, id: u64) -> Result<&mut Window, Error> {
+    wm.window(id as usize)
+        .filter(|window| window.pid() == process::current())
+        .ok_or(Error::NotFound)
+}
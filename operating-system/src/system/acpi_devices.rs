@@ -0,0 +1,93 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use aml::{AmlName, AmlValue};
+use log::debug;
+
+use super::acpi_namespace::{self, DeviceInfo};
+use super::acpi_resources::{self, Resource};
+use super::{acpi_methods, Error};
+
+/// The \_STA bits of a device that is present and working
+const STA_PRESENT: u64 = 1 << 0;
+const STA_FUNCTIONING: u64 = 1 << 3;
+
+/// The \_STA value of devices that do not have one
+const STA_DEFAULT: u64 = 0xF;
+
+/// A device under \_SB that the firmware reported as present, along
+/// with the resources it is using
+#[derive(Debug)]
+pub struct Device {
+    pub path: AmlName,
+    pub hid: Option<String>,
+    pub cid: Vec<String>,
+    pub uid: Option<String>,
+    pub adr: Option<u64>,
+    pub status: u64,
+    pub resources: Vec<Resource>,
+}
+impl Device {
+    /// Checks whether the hardware id or one of the compatible ids is `id`
+    pub fn matches(&self, id: &str) -> bool {
+        self.hid.as_deref() == Some(id) || self.cid.iter().any(|cid| cid == id)
+    }
+}
+
+static mut DEVICES: Vec<Device> = Vec::new();
+
+/// Finds the present devices under \_SB, evaluates their \_CRS and adds
+/// them to the device registry
+pub fn enumerate_devices() -> Result<(), Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+    let system_bus = AmlName::from_str("\\_SB")?;
+
+    let mut devices = Vec::new();
+    for info in acpi_namespace::devices()? {
+        if !is_below(&info.path, &system_bus) {
+            continue;
+        }
+
+        let status = match acpi_namespace::optional(aml_ctx, &info.path, "_STA")? {
+            Some(status) => status.as_integer(aml_ctx)?,
+            None => STA_DEFAULT,
+        };
+        if status & STA_PRESENT == 0 || status & STA_FUNCTIONING == 0 {
+            debug!("{} is not present or not working", info.path);
+            continue;
+        }
+
+        let resources = match acpi_namespace::optional(aml_ctx, &info.path, "_CRS")? {
+            Some(AmlValue::Buffer(template)) => acpi_resources::decode(&template)
+                .unwrap_or_else(|err| {
+                    debug!("Could not decode the resources of {}: {:?}", info.path, err);
+                    Vec::new()
+                }),
+            _ => Vec::new(),
+        };
+
+        let DeviceInfo { path, hid, cid, uid, adr } = info;
+        debug!("Found {} ({:?}) with {} resources", path, hid, resources.len());
+        devices.push(Device { path, hid, cid, uid, adr, status, resources });
+    }
+
+    unsafe { DEVICES = devices };
+    Ok(())
+}
+
+/// Gets every device in the registry
+pub fn devices() -> &'static [Device] {
+    unsafe { &DEVICES }
+}
+
+/// Finds the devices whose hardware id or compatible ids match `id`, so
+/// that drivers can bind to them
+pub fn find_devices(id: &str) -> impl Iterator<Item = &'static Device> + '_ {
+    devices().iter().filter(move |device| device.matches(id))
+}
+
+/// Checks whether `path` is inside the scope `scope`
+fn is_below(path: &AmlName, scope: &AmlName) -> bool {
+    path.as_string().starts_with(&alloc::format!("{}.", scope.as_string()))
+}
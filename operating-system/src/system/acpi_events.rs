@@ -0,0 +1,347 @@
+extern crate alloc;
+use alloc::format;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use acpi::platform::address::GenericAddress;
+use aml::{AmlError, AmlName};
+use aml::value::Args;
+use log::debug;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::port::PortWrite;
+
+use super::{acpi_methods, acpi_registers, apic, Error};
+use crate::task::AtomicWaker;
+
+/// Set in the PM1 control registers once the firmware has handed the
+/// acpi hardware over to the os
+const SCI_EN: u64 = 1;
+
+/// The fixed events in the PM1 status and enable registers
+const PWRBTN_STS: u16 = 1 << 8;
+const SLPBTN_STS: u16 = 1 << 9;
+const RTC_STS: u16 = 1 << 10;
+const FIXED_EVENTS: u16 = PWRBTN_STS | SLPBTN_STS | RTC_STS;
+
+/// How many times SCI_EN is polled after asking the firmware to switch
+/// to acpi mode
+const ACPI_ENABLE_TRIES: usize = 1_000_000;
+
+/// The largest number of general purpose events in the two GPE blocks
+const MAX_GPES: usize = 256;
+
+/// An event signalled through the SCI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    PowerButton,
+    SleepButton,
+    RtcAlarm,
+
+    /// A general purpose event. It stays disabled until it has been
+    /// passed to `handle_gpe`.
+    Gpe(u16),
+}
+
+/// A general purpose event block and the number of its first event
+#[derive(Clone, Copy)]
+struct GpeBlock {
+    block: GenericAddress,
+    base: u16,
+}
+impl GpeBlock {
+    /// The number of status (and enable) bytes in the block
+    fn len(&self) -> usize { self.block.bit_width as usize / 16 }
+
+    fn status(&self, index: usize) -> GenericAddress {
+        acpi_registers::byte(&acpi_registers::status_register(&self.block), index)
+    }
+
+    fn enable(&self, index: usize) -> GenericAddress {
+        acpi_registers::byte(&acpi_registers::enable_register(&self.block), index)
+    }
+
+    /// Gets the register byte and bit of an event in this block
+    fn locate(&self, gpe: u16) -> Option<(usize, u8)> {
+        let offset = gpe.checked_sub(self.base)? as usize;
+        if offset >= self.len() * 8 {
+            return None;
+        }
+        Some((offset / 8, 1 << (offset % 8)))
+    }
+}
+
+/// Events that were signalled but not yet taken by `next_event`. The
+/// interrupt handler only ever adds bits, and general purpose events
+/// stay disabled while they are pending.
+struct Pending {
+    fixed: u16,
+    gpes: [u64; MAX_GPES / 64],
+}
+impl Pending {
+    const fn new() -> Pending {
+        Pending { fixed: 0, gpes: [0; MAX_GPES / 64] }
+    }
+
+    fn take(&mut self) -> Option<Event> {
+        for &(bit, event) in [
+            (PWRBTN_STS, Event::PowerButton),
+            (SLPBTN_STS, Event::SleepButton),
+            (RTC_STS, Event::RtcAlarm),
+        ].iter() {
+            if self.fixed & bit != 0 {
+                self.fixed &= !bit;
+                return Some(event);
+            }
+        }
+
+        for (i, word) in self.gpes.iter_mut().enumerate() {
+            if *word != 0 {
+                let bit = word.trailing_zeros();
+                *word &= !(1 << bit);
+                return Some(Event::Gpe((i * 64) as u16 + bit as u16));
+            }
+        }
+        None
+    }
+}
+
+static PENDING: Mutex<Pending> = Mutex::new(Pending::new());
+static WAKER: AtomicWaker = AtomicWaker::new();
+static mut GPE_BLOCKS: Vec<GpeBlock> = Vec::new();
+
+/// Switches the firmware to acpi mode, enables the power button, sleep
+/// button and RTC alarm events and every general purpose event that
+/// has a handler method, and routes the SCI through the I/O apic
+pub fn init_events() -> Result<(), Error> {
+    let fadt = acpi_methods::fadt()?;
+
+    debug!("Switching to ACPI mode");
+    enable_acpi_mode()?;
+
+    debug!("Finding the GPE blocks");
+    let mut blocks = Vec::new();
+    if let Some(block) = fadt.gpe0_block()? {
+        blocks.push(GpeBlock { block, base: 0 });
+    }
+    if let Some(block) = fadt.gpe1_block()? {
+        blocks.push(GpeBlock { block, base: fadt.gpe1_base as u16 });
+    }
+
+    // Start with every event disabled and acknowledged
+    debug!("Disabling every ACPI event");
+    for_each_pm1(|status, enable| {
+        acpi_registers::write(&enable, 0)?;
+        acpi_registers::write(&status, 0xFFFF)
+    })?;
+    for block in blocks.iter() {
+        for i in 0..block.len() {
+            acpi_registers::write(&block.enable(i), 0)?;
+            acpi_registers::write(&block.status(i), 0xFF)?;
+        }
+    }
+    unsafe { GPE_BLOCKS = blocks };
+
+    let sci = fadt.sci_interrupt;
+    debug!("Routing the SCI (irq {})", sci);
+    let pin = apic::enable_sci(sci as u8);
+    super::interrupts::set_irq_handler(pin, handle_sci);
+
+    debug!("Enabling the fixed events");
+    for_each_pm1(|_, enable| acpi_registers::write(&enable, FIXED_EVENTS as u64))?;
+
+    debug!("Enabling the general purpose events");
+    for block in unsafe { GPE_BLOCKS.iter() } {
+        for gpe in block.base..block.base + block.len() as u16 * 8 {
+            if gpe_method(gpe)?.is_some() {
+                debug!("Enabling GPE {:#x}", gpe);
+                enable_gpe(gpe)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the next event signalled through the SCI
+pub fn next_event() -> NextEvent { NextEvent }
+
+/// Takes the next event signalled through the SCI without waiting
+pub fn try_event() -> Option<Event> {
+    interrupts::without_interrupts(|| PENDING.lock().take())
+}
+
+pub struct NextEvent;
+impl Future for NextEvent {
+    type Output = Event;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Event> {
+        if let Some(event) = try_event() {
+            return Poll::Ready(event);
+        }
+
+        WAKER.register(cx.waker());
+        match try_event() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Runs the \_Lxx or \_Exx method of a general purpose event taken from
+/// `next_event`, then enables the event again
+pub fn handle_gpe(gpe: u16) -> Result<(), Error> {
+    let (name, level) = match gpe_method(gpe)? {
+        Some(method) => method,
+        None => return Ok(()),
+    };
+
+    // Edge triggered events are cleared before running the method so
+    // that an edge during the method is not lost. Level triggered events
+    // are only cleared after the method has dealt with their cause.
+    if !level {
+        clear_gpe(gpe)?;
+    }
+    debug!("Running {}", name);
+    acpi_methods::aml_context()?.invoke_method(&name, Args::default())?;
+    if level {
+        clear_gpe(gpe)?;
+    }
+    enable_gpe(gpe)
+}
+
+/// Called by the interrupt handler of the SCI's I/O apic pin. Fixed
+/// events are acknowledged right away, while general purpose events are
+/// disabled until their method has run, since that can not happen in
+/// interrupt context.
+fn handle_sci() {
+    let mut pending = match PENDING.try_lock() {
+        Some(pending) => pending,
+        None => return,
+    };
+    let mut signalled = false;
+
+    let _ = for_each_pm1(|status, enable| {
+        let events = acpi_registers::read(&status)? as u16
+            & acpi_registers::read(&enable)? as u16
+            & FIXED_EVENTS;
+        if events != 0 {
+            acpi_registers::write(&status, events as u64)?;
+            pending.fixed |= events;
+            signalled = true;
+        }
+        Ok(())
+    });
+
+    for block in unsafe { GPE_BLOCKS.iter() } {
+        for i in 0..block.len() {
+            let (status, enable) = (block.status(i), block.enable(i));
+            let (active, enabled) = match (acpi_registers::read(&status), acpi_registers::read(&enable)) {
+                (Ok(active), Ok(enabled)) => (active, enabled),
+                _ => continue,
+            };
+            let events = active & enabled;
+            if events == 0 {
+                continue;
+            }
+
+            let _ = acpi_registers::write(&enable, enabled & !events);
+            for bit in (0..8).filter(|bit| events & (1 << bit) != 0) {
+                let gpe = block.base as usize + i * 8 + bit;
+                if gpe < MAX_GPES {
+                    pending.gpes[gpe / 64] |= 1 << (gpe % 64);
+                }
+            }
+            signalled = true;
+        }
+    }
+
+    drop(pending);
+    if signalled {
+        WAKER.wake();
+    }
+}
+
+/// Asks the firmware to hand over the acpi hardware through the SMI
+/// command port, unless it already has
+fn enable_acpi_mode() -> Result<(), Error> {
+    let fadt = acpi_methods::fadt()?;
+    let pm1a = fadt.pm1a_control_block()?;
+    if acpi_registers::read(&pm1a)? & SCI_EN != 0 {
+        return Ok(());
+    }
+
+    // Hardware reduced platforms and ones that are always in acpi mode
+    // have no SMI command port
+    let (smi_cmd, acpi_enable) = (fadt.smi_cmd_port, fadt.acpi_enable);
+    if smi_cmd == 0 || acpi_enable == 0 {
+        return Err(Error::AcpiModeNotEnabled);
+    }
+    unsafe { u8::write_to_port(smi_cmd as u16, acpi_enable) };
+
+    for _ in 0..ACPI_ENABLE_TRIES {
+        if acpi_registers::read(&pm1a)? & SCI_EN != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Error::AcpiModeNotEnabled)
+}
+
+/// Calls `f` with the status and enable registers of PM1a and PM1b
+fn for_each_pm1(
+    mut f: impl FnMut(GenericAddress, GenericAddress) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let fadt = acpi_methods::fadt()?;
+    let pm1a = fadt.pm1a_event_block()?;
+    f(acpi_registers::status_register(&pm1a), acpi_registers::enable_register(&pm1a))?;
+    if let Some(pm1b) = fadt.pm1b_event_block()? {
+        f(acpi_registers::status_register(&pm1b), acpi_registers::enable_register(&pm1b))?;
+    }
+    Ok(())
+}
+
+/// Finds the \_Lxx (level triggered) or \_Exx (edge triggered) method of
+/// a general purpose event
+fn gpe_method(gpe: u16) -> Result<Option<(AmlName, bool)>, Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+    for &(prefix, level) in [("L", true), ("E", false)].iter() {
+        let name = AmlName::from_str(&format!("\\_GPE._{}{:02X}", prefix, gpe))?;
+        match aml_ctx.namespace.get_by_path(&name) {
+            Ok(_) => return Ok(Some((name, level))),
+            Err(AmlError::ValueDoesNotExist(_)) | Err(AmlError::LevelDoesNotExist(_)) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Finds the block, register byte and bit of a general purpose event
+fn locate_gpe(gpe: u16) -> Option<(GpeBlock, usize, u8)> {
+    unsafe { GPE_BLOCKS.iter() }
+        .find_map(|block| block.locate(gpe).map(|(i, bit)| (*block, i, bit)))
+}
+
+/// Acknowledges a general purpose event
+fn clear_gpe(gpe: u16) -> Result<(), Error> {
+    if let Some((block, i, bit)) = locate_gpe(gpe) {
+        acpi_registers::write(&block.status(i), bit as u64)?;
+    }
+    Ok(())
+}
+
+/// Sets the enable bit of a general purpose event
+fn enable_gpe(gpe: u16) -> Result<(), Error> {
+    let (block, i, bit) = match locate_gpe(gpe) {
+        Some(location) => location,
+        None => return Ok(()),
+    };
+
+    // The interrupt handler also changes the enable registers
+    interrupts::without_interrupts(|| {
+        let enable = block.enable(i);
+        let value = acpi_registers::read(&enable)?;
+        acpi_registers::write(&enable, value | bit as u64)
+    })
+}
@@ -1,19 +1,38 @@
 extern crate alloc;
 use alloc::boxed::Box;
+use alloc::format;
 
-use acpi::{AcpiTables, PhysicalMapping};
-use acpi::mcfg::{PciConfigRegions};
-use aml::{AmlContext, DebugVerbosity, AmlName};
+use acpi::{AcpiTables, InterruptModel, PhysicalMapping, PlatformInfo};
+use acpi::fadt::Fadt;
+use acpi::sdt::Signature;
+use aml::{AmlContext, AmlError, DebugVerbosity, AmlName};
 use aml::value::{Args, AmlValue};
 use rsdp::Rsdp;
-// use x86_64::instructions::port::Port;
+use x86_64::instructions::interrupts;
 use x86_64::structures::port::{PortRead, PortWrite};
 
-use super::{Error, SystemHandles};
-use log::debug;
+use super::{acpi_registers, pci_config, Error, SystemHandles};
+use super::pci_express::PciAddress;
+use crate::task::timer;
+use log::{debug, warn};
 
 static mut AML_CONTEXT: Option<AmlContext> = None;
-static mut PCI_REGIONS: Option<PciConfigRegions> = None;
+static mut PLATFORM_INFO: Option<PlatformInfo> = None;
+static mut FADT: Option<PhysicalMapping<Handler, Fadt>> = None;
+
+/// The sleep type field of the PM1 control registers
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0b111 << SLP_TYP_SHIFT;
+
+/// Setting this bit in the PM1 control registers enters the sleep
+/// state in SLP_TYP
+const SLP_EN: u64 = 1 << 13;
+
+/// Set in the PM1 status registers when the system wakes up
+const WAK_STS: u64 = 1 << 15;
+
+/// How long to wait for soft-off to take effect before giving up
+const POWER_OFF_TIMEOUT_MS: u64 = 1000;
 
 
 /// Parses the acpi tables and creates an aml context object to be
@@ -32,10 +51,24 @@ pub fn init_acpi(h: &SystemHandles) -> Result<(), Error> {
         ) 
     }?;
     
-    debug!("Locating the PCIe configuration space");
-    let regions = PciConfigRegions::new(&tables)?;
-    unsafe { PCI_REGIONS = Some(regions)};
-
+    // Machines without an MCFG still have the configuration ports
+    debug!("Locating the PCI configuration space");
+    let mcfg = tables.sdts.get(&Signature::MCFG).map(|mcfg| unsafe {
+        alloc::slice::from_raw_parts(
+            mcfg.physical_address as *const u8,
+            mcfg.length as usize,
+        )
+    });
+    pci_config::init(mcfg);
+
+    debug!("Reading the platform's interrupt model");
+    let platform_info = tables.platform_info()?;
+    unsafe { PLATFORM_INFO = Some(platform_info) };
+
+    debug!("Locating the FADT");
+    let fadt = unsafe { tables.get_sdt::<Fadt>(Signature::FADT) }?
+        .ok_or(Error::NoFadt)?;
+    unsafe { FADT = Some(fadt) };
 
     debug!("Creating a new AML context");
     let mut aml_ctx = AmlContext::new(
@@ -74,83 +107,194 @@ pub fn init_acpi(h: &SystemHandles) -> Result<(), Error> {
     Ok(())
 }
 
-/// More information in chapter 7 of the acpi specification
+/// Gets the interrupt model described by the MADT
+pub fn interrupt_model() -> Option<&'static InterruptModel> {
+    unsafe { PLATFORM_INFO.as_ref() }.map(|info| &info.interrupt_model)
+}
+
+/// Puts the system into a sleep state (1 to 5, where 5 is soft-off)
+/// using the sequence in chapter 7 of the acpi specification. Entering
+/// S5 powers the machine off, so this only returns if that failed. S3
+/// returns once the system has woken up again.
 pub fn shutdown(mode: usize) -> Result<(), Error> {
+    if mode == 3 {
+        return super::sleep::suspend();
+    }
+
+    let sleep_types = prepare_sleep(mode)?;
+
+    // Nothing may run between writing SLP_TYP and SLP_EN
+    interrupts::disable();
+    debug!("Entering S{}", mode);
+    if let Err(err) = enter_sleep(sleep_types) {
+        interrupts::enable();
+        return Err(err);
+    }
+
+    // Soft-off takes effect within a few microseconds, so still running
+    // after a while means it did not work
+    if mode == 5 {
+        interrupts::enable();
+        let deadline = timer::uptime_ms() + POWER_OFF_TIMEOUT_MS;
+        while timer::uptime_ms() < deadline {
+            x86_64::instructions::hlt();
+        }
+
+        // SLP_EN always reads as 0, but SLP_TYP shows whether the write
+        // reached the register
+        let pm1a = || -> Result<u64, Error> {
+            acpi_registers::read(&fadt()?.pm1a_control_block()?)
+        };
+        warn!("Still running {} ms after entering S5, PM1a control is {:x?}",
+            POWER_OFF_TIMEOUT_MS, pm1a());
+        return Err(Error::SleepFailed);
+    }
+
+    interrupts::enable();
+    Ok(())
+}
+
+/// Reads the sleep type values of a sleep state and tells the firmware
+/// that the system is about to enter it
+pub(super) fn prepare_sleep(mode: usize) -> Result<(u64, u64), Error> {
+    if !(1..=5).contains(&mode) {
+        return Err(Error::InvalidSleepState(mode));
+    }
+
     // Get the current Aml context
     let aml_ctx = unsafe { AML_CONTEXT.as_mut() }
         .ok_or(Error::NoAmlContext)?;
-   
-    // let s_mode = "\\_S".to_owned() + mode.to_string().as_str();
-    // let s_mode = &aml_ctx
-    //     .namespace
-        // .get_by_path(&AmlName::from_str(&s_mode)?)?;
-    // debug!("S5: {:?}", &s_mode);
-   
-    for mode in ["\\_S0", "\\_S1", "\\_S2", "\\_S3", "\\_S4", "\\_S5"] {
-        let name =  AmlName::from_str(&mode).unwrap();
-        debug!("{}: {:?}", mode, &aml_ctx.namespace.get_by_path(&name));
-    }
-
-    // System status indicator, messages waiter indicator,
-    // battery level threshold, 
-    // let cmds = ["\\SI_SST", "\\SI_MSG", "\\SI_BLT"]; {
-    // }
-    
+
+    debug!("Reading the sleep type values of S{}", mode);
+    let sleep_types = sleep_types(aml_ctx, mode)?;
+
     // Call TTS: a control method used to prepare to sleep
     // and run once awakened
-    aml_ctx.invoke_method(
-        &AmlName::from_str("\\_TTS")?,
-        Args {
-            arg_0: Some(AmlValue::Integer(mode as u64)),
-            arg_1: None,
-            arg_2: None,
-            arg_3: None,
-            arg_4: None,
-            arg_5: None,
-            arg_6: None,
-        },
-    )?;
-    
+    invoke_optional(aml_ctx, "\\_TTS", mode as u64)?;
+
     // Call PTS: control method used to notify the platform
     // of impending sleep transition
-    aml_ctx.invoke_method(
-        &AmlName::from_str("\\_TTS")?,
-        Args {
-            arg_0: Some(AmlValue::Integer(mode as u64)),
-            arg_1: None,
-            arg_2: None,
-            arg_3: None,
-            arg_4: None,
-            arg_5: None,
-            arg_6: None,
-        },
-    )?;
+    invoke_optional(aml_ctx, "\\_PTS", mode as u64)?;
 
+    Ok(sleep_types)
+}
 
-    // Returns a PhysicalMapping<H, T>
-    // let fadt = unsafe { tables.get_sdt::<Fadt>(Signature::FADT) }?
-    //     .unwrap();
-    
+/// Writes the sleep type values to the PM1 control registers, followed
+/// by SLP_EN. Interrupts must be disabled by the caller.
+pub(super) fn enter_sleep((slp_typa, slp_typb): (u64, u64)) -> Result<(), Error> {
+    let fadt = fadt()?;
+    let pm1a = fadt.pm1a_control_block()?;
+    let pm1b = fadt.pm1b_control_block()?;
+
+    // Write the sleep type first, then set SLP_EN to enter the sleep
+    // state. The other bits of the registers (like SCI_EN) are kept.
+    let pm1a_value = (acpi_registers::read(&pm1a)? & !SLP_TYP_MASK)
+        | (slp_typa << SLP_TYP_SHIFT);
+    let pm1b_value = match pm1b {
+        Some(pm1b) => Some((acpi_registers::read(&pm1b)? & !SLP_TYP_MASK)
+            | (slp_typb << SLP_TYP_SHIFT)),
+        None => None,
+    };
+    acpi_registers::write(&pm1a, pm1a_value)?;
+    if let (Some(pm1b), Some(value)) = (pm1b, pm1b_value) {
+        acpi_registers::write(&pm1b, value)?;
+    }
+    acpi_registers::write(&pm1a, pm1a_value | SLP_EN)?;
+    if let (Some(pm1b), Some(value)) = (pm1b, pm1b_value) {
+        acpi_registers::write(&pm1b, value | SLP_EN)?;
+    }
+    Ok(())
+}
 
-    // let mut port = Port::new(fadt.pm1a_control_block as u16);
-    // unsafe { port.write(0b0011110000000000 as u16) };
+/// Gets the AML context created by `init_acpi`
+pub(super) fn aml_context() -> Result<&'static mut AmlContext, Error> {
+    unsafe { AML_CONTEXT.as_mut() }.ok_or(Error::NoAmlContext)
+}
 
-    Ok(())
+/// Gets the FADT found by `init_acpi`
+pub(super) fn fadt() -> Result<&'static Fadt, Error> {
+    unsafe { FADT.as_deref() }.ok_or(Error::NoFadt)
+}
+
+/// Reads the SLP_TYPa and SLP_TYPb values of a sleep state from its
+/// \_Sx package
+fn sleep_types(aml_ctx: &AmlContext, mode: usize) -> Result<(u64, u64), Error> {
+    let name = AmlName::from_str(&format!("\\_S{}", mode))?;
+    let package = match aml_ctx.namespace.get_by_path(&name) {
+        Ok(AmlValue::Package(package)) => package,
+        Ok(_) | Err(AmlError::ValueDoesNotExist(_)) | Err(AmlError::LevelDoesNotExist(_)) => {
+            return Err(Error::SleepStateNotSupported(mode));
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    let value = |i: usize| -> Result<u64, Error> {
+        match package.get(i) {
+            Some(value) => Ok(value.as_integer(aml_ctx)? & 0b111),
+            None => Err(Error::SleepStateNotSupported(mode)),
+        }
+    };
+
+    // Some firmware packs both values into the first element
+    if package.len() == 1 {
+        let packed = package[0].as_integer(aml_ctx)?;
+        return Ok((packed & 0b111, (packed >> 8) & 0b111));
+    }
+    Ok((value(0)?, value(1)?))
 }
 
-pub fn wakeup(_mod: usize) -> Result<(), Error> {
-    // let context = unsafe { AML_CONTEXT.as_ref() }
-    //     .ok_or(Error::NoAmlContext)?;
+/// Calls a control method that takes one integer argument, if the
+/// firmware provides it
+fn invoke_optional(
+    aml_ctx: &mut AmlContext,
+    path: &str,
+    arg: u64,
+) -> Result<Option<AmlValue>, Error> {
+    let name = AmlName::from_str(path)?;
+    let args = Args {
+        arg_0: Some(AmlValue::Integer(arg)),
+        arg_1: None,
+        arg_2: None,
+        arg_3: None,
+        arg_4: None,
+        arg_5: None,
+        arg_6: None,
+    };
+    match aml_ctx.invoke_method(&name, args) {
+        Ok(value) => Ok(Some(value)),
+        Err(AmlError::ValueDoesNotExist(_)) | Err(AmlError::LevelDoesNotExist(_)) => {
+            debug!("{} does not exist", path);
+            Ok(None)
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Tells the firmware that the system has woken up from a sleep state,
+/// using the wake sequence in chapter 7 of the acpi specification
+pub fn wakeup(mode: usize) -> Result<(), Error> {
+    if !(1..=5).contains(&mode) {
+        return Err(Error::InvalidSleepState(mode));
+    }
+
+    // Get the current Aml context
+    let aml_ctx = unsafe { AML_CONTEXT.as_mut() }
+        .ok_or(Error::NoAmlContext)?;
+    let fadt = fadt()?;
 
-    // Returns a PhysicalMapping<H, T>
-    // let fadt = unsafe { tables.get_sdt::<Fadt>(Signature::FADT) }?
-    //     .unwrap();
+    debug!("Clearing the wake status");
+    let pm1a = acpi_registers::status_register(&fadt.pm1a_event_block()?);
+    acpi_registers::write(&pm1a, WAK_STS)?;
+    if let Some(pm1b) = fadt.pm1b_event_block()? {
+        acpi_registers::write(&acpi_registers::status_register(&pm1b), WAK_STS)?;
+    }
 
-    // c.parse_table(fadt.virtual_start.as_ref());
-    // let s5 = c.invoke_method(AmlName::from_str("\\_S5"))?;
+    // Call WAK: a control method used to notify the platform
+    // that the system has woken up
+    invoke_optional(aml_ctx, "\\_WAK", mode as u64)?;
 
-    // let mut port = Port::new(fadt.pm1a_control_block as u16);
-    // unsafe { port.write(0b0011110000000000 as u16) };
+    // Call TTS again with the working state
+    invoke_optional(aml_ctx, "\\_TTS", 0)?;
 
     Ok(())
 }
@@ -241,20 +385,8 @@ impl aml::Handler for Handler {
         function: u8,
         offset: u16,
     ) -> u8 {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        self.read_u8(addr + offset)
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::read_u8(address, offset)
     }
 
     fn read_pci_u16(
@@ -265,20 +397,8 @@ impl aml::Handler for Handler {
         function: u8,
         offset: u16,
     ) -> u16 {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        self.read_u16(addr + offset)
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::read_u16(address, offset)
     }
 
     fn read_pci_u32(
@@ -289,20 +409,8 @@ impl aml::Handler for Handler {
         function: u8,
         offset: u16,
     ) -> u32 {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        self.read_u32(addr + offset)
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::read_u32(address, offset)
     }
 
     fn write_pci_u8(
@@ -314,20 +422,8 @@ impl aml::Handler for Handler {
         offset: u16,
         value: u8,
     ) {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        unsafe { *((addr + offset) as *mut u8) = value }
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::write_u8(address, offset, value)
     }
 
     fn write_pci_u16(
@@ -339,20 +435,8 @@ impl aml::Handler for Handler {
         offset: u16,
         value: u16,
     ) {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        unsafe { *((addr + offset) as *mut u16) = value }
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::write_u16(address, offset, value)
     }
 
     fn write_pci_u32(
@@ -364,19 +448,7 @@ impl aml::Handler for Handler {
         offset: u16,
         value: u32,
     ) {
-
-        // Get the PCIe regions
-        let pci_regions = unsafe { PCI_REGIONS.as_ref() }
-            .expect("Could not find PCI Configuration Space");
-        
-        // Get the addressof the 
-        let addr = pci_regions
-            .physical_address(segment, bus, device, function)
-            .expect("Cannot manage device") as usize;
-        
-        // Read the address
-        let offset = offset as usize;
-        unsafe { *((addr + offset) as *mut u32) = value }
-
+        let address = PciAddress { segment, bus, device, function };
+        pci_config::write_u32(address, offset, value)
     }
 }
@@ -0,0 +1,213 @@
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use aml::{AmlContext, AmlError, AmlName};
+use aml::value::{AmlType, AmlValue, Args};
+
+use super::{acpi_methods, Error};
+
+/// An object directly inside a scope of the acpi namespace
+#[derive(Debug)]
+pub enum Node {
+    /// A scope, device or processor that holds other objects
+    Level(AmlName, LevelKind),
+
+    /// A named value, which can also be a method or a field
+    Value(AmlName, AmlType),
+}
+impl Node {
+    pub fn name(&self) -> &AmlName {
+        match self {
+            Node::Level(name, _) | Node::Value(name, _) => name,
+        }
+    }
+}
+
+/// The kind of a level of the namespace. This mirrors the level type of
+/// the aml crate, which is not exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelKind {
+    Scope,
+    Device,
+    Processor,
+    MethodLocals,
+}
+impl LevelKind {
+    fn of(typ: &dyn fmt::Debug) -> LevelKind {
+        match format!("{:?}", typ).as_str() {
+            "Device" => LevelKind::Device,
+            "Processor" => LevelKind::Processor,
+            "MethodLocals" => LevelKind::MethodLocals,
+            _ => LevelKind::Scope,
+        }
+    }
+}
+
+/// The identification objects of a device in the acpi namespace
+#[derive(Debug)]
+pub struct DeviceInfo {
+    pub path: AmlName,
+
+    /// The hardware id, like PNP0A08
+    pub hid: Option<String>,
+
+    /// Compatible ids, which drivers can also match on
+    pub cid: Vec<String>,
+
+    /// Tells apart devices with the same hardware id
+    pub uid: Option<String>,
+
+    /// The address of the device on its parent bus
+    pub adr: Option<u64>,
+}
+
+/// Lists the levels and values directly inside a scope, like `\_SB`
+pub fn list(path: &str) -> Result<Vec<Node>, Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+    let scope = AmlName::from_str(path)?.resolve(&AmlName::root())?;
+
+    let mut nodes = Vec::new();
+    let mut values = Vec::new();
+    let mut found = false;
+    aml_ctx.namespace.traverse(|name, level| {
+        if *name != scope {
+            // Only descend into the levels on the way to the scope
+            return Ok(is_prefix(name, &scope));
+        }
+        found = true;
+        for (seg, child) in level.children.iter() {
+            nodes.push(Node::Level(
+                AmlName::from_name_seg(*seg).resolve(name)?,
+                LevelKind::of(&child.typ),
+            ));
+        }
+        for (seg, handle) in level.values.iter() {
+            values.push((AmlName::from_name_seg(*seg).resolve(name)?, *handle));
+        }
+        Ok(false)
+    })?;
+    if !found {
+        return Err(AmlError::LevelDoesNotExist(scope).into());
+    }
+
+    for (name, handle) in values {
+        let typ = aml_ctx.namespace.get(handle)?.type_of();
+        nodes.push(Node::Value(name, typ));
+    }
+    nodes.sort_by(|a, b| a.name().cmp(b.name()));
+    Ok(nodes)
+}
+
+/// Finds every device in the namespace along with its identification
+/// objects
+pub fn devices() -> Result<Vec<DeviceInfo>, Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+
+    let mut paths = Vec::new();
+    aml_ctx.namespace.traverse(|name, level| {
+        let kind = LevelKind::of(&level.typ);
+        if kind == LevelKind::Device {
+            paths.push(name.clone());
+        }
+        Ok(kind != LevelKind::MethodLocals)
+    })?;
+
+    let mut devices = Vec::new();
+    for path in paths {
+        let hid = optional(aml_ctx, &path, "_HID")?
+            .map(|hid| device_id(aml_ctx, &hid))
+            .transpose()?;
+        let cid = match optional(aml_ctx, &path, "_CID")? {
+            Some(AmlValue::Package(ids)) => ids.iter()
+                .map(|id| device_id(aml_ctx, id))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(id) => alloc::vec![device_id(aml_ctx, &id)?],
+            None => Vec::new(),
+        };
+        let uid = optional(aml_ctx, &path, "_UID")?
+            .map(|uid| match uid {
+                AmlValue::String(uid) => Ok(uid),
+                uid => uid.as_integer(aml_ctx).map(|uid| format!("{}", uid)),
+            })
+            .transpose()?;
+        let adr = optional(aml_ctx, &path, "_ADR")?
+            .map(|adr| adr.as_integer(aml_ctx))
+            .transpose()?;
+        devices.push(DeviceInfo { path, hid, cid, uid, adr });
+    }
+    Ok(devices)
+}
+
+/// Evaluates an object, calling it with `args` if it is a method
+pub fn eval(path: &str, args: Vec<AmlValue>) -> Result<AmlValue, Error> {
+    if args.len() > 7 {
+        return Err(Error::TooManyArguments);
+    }
+    let aml_ctx = acpi_methods::aml_context()?;
+    let name = AmlName::from_str(path)?.resolve(&AmlName::root())?;
+    Ok(aml_ctx.invoke_method(&name, Args::from_list(args))?)
+}
+
+/// Formats an acpi value for printing, showing integers in hex and
+/// buffers as bytes
+pub struct Pretty<'a>(pub &'a AmlValue);
+impl fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            AmlValue::Boolean(value) => write!(f, "{}", value),
+            AmlValue::Integer(value) => write!(f, "{:#x}", value),
+            AmlValue::String(value) => write!(f, "{:?}", value),
+            AmlValue::Buffer(bytes) => write!(f, "Buffer {:02x?}", bytes),
+            AmlValue::Package(values) => {
+                write!(f, "Package {{")?;
+                for (i, value) in values.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, Pretty(value))?;
+                }
+                write!(f, " }}")
+            },
+            AmlValue::Method { flags, .. } => write!(f, "Method({} args)", flags.arg_count()),
+            value => write!(f, "{:?}", value.type_of()),
+        }
+    }
+}
+
+/// Evaluates an object inside a device if it exists
+pub(super) fn optional(
+    aml_ctx: &mut AmlContext,
+    device: &AmlName,
+    name: &str,
+) -> Result<Option<AmlValue>, Error> {
+    let path = AmlName::from_str(name)?.resolve(device)?;
+    match aml_ctx.invoke_method(&path, Args::default()) {
+        Ok(value) => Ok(Some(value)),
+        Err(AmlError::ValueDoesNotExist(_)) | Err(AmlError::LevelDoesNotExist(_)) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Gets a device id from a string or from an integer holding a
+/// compressed EISA id
+fn device_id(aml_ctx: &AmlContext, value: &AmlValue) -> Result<String, Error> {
+    match value {
+        AmlValue::String(id) => Ok(id.clone()),
+        value => Ok(eisa_id(value.as_integer(aml_ctx)? as u32)),
+    }
+}
+
+/// Decodes a compressed EISA id, which stores three letters in five
+/// bits each followed by four hex digits, in big endian order
+fn eisa_id(id: u32) -> String {
+    let id = id.swap_bytes();
+    let letter = |shift: u32| (b'@' + ((id >> shift) & 0x1F) as u8) as char;
+    format!("{}{}{}{:04X}", letter(26), letter(21), letter(16), id & 0xFFFF)
+}
+
+/// Checks whether `prefix` is `name` or one of its parents
+fn is_prefix(prefix: &AmlName, name: &AmlName) -> bool {
+    let (prefix, name) = (prefix.as_string(), name.as_string());
+    prefix == "\\" || name == prefix || name.starts_with(&format!("{}.", prefix))
+}
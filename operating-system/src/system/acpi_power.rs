@@ -0,0 +1,185 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use aml::{AmlContext, AmlName, AmlValue};
+
+use super::{acpi_devices, acpi_methods, acpi_namespace, Error};
+
+/// The hardware id of control method batteries
+const BATTERY_HID: &str = "PNP0C0A";
+
+/// Returned by batteries for values they do not know
+const UNKNOWN: u64 = 0xFFFF_FFFF;
+
+/// 0 degrees Celsius in tenths of a Kelvin
+const ZERO_CELSIUS: i64 = 2732;
+
+/// A temperature in tenths of a Kelvin, the unit used by acpi
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Temperature(pub u64);
+impl Temperature {
+    /// Gets the temperature in tenths of a degree Celsius
+    pub fn deci_celsius(self) -> i64 { self.0 as i64 - ZERO_CELSIUS }
+}
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let t = self.deci_celsius();
+        let sign = if t < 0 { "-" } else { "" };
+        write!(f, "{}{}.{} C", sign, t.abs() / 10, t.abs() % 10)
+    }
+}
+
+/// The readings of a thermal zone
+#[derive(Debug)]
+pub struct ThermalZone {
+    pub path: AmlName,
+
+    /// The current temperature from \_TMP
+    pub temperature: Temperature,
+
+    /// The temperature at which the system must shut down (\_CRT)
+    pub critical: Option<Temperature>,
+
+    /// The temperature at which the cpu should be slowed down (\_PSV)
+    pub passive: Option<Temperature>,
+}
+impl ThermalZone {
+    /// Checks whether the zone has reached its critical temperature
+    pub fn is_critical(&self) -> bool {
+        self.critical.map_or(false, |critical| self.temperature >= critical)
+    }
+}
+
+/// The unit of battery capacities and rates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerUnit {
+    /// Capacities in mWh and rates in mW
+    MilliWatts,
+
+    /// Capacities in mAh and rates in mA
+    MilliAmps,
+}
+
+/// The static information and current state of a control method battery.
+/// Values that the battery does not know are None.
+#[derive(Debug)]
+pub struct Battery {
+    pub path: AmlName,
+    pub unit: PowerUnit,
+    pub design_capacity: Option<u64>,
+    pub full_capacity: Option<u64>,
+    pub remaining_capacity: Option<u64>,
+    pub rate: Option<u64>,
+
+    /// The voltage in mV
+    pub voltage: Option<u64>,
+    pub charging: bool,
+    pub discharging: bool,
+    pub critical: bool,
+}
+impl Battery {
+    /// Gets the remaining charge as a percentage of the last full charge
+    pub fn percent(&self) -> Option<u64> {
+        match (self.remaining_capacity, self.full_capacity) {
+            (Some(remaining), Some(full)) if full != 0 => Some((remaining * 100 / full).min(100)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads every thermal zone in the namespace. Any level with a \_TMP
+/// object is treated as one, since the aml crate does not parse
+/// ThermalZone objects into a level type of their own.
+pub fn thermal_zones() -> Result<Vec<ThermalZone>, Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+
+    let mut paths = Vec::new();
+    aml_ctx.namespace.traverse(|name, level| {
+        if level.values.keys().any(|seg| seg.as_str() == "_TMP") {
+            paths.push(name.clone());
+        }
+        Ok(true)
+    })?;
+
+    let mut zones = Vec::new();
+    for path in paths {
+        let temperature = match temperature(aml_ctx, &path, "_TMP")? {
+            Some(temperature) => temperature,
+            None => continue,
+        };
+        let critical = temperature(aml_ctx, &path, "_CRT")?;
+        let passive = temperature(aml_ctx, &path, "_PSV")?;
+        zones.push(ThermalZone { path, temperature, critical, passive });
+    }
+    Ok(zones)
+}
+
+/// Reads every control method battery in the device registry that has a
+/// battery inserted
+pub fn batteries() -> Result<Vec<Battery>, Error> {
+    let aml_ctx = acpi_methods::aml_context()?;
+
+    let mut batteries = Vec::new();
+    for device in acpi_devices::find_devices(BATTERY_HID) {
+        // Bit 4 of the status is set when a battery is inserted
+        if device.status & (1 << 4) == 0 {
+            continue;
+        }
+        let path = device.path.clone();
+
+        // _BIX has a revision field in front of the fields of _BIF
+        let (info, first) = match acpi_namespace::optional(aml_ctx, &path, "_BIX")? {
+            Some(AmlValue::Package(info)) => (info, 1),
+            _ => match acpi_namespace::optional(aml_ctx, &path, "_BIF")? {
+                Some(AmlValue::Package(info)) => (info, 0),
+                _ => continue,
+            },
+        };
+        let state = match acpi_namespace::optional(aml_ctx, &path, "_BST")? {
+            Some(AmlValue::Package(state)) => state,
+            _ => continue,
+        };
+
+        let field = |package: &[AmlValue], i: usize| -> Result<Option<u64>, Error> {
+            match package.get(i) {
+                Some(value) => match value.as_integer(aml_ctx)? {
+                    UNKNOWN => Ok(None),
+                    value => Ok(Some(value)),
+                },
+                None => Ok(None),
+            }
+        };
+
+        let flags = field(&state, 0)?.unwrap_or(0);
+        batteries.push(Battery {
+            path,
+            unit: match field(&info, first)? {
+                Some(1) => PowerUnit::MilliAmps,
+                _ => PowerUnit::MilliWatts,
+            },
+            design_capacity: field(&info, first + 1)?,
+            full_capacity: field(&info, first + 2)?,
+            remaining_capacity: field(&state, 2)?,
+            rate: field(&state, 1)?,
+            voltage: field(&state, 3)?,
+            discharging: flags & (1 << 0) != 0,
+            charging: flags & (1 << 1) != 0,
+            critical: flags & (1 << 2) != 0,
+        });
+    }
+    Ok(batteries)
+}
+
+/// Evaluates a temperature or trip point of a thermal zone, if it has
+/// one
+fn temperature(
+    aml_ctx: &mut AmlContext,
+    zone: &AmlName,
+    name: &str,
+) -> Result<Option<Temperature>, Error> {
+    match acpi_namespace::optional(aml_ctx, zone, name)? {
+        Some(value) => Ok(Some(Temperature(value.as_integer(aml_ctx)?))),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,83 @@
+use acpi::platform::address::{AddressSpace, GenericAddress};
+use x86_64::structures::port::{PortRead, PortWrite};
+
+use super::Error;
+
+/// Reads a fixed hardware register described by a generic address
+pub fn read(reg: &GenericAddress) -> Result<u64, Error> {
+    let value = match (reg.address_space, reg.bit_width) {
+        (AddressSpace::SystemIo, 8) => unsafe { u8::read_from_port(port(reg)?) as u64 },
+        (AddressSpace::SystemIo, 16) => unsafe { u16::read_from_port(port(reg)?) as u64 },
+        (AddressSpace::SystemIo, 32) => unsafe { u32::read_from_port(port(reg)?) as u64 },
+        (AddressSpace::SystemMemory, width) => unsafe {
+            let addr = reg.address as usize;
+            match width {
+                8 => core::ptr::read_volatile(addr as *const u8) as u64,
+                16 => core::ptr::read_volatile(addr as *const u16) as u64,
+                32 => core::ptr::read_volatile(addr as *const u32) as u64,
+                64 => core::ptr::read_volatile(addr as *const u64),
+                _ => return Err(Error::UnsupportedRegister),
+            }
+        },
+        _ => return Err(Error::UnsupportedRegister),
+    };
+    Ok(value >> reg.bit_offset)
+}
+
+/// Writes to a fixed hardware register described by a generic address
+pub fn write(reg: &GenericAddress, value: u64) -> Result<(), Error> {
+    let value = value << reg.bit_offset;
+    match (reg.address_space, reg.bit_width) {
+        (AddressSpace::SystemIo, 8) => unsafe { u8::write_to_port(port(reg)?, value as u8) },
+        (AddressSpace::SystemIo, 16) => unsafe { u16::write_to_port(port(reg)?, value as u16) },
+        (AddressSpace::SystemIo, 32) => unsafe { u32::write_to_port(port(reg)?, value as u32) },
+        (AddressSpace::SystemMemory, width) => unsafe {
+            let addr = reg.address as usize;
+            match width {
+                8 => core::ptr::write_volatile(addr as *mut u8, value as u8),
+                16 => core::ptr::write_volatile(addr as *mut u16, value as u16),
+                32 => core::ptr::write_volatile(addr as *mut u32, value as u32),
+                64 => core::ptr::write_volatile(addr as *mut u64, value),
+                _ => return Err(Error::UnsupportedRegister),
+            }
+        },
+        _ => return Err(Error::UnsupportedRegister),
+    }
+    Ok(())
+}
+
+/// Gets the status register of an event block, which is its first half.
+/// Its bits are cleared by writing ones to them.
+pub fn status_register(block: &GenericAddress) -> GenericAddress {
+    GenericAddress {
+        bit_width: block.bit_width / 2,
+        ..*block
+    }
+}
+
+/// Gets the enable register of an event block, which is its second half
+pub fn enable_register(block: &GenericAddress) -> GenericAddress {
+    GenericAddress {
+        bit_width: block.bit_width / 2,
+        address: block.address + block.bit_width as u64 / 16,
+        ..*block
+    }
+}
+
+/// Gets one byte of a register, for registers like the GPE blocks that
+/// are only accessed a byte at a time
+pub fn byte(reg: &GenericAddress, index: usize) -> GenericAddress {
+    GenericAddress {
+        bit_width: 8,
+        address: reg.address + index as u64,
+        ..*reg
+    }
+}
+
+/// Gets the port of a register in the io address space
+fn port(reg: &GenericAddress) -> Result<u16, Error> {
+    if reg.address > u16::MAX as u64 {
+        return Err(Error::UnsupportedRegister);
+    }
+    Ok(reg.address as u16)
+}
@@ -0,0 +1,220 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use super::Error;
+
+/// A resource used by a device, decoded from a resource template like
+/// the one returned by `_CRS`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Io(IoRange),
+    Memory(MemoryRange),
+    AddressSpace(AddressSpace),
+    Irq(Irq),
+
+    /// A mask of the ISA DMA channels the device can use
+    Dma(u8),
+}
+
+/// A range of io ports. Fixed ranges have the same minimum and maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoRange {
+    /// The lowest base port the range can be placed at
+    pub min: u16,
+
+    /// The highest base port the range can be placed at
+    pub max: u16,
+    pub alignment: u16,
+    pub len: u16,
+}
+
+/// A range of physical memory from a Memory24, Memory32 or Memory32Fixed
+/// descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub base: u64,
+    pub len: u64,
+    pub writable: bool,
+}
+
+/// What the range of an address space descriptor is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceKind {
+    Memory,
+    Io,
+    BusNumber,
+    Other(u8),
+}
+
+/// A range from a Word, DWord, QWord or Extended address space
+/// descriptor, which bridges like the PCI host use to describe the
+/// windows they decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpace {
+    pub kind: AddressSpaceKind,
+    pub min: u64,
+    pub max: u64,
+
+    /// Added to an address on the parent side to get the address on the
+    /// child side of the bridge
+    pub translation: u64,
+    pub len: u64,
+
+    /// Whether the range is used by the device itself rather than
+    /// passed on to its children
+    pub consumer: bool,
+}
+
+/// The interrupts from an IRQ or Extended Interrupt descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Irq {
+    /// ISA irqs for IRQ descriptors, global system interrupts for
+    /// Extended Interrupt descriptors
+    pub irqs: Vec<u32>,
+    pub level_triggered: bool,
+    pub active_low: bool,
+    pub shared: bool,
+}
+
+/// Small descriptor types
+const SMALL_IRQ: u8 = 0x04;
+const SMALL_DMA: u8 = 0x05;
+const SMALL_IO: u8 = 0x08;
+const SMALL_FIXED_IO: u8 = 0x09;
+const SMALL_END: u8 = 0x0F;
+
+/// Large descriptor types
+const LARGE_MEMORY24: u8 = 0x01;
+const LARGE_MEMORY32: u8 = 0x05;
+const LARGE_FIXED_MEMORY32: u8 = 0x06;
+const LARGE_DWORD_SPACE: u8 = 0x07;
+const LARGE_WORD_SPACE: u8 = 0x08;
+const LARGE_EXTENDED_IRQ: u8 = 0x09;
+const LARGE_QWORD_SPACE: u8 = 0x0A;
+const LARGE_EXTENDED_SPACE: u8 = 0x0B;
+
+/// Decodes a resource template. Descriptors that do not describe a
+/// resource (like vendor data or dependent function markers) are
+/// skipped.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Resource>, Error> {
+    let mut resources = Vec::new();
+    let mut bytes = bytes;
+
+    while let Some(&tag) = bytes.first() {
+        if tag & 0x80 == 0 {
+            // Small descriptors have their type in bits 3 to 6 and their
+            // length in bits 0 to 2
+            let len = (tag & 0b111) as usize;
+            let data = bytes.get(1..1 + len).ok_or(Error::InvalidResource)?;
+            match (tag >> 3) & 0xF {
+                SMALL_END => break,
+                SMALL_IRQ => resources.push(Resource::Irq(irq(data)?)),
+                SMALL_DMA => resources.push(Resource::Dma(read(data, 0, 1)? as u8)),
+                SMALL_IO => resources.push(Resource::Io(IoRange {
+                    min: read(data, 1, 2)? as u16,
+                    max: read(data, 3, 2)? as u16,
+                    alignment: read(data, 5, 1)? as u16,
+                    len: read(data, 6, 1)? as u16,
+                })),
+                SMALL_FIXED_IO => {
+                    let base = read(data, 0, 2)? as u16 & 0x3FF;
+                    resources.push(Resource::Io(IoRange {
+                        min: base,
+                        max: base,
+                        alignment: 1,
+                        len: read(data, 2, 1)? as u16,
+                    }));
+                },
+                _ => (),
+            }
+            bytes = &bytes[1 + len..];
+        } else {
+            // Large descriptors have their type in bits 0 to 6 and a 16
+            // bit length after the tag
+            let len = read(bytes, 1, 2)? as usize;
+            let data = bytes.get(3..3 + len).ok_or(Error::InvalidResource)?;
+            let resource = match tag & 0x7F {
+                LARGE_MEMORY24 => Some(Resource::Memory(MemoryRange {
+                    base: read(data, 1, 2)? << 8,
+                    len: read(data, 7, 2)? << 8,
+                    writable: read(data, 0, 1)? & 1 != 0,
+                })),
+                LARGE_MEMORY32 => Some(Resource::Memory(MemoryRange {
+                    base: read(data, 1, 4)?,
+                    len: read(data, 13, 4)?,
+                    writable: read(data, 0, 1)? & 1 != 0,
+                })),
+                LARGE_FIXED_MEMORY32 => Some(Resource::Memory(MemoryRange {
+                    base: read(data, 1, 4)?,
+                    len: read(data, 5, 4)?,
+                    writable: read(data, 0, 1)? & 1 != 0,
+                })),
+                LARGE_WORD_SPACE => Some(address_space(data, 2, 3)?),
+                LARGE_DWORD_SPACE => Some(address_space(data, 4, 3)?),
+                LARGE_QWORD_SPACE => Some(address_space(data, 8, 3)?),
+                LARGE_EXTENDED_SPACE => Some(address_space(data, 8, 5)?),
+                LARGE_EXTENDED_IRQ => Some(Resource::Irq(extended_irq(data)?)),
+                _ => None,
+            };
+            resources.extend(resource);
+            bytes = &bytes[3 + len..];
+        }
+    }
+    Ok(resources)
+}
+
+/// Decodes an IRQ descriptor, which has a mask of ISA irqs and optional
+/// flags. Without the flags the irqs are edge triggered and active high.
+fn irq(data: &[u8]) -> Result<Irq, Error> {
+    let mask = read(data, 0, 2)?;
+    let flags = if data.len() > 2 { read(data, 2, 1)? } else { 1 };
+    Ok(Irq {
+        irqs: (0..16).filter(|irq| mask & (1 << irq) != 0).collect(),
+        level_triggered: flags & 1 == 0,
+        active_low: flags & (1 << 3) != 0,
+        shared: flags & (1 << 4) != 0,
+    })
+}
+
+/// Decodes an Extended Interrupt descriptor, which lists global system
+/// interrupts
+fn extended_irq(data: &[u8]) -> Result<Irq, Error> {
+    let flags = read(data, 0, 1)?;
+    let count = read(data, 1, 1)? as usize;
+    let irqs = (0..count)
+        .map(|i| read(data, 2 + i * 4, 4).map(|irq| irq as u32))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Irq {
+        irqs,
+        level_triggered: flags & (1 << 1) == 0,
+        active_low: flags & (1 << 2) != 0,
+        shared: flags & (1 << 3) != 0,
+    })
+}
+
+/// Decodes an address space descriptor whose fields are `width` bytes
+/// wide and start `start` bytes into the descriptor. The fields are the
+/// granularity, minimum, maximum, translation offset and length.
+fn address_space(data: &[u8], width: usize, start: usize) -> Result<Resource, Error> {
+    let field = |i: usize| read(data, start + i * width, width);
+    let kind = match read(data, 0, 1)? as u8 {
+        0 => AddressSpaceKind::Memory,
+        1 => AddressSpaceKind::Io,
+        2 => AddressSpaceKind::BusNumber,
+        kind => AddressSpaceKind::Other(kind),
+    };
+    Ok(Resource::AddressSpace(AddressSpace {
+        kind,
+        min: field(1)?,
+        max: field(2)?,
+        translation: field(3)?,
+        len: field(4)?,
+        consumer: read(data, 1, 1)? & 1 != 0,
+    }))
+}
+
+/// Reads a little endian value of `len` bytes
+fn read(data: &[u8], offset: usize, len: usize) -> Result<u64, Error> {
+    let bytes = data.get(offset..offset + len).ok_or(Error::InvalidResource)?;
+    Ok(bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64))
+}
@@ -1,34 +1,243 @@
-/*
-pub fn init_lapic() {
-    use x2apic::lapic::{LocalApic, LocalApicBuilder};
-
-    let lapic = LocalApicBuilder::new()
-        .timer_vector(0xFFF)
-        .error_vector(0xFFFF)
-        .spurious_vector(0xFF)
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use acpi::InterruptModel;
+use acpi::platform::{Apic, Polarity, TriggerMode};
+use log::debug;
+use x2apic::ioapic::{IoApic, IrqFlags, IrqMode};
+use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder, TimerDivide, TimerMode};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use super::interrupts::{APIC_ERROR, IRQ_OFFSET, SPURIOUS_VECTOR, TIMER};
+use crate::task::timer::{self, TICKS_PER_SECOND};
+
+pub static mut LAPIC: Option<LocalApic> = None;
+pub static mut IOAPIC: Option<IoApic> = None;
+
+/// The frequency of the programmable interval timer's oscillator
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// The initial count used for the APIC timer before it is calibrated
+const UNCALIBRATED_INITIAL_COUNT: u32 = 100_000;
+
+/// The number of milliseconds the APIC timer is measured for
+const CALIBRATION_MS: u64 = 100;
+
+/// The calibrated initial count of the APIC timer, kept so that it does
+/// not have to be measured again when resuming from a sleep state
+static TIMER_INITIAL: AtomicU32 = AtomicU32::new(0);
+
+/// The default flags of every irq routed through the I/O apic, so that
+/// they can be routed again when resuming from a sleep state
+static mut ROUTED_IRQS: [Option<IrqFlags>; 32] = [None; 32];
+
+/// Sets up the local apic of the current cpu and the first I/O apic
+/// described by the MADT
+pub fn init(apic: &Apic) {
+    debug!("Disabling the legacy PIC");
+    disable_pic();
+
+    debug!("Initializing the local APIC");
+    init_lapic();
+
+    if let Some(io_apic) = apic.io_apics.first() {
+        debug!("Initializing the I/O APIC at {:#x}", io_apic.address);
+        init_ioapic(io_apic.address as u64);
+    } else {
+        debug!("No I/O APIC found");
+    }
+
+    debug!("Calibrating the APIC timer");
+    calibrate_timer();
+}
+
+/// Programs the apics again after their state was lost in a sleep
+/// state, routing the same irqs as before
+pub fn resume(apic: &Apic) {
+    debug!("Disabling the legacy PIC");
+    disable_pic();
+
+    debug!("Initializing the local APIC");
+    init_lapic();
+    let initial = TIMER_INITIAL.load(Ordering::SeqCst);
+    if let (Some(lapic), true) = (unsafe { LAPIC.as_mut() }, initial != 0) {
+        unsafe { lapic.set_timer_initial(initial) };
+    }
+
+    if let Some(io_apic) = apic.io_apics.first() {
+        debug!("Initializing the I/O APIC at {:#x}", io_apic.address);
+        init_ioapic(io_apic.address as u64);
+    }
+
+    let routed = unsafe { ROUTED_IRQS };
+    for (irq, flags) in routed.iter().enumerate() {
+        if let Some(flags) = flags {
+            debug!("Routing irq {}", irq);
+            route_irq(irq as u8, *flags);
+        }
+    }
+}
+
+/// Enables the local apic and starts its periodic timer
+fn init_lapic() {
+    let mut lapic = LocalApicBuilder::new()
+        .timer_vector(TIMER)
+        .error_vector(APIC_ERROR)
+        .spurious_vector(SPURIOUS_VECTOR)
+        .timer_mode(TimerMode::Periodic)
+        .timer_divide(TimerDivide::Div16)
+        .timer_initial(UNCALIBRATED_INITIAL_COUNT)
+        .set_xapic_base(unsafe { xapic_base() })
         .build()
         .unwrap_or_else(|err| panic!("{}", err));
 
-    unsafe { lapic.enable(); }
-    
+    unsafe {
+        lapic.enable();
+        LAPIC = Some(lapic);
+    }
+}
+
+/// Maps the I/O apic's interrupts starting at IRQ_OFFSET
+fn init_ioapic(addr: u64) {
+    unsafe {
+        let mut ioapic = IoApic::new(addr);
+        ioapic.init(IRQ_OFFSET);
+        IOAPIC = Some(ioapic);
+    }
+}
+
+/// Routes an ISA irq through the I/O apic to the current cpu and returns
+/// the I/O apic pin that it arrives on
+pub fn enable_irq(irq: u8) -> u8 {
+    route_irq(irq, IrqFlags::empty())
+}
+
+/// Routes the SCI, which is level triggered and active low unless the
+/// MADT overrides it, and returns the I/O apic pin that it arrives on
+pub fn enable_sci(irq: u8) -> u8 {
+    route_irq(irq, IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE)
 }
 
-pub fn init_ioapic() {
-    use x2apic::ioapic::{IoApic, IrqFlags, IrqMode};
+/// Routes an irq to the current cpu, following the interrupt source
+/// overrides in the MADT
+fn route_irq(irq: u8, default: IrqFlags) -> u8 {
+    if let Some(slot) = unsafe { ROUTED_IRQS.get_mut(irq as usize) } {
+        *slot = Some(default);
+    }
+
+    let (pin, flags) = resolve_irq(irq, default);
+    let dest = unsafe { LAPIC.as_ref() }.map_or(0, |lapic| unsafe { lapic.id() });
+    if let Some(ioapic) = unsafe { IOAPIC.as_mut() } {
+        unsafe {
+            ioapic.enable_irq(pin, dest, IrqMode::Fixed, flags);
+        }
+    }
+    pin
+}
+
+/// Finds the I/O apic pin and the polarity and trigger mode of an irq
+fn resolve_irq(irq: u8, default: IrqFlags) -> (u8, IrqFlags) {
+    let apic = match super::interrupt_model() {
+        Some(InterruptModel::Apic(apic)) => apic,
+        _ => return (irq, default),
+    };
+    let base = apic.io_apics.first().map_or(0, |io_apic| io_apic.global_system_interrupt_base);
 
-    //Map the IOAPIC's MMIO address `addr` here
-    
+    let source = apic.interrupt_source_overrides.iter()
+        .find(|source| source.isa_source == irq);
+    let source = match source {
+        Some(source) => source,
+        None => return (irq, default),
+    };
+
+    let mut flags = default;
+    match source.polarity {
+        Polarity::ActiveHigh => flags.remove(IrqFlags::LOW_ACTIVE),
+        Polarity::ActiveLow => flags.insert(IrqFlags::LOW_ACTIVE),
+        Polarity::SameAsBus => (),
+    }
+    match source.trigger_mode {
+        TriggerMode::Edge => flags.remove(IrqFlags::LEVEL_TRIGGERED),
+        TriggerMode::Level => flags.insert(IrqFlags::LEVEL_TRIGGERED),
+        TriggerMode::SameAsBus => (),
+    }
+    ((source.global_system_interrupt - base) as u8, flags)
+}
+
+/// Gets the id of the current cpu's local apic, which MSIs are sent to
+pub fn id() -> u32 {
+    match unsafe { LAPIC.as_ref() } {
+        Some(lapic) => unsafe { lapic.id() },
+        None => 0,
+    }
+}
+
+/// Signals the end of an interrupt to the local apic
+pub fn end_of_interrupt() {
+    if let Some(lapic) = unsafe { LAPIC.as_mut() } {
+        unsafe { lapic.end_of_interrupt() };
+    }
+}
+
+/// Masks every interrupt of the 8259 PICs so that they do not fire
+/// alongside the apic
+fn disable_pic() {
     unsafe {
-        let ioapic = IoApic::new(addr);
-        
-        ioapic.init(irq_offset);
-        
-        ioapic.enable_irq(
-            irq_number,
-            dest, // CPU(s)
-            IrqMode::Fixed,
-            IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE,
-        );
-    }
-}
-*/
+        Port::<u8>::new(0xA1).write(0xFF);
+        Port::<u8>::new(0x21).write(0xFF);
+    }
+}
+
+/// Measures how many times the APIC timer fires over a known amount of
+/// time and rescales its initial count to fire at TICKS_PER_SECOND
+fn calibrate_timer() {
+    // Count the ticks over the calibration period
+    interrupts::enable();
+    let start = timer::ticks();
+    for _ in 0..(CALIBRATION_MS / 10) {
+        pit_sleep(10);
+    }
+    let elapsed = timer::ticks() - start;
+    interrupts::disable();
+
+    // The number of ticks that should have happened
+    let expected = CALIBRATION_MS * TICKS_PER_SECOND / 1000;
+    if elapsed == 0 {
+        debug!("APIC timer did not fire during calibration");
+        return;
+    }
+
+    let initial = UNCALIBRATED_INITIAL_COUNT as u64 * elapsed / expected;
+    debug!("APIC timer initial count: {}", initial);
+    TIMER_INITIAL.store(initial as u32, Ordering::SeqCst);
+    if let Some(lapic) = unsafe { LAPIC.as_mut() } {
+        unsafe { lapic.set_timer_initial(initial as u32) };
+    }
+}
+
+/// Busy waits using channel 2 of the PIT. `ms` must be less than 55.
+fn pit_sleep(ms: u64) {
+    let count = (PIT_FREQUENCY * ms / 1000) as u16;
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel_2 = Port::<u8>::new(0x42);
+
+    unsafe {
+        // Enable the channel 2 gate with the speaker disconnected
+        let value = (gate.read() & 0xFD) | 0x01;
+        gate.write(value);
+
+        // Channel 2, lobyte/hibyte, interrupt on terminal count
+        command.write(0b1011_0000);
+        channel_2.write(count as u8);
+        channel_2.write((count >> 8) as u8);
+
+        // Restart the count by toggling the gate
+        let value = gate.read() & 0xFE;
+        gate.write(value);
+        gate.write(value | 0x01);
+
+        // Wait for the output of channel 2 to go high
+        while gate.read() & 0x20 == 0 {}
+    }
+}
@@ -2,48 +2,123 @@ use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 use lazy_static::lazy_static;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 pub fn init() {
-    use x86_64::instructions::segmentation::set_cs;
+    use x86_64::instructions::segmentation::{load_ds, load_ss, set_cs};
     use x86_64::instructions::tables::load_tss;
 
-    //GDT.0.load();
-    //unsafe {
-    //    set_cs(GDT.1.code_selector);
-    //    load_tss(GDT.1.tss_selector);
-    //}
+    GDT.0.load();
+    unsafe {
+        set_cs(GDT.1.kernel_code);
+        load_ds(GDT.1.kernel_data);
+        load_ss(GDT.1.kernel_data);
+        load_tss(GDT.1.tss);
+    }
+}
+
+/// Loads the GDT and TSS again after the cpu lost its state, like when
+/// resuming from a sleep state
+///
+/// The TSS descriptor is still marked busy from when it was first
+/// loaded, and loading a busy TSS faults, so the busy bit is cleared
+/// first. This is unsafe because it must only be called while nothing
+/// else is using the TSS.
+pub unsafe fn reload() {
+    use x86_64::structures::DescriptorTablePointer;
+
+    GDT.0.load();
+    let mut gdtr = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    asm!("sgdt [{}]", in(reg) &mut gdtr, options(nostack, preserves_flags));
+    let tss = (gdtr.base.as_u64() as *mut u64).add(GDT.1.tss.index() as usize);
+    *tss &= !TSS_BUSY;
+    init();
+}
+
+/// The bit of a TSS descriptor that is set by the cpu when it is loaded
+const TSS_BUSY: u64 = 1 << 41;
+
+/// Gets the segment selectors of the loaded GDT
+pub fn selectors() -> &'static Selectors { &GDT.1 }
+
+/// Gets the stack that the cpu switches to when entering ring 0
+/// from ring 3
+pub fn kernel_stack() -> VirtAddr {
+    init_tss();
+    unsafe { TSS.privilege_stack_table[0] }
+}
+
+/// Sets the stack that the cpu switches to when entering ring 0 from
+/// ring 3
+///
+/// This is unsafe because the stack must stay valid while code runs in
+/// ring 3, and must not be the stack currently in use by a system call
+/// or interrupt from ring 3
+pub unsafe fn set_kernel_stack(stack: VirtAddr) {
+    init_tss();
+    TSS.privilege_stack_table[0] = stack;
 }
 
 // Define the 0th entry of the IST to hold the double fault stack
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            
-            // Change this to allocate a new stack when memory management is implemented
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+/// The task state segment. The ring 0 stack in it is changed whenever a
+/// different process runs, so it can not be behind a lazy_static.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Fills in the stacks of the TSS the first time it is used
+fn init_tss() {
+    static INITIALIZED: AtomicBool = AtomicBool::new(false);
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let tss = unsafe { &mut TSS };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+
+        // Change this to allocate a new stack when memory management is implemented
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
+    };
+
+    // The stack used by interrupts and system calls coming from ring 3
+    tss.privilege_stack_table[0] = {
+        const STACK_SIZE: usize = 4096 * 5;
+
+        #[repr(align(16))]
+        struct Stack([u8; STACK_SIZE]);
+        static mut STACK: Stack = Stack([0; STACK_SIZE]);
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        stack_start + STACK_SIZE
     };
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors { code_selector, tss_selector })
+
+        // The order of these entries is required by the STAR msr:
+        // SYSCALL loads the kernel data segment from kernel_code + 8,
+        // and SYSRET loads user data from user_code - 8
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        init_tss();
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &TSS }));
+        (gdt, Selectors { kernel_code, kernel_data, user_data, user_code, tss })
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
 }
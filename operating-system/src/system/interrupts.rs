@@ -1,12 +1,17 @@
 use log::info;
-use crate::system::gdt;
+use crate::system::{apic, gdt, syscall, usermode};
+use core::fmt;
+use crate::task;
 
+use x86_64::{PrivilegeLevel, VirtAddr};
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{
     InterruptDescriptorTable, 
     InterruptStackFrame,
     PageFaultErrorCode,
 };
 use lazy_static::lazy_static;
+use spin::Mutex;
 
 /// Enables interrupts and sets up the IDT
 pub fn enable() {
@@ -15,7 +20,7 @@ pub fn enable() {
 
 extern "x86-interrupt" fn divide_error_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("divide_error");
+    fault(stack_frame, format_args!("divide_error"));
 }
 
 extern "x86-interrupt" fn debug_handler(
@@ -35,91 +40,181 @@ extern "x86-interrupt" fn breakpoint_handler(
 
 extern "x86-interrupt" fn overflow_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("overflow");
+    fault(stack_frame, format_args!("overflow"));
 }
 
 extern "x86-interrupt" fn bound_range_exceeded_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("bound_range_exceeded");
+    fault(stack_frame, format_args!("bound_range_exceeded"));
 }
 
 extern "x86-interrupt" fn invalid_opcode_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("invalid_opcode");
+    fault(stack_frame, format_args!("invalid_opcode"));
 }
 
 extern "x86-interrupt" fn device_not_available_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("device_not_available");
+    fault(stack_frame, format_args!("device_not_available"));
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) -> ! {
-    info!("double_fault");
-    loop{}
+    // Aborts cannot be recovered from, even in user mode
+    panic!("double_fault at {:?}", stack_frame.instruction_pointer);
 }
 
 extern "x86-interrupt" fn invalid_tss_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("invalid_tss");
+    fault(stack_frame, format_args!("invalid_tss: err_code={:#x}", error_code));
 }
 
 extern "x86-interrupt" fn segment_not_present_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("segment_not_present: err_code={}", error_code);
-    info!("InterruptStackFrame: {:?}", stack_frame);
+    fault(stack_frame, format_args!("segment_not_present: err_code={:#x}", error_code));
 }
 
 extern "x86-interrupt" fn stack_segment_fault_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("stack_segment_fault");
+    fault(stack_frame, format_args!("stack_segment_fault: err_code={:#x}", error_code));
 }
 
 extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("general_protection_fault: err_code={}", error_code);
+    fault(stack_frame, format_args!("general_protection_fault: err_code={:#x}", error_code));
 }
 
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: &mut InterruptStackFrame, error_code: PageFaultErrorCode) {
-    info!("page_fault");
+    fault(stack_frame, format_args!("page_fault at {:?}: {:?}", Cr2::read(), error_code));
+}
+
+/// Stops the running program if a fault came from ring 3, and panics if
+/// it came from the kernel. Returning would run the faulting instruction
+/// again.
+fn fault(stack_frame: &InterruptStackFrame, description: fmt::Arguments) -> ! {
+    if stack_frame.code_segment & 0b11 == PrivilegeLevel::Ring3 as u64 {
+        info!("{} at {:?} in user mode, stopping the program",
+            description, stack_frame.instruction_pointer);
+        usermode::exit(usermode::FAULT_EXIT_CODE);
+    }
+    panic!("{} at {:?}", description, stack_frame.instruction_pointer);
 }
 
 extern "x86-interrupt" fn x87_floating_point_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("x87_floating_point");
+    fault(stack_frame, format_args!("x87_floating_point"));
 }
 
 extern "x86-interrupt" fn alignment_check_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("alignment_check");
+    fault(stack_frame, format_args!("alignment_check: err_code={:#x}", error_code));
 }
 
 extern "x86-interrupt" fn machine_check_handler(
     stack_frame: &mut InterruptStackFrame) -> ! {
-    info!("machine_check");
-    loop {}
+    panic!("machine_check at {:?}", stack_frame.instruction_pointer);
 }
 
 extern "x86-interrupt" fn simd_floating_point_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("simd_floating_point");
+    fault(stack_frame, format_args!("simd_floating_point"));
 }
 
 extern "x86-interrupt" fn virtualization_handler(
     stack_frame: &mut InterruptStackFrame) {
-    info!("virtualization");
+    fault(stack_frame, format_args!("virtualization"));
 }
 
 extern "x86-interrupt" fn security_exception_handler(
     stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    info!("security_exception");
+    fault(stack_frame, format_args!("security_exception: err_code={:#x}", error_code));
+}
+
+/// The vector that the I/O apic's first irq is mapped to
+pub const IRQ_OFFSET: u8 = 0x20;
+
+/// The number of I/O apic pins that handlers can be set for at runtime
+pub const IRQ_COUNT: usize = 24;
+
+/// The vectors that are handed out to MSI and MSI-X interrupts
+pub const MSI_OFFSET: u8 = 0x40;
+pub const MSI_COUNT: usize = 64;
+
+pub const SYSCALL: usize = 0x80;
+pub const TIMER: usize = 0x81;
+pub const APIC_ERROR: usize = 0x82;
+pub const SPURIOUS_VECTOR: usize = 0xff;
+
+/// Points the vectors of the I/O apic pins at `irq_handler`
+macro_rules! set_irq_handlers {
+    ($idt:ident, $($irq:literal)*) => {
+        $($idt[IRQ_OFFSET as usize + $irq].set_handler_fn(irq_handler::<$irq>);)*
+    };
+}
+
+/// Handlers of I/O apic pins that are set at runtime, like the SCI
+static mut IRQ_HANDLERS: [Option<fn()>; IRQ_COUNT] = [None; IRQ_COUNT];
+
+/// Calls `handler` in interrupt context whenever the I/O apic pin `irq`
+/// fires
+pub fn set_irq_handler(irq: u8, handler: fn()) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        IRQ_HANDLERS[irq as usize] = Some(handler);
+    });
+}
+
+/// Points the MSI vectors at `msi_handler`
+macro_rules! set_msi_handlers {
+    ($idt:ident, $($vector:literal)*) => {
+        $($idt[MSI_OFFSET as usize + $vector].set_handler_fn(msi_handler::<$vector>);)*
+    };
 }
 
-const SYSCALL: usize = 0x80;
-const TIMER: usize = 0x81;
-const APIC_ERROR: usize = 0x82;
-const SPURIOUS_VECTOR: usize = 0xff;
+/// Handlers of MSI vectors and the context they are called with, like
+/// the queue the vector belongs to
+static mut MSI_HANDLERS: [Option<(fn(usize), usize)>; MSI_COUNT] = [None; MSI_COUNT];
+
+/// A bit for each MSI vector that has been allocated
+static MSI_ALLOCATED: Mutex<u64> = Mutex::new(0);
+
+/// Allocates `count` consecutive MSI vectors, aligned to `count` as
+/// multiple message MSI needs, and returns the first one. `count` has to
+/// be a power of two.
+pub fn allocate_vectors(count: usize) -> Option<u8> {
+    if !count.is_power_of_two() || count > MSI_COUNT {
+        return None;
+    }
+    let block = if count == 64 { u64::MAX } else { (1 << count) - 1 };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut allocated = MSI_ALLOCATED.lock();
+        let first = (0..MSI_COUNT).step_by(count)
+            .find(|&first| *allocated & (block << first) == 0)?;
+        *allocated |= block << first;
+        Some(MSI_OFFSET + first as u8)
+    })
+}
+
+/// Frees MSI vectors from `allocate_vectors` and removes their handlers
+pub fn free_vectors(first: u8, count: usize) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut allocated = MSI_ALLOCATED.lock();
+        for vector in first..first + count as u8 {
+            let i = (vector - MSI_OFFSET) as usize;
+            *allocated &= !(1 << i);
+            unsafe { MSI_HANDLERS[i] = None };
+        }
+    });
+}
+
+/// Calls `handler` with `context` in interrupt context whenever the MSI
+/// vector `vector` fires
+pub fn set_vector_handler(vector: u8, handler: fn(usize), context: usize) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        MSI_HANDLERS[(vector - MSI_OFFSET) as usize] = Some((handler, context));
+    });
+}
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -147,20 +242,30 @@ lazy_static! {
         idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
         idt.virtualization.set_handler_fn(virtualization_handler);
         idt.security_exception.set_handler_fn(security_exception_handler);
-        idt[SYSCALL].set_handler_fn(syscall_handler);
+        unsafe {
+            // The int 0x80 fallback shares the register layout of SYSCALL
+            // and is callable from ring 3
+            idt[SYSCALL]
+                .set_handler_addr(VirtAddr::new(syscall::syscall_int80_entry as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt[TIMER].set_handler_fn(timer_handler);
         idt[APIC_ERROR].set_handler_fn(apic_error_handler);
         idt[SPURIOUS_VECTOR].set_handler_fn(spurious_vector_handler);
+        set_irq_handlers!(idt,
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23);
+        set_msi_handlers!(idt,
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23
+            24 25 26 27 28 29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47
+            48 49 50 51 52 53 54 55 56 57 58 59 60 61 62 63);
 
         idt
     };
 }
 
-extern "x86-interrupt" fn syscall_handler(stack_frame: &mut InterruptStackFrame) {
-    info!("syscall");
-}
 extern "x86-interrupt" fn timer_handler(stack_frame: &mut InterruptStackFrame) {
-    info!("timer");
+    task::timer::tick();
+    apic::end_of_interrupt();
 }
 extern "x86-interrupt" fn apic_error_handler(stack_frame: &mut InterruptStackFrame) {
     info!("apic_error");
@@ -168,3 +273,15 @@ extern "x86-interrupt" fn apic_error_handler(stack_frame: &mut InterruptStackFra
 extern "x86-interrupt" fn spurious_vector_handler(stack_frame: &mut InterruptStackFrame) {
     info!("spurious_vector");
 }
+extern "x86-interrupt" fn irq_handler<const IRQ: usize>(stack_frame: &mut InterruptStackFrame) {
+    if let Some(handler) = unsafe { IRQ_HANDLERS[IRQ] } {
+        handler();
+    }
+    apic::end_of_interrupt();
+}
+extern "x86-interrupt" fn msi_handler<const VECTOR: usize>(stack_frame: &mut InterruptStackFrame) {
+    if let Some((handler, context)) = unsafe { MSI_HANDLERS[VECTOR] } {
+        handler(context);
+    }
+    apic::end_of_interrupt();
+}
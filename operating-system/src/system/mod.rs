@@ -1,9 +1,24 @@
-// pub mod gdt;
-// pub mod apic;
-// pub mod interrupts;
+pub mod gdt;
+pub mod apic;
+pub mod interrupts;
+pub mod syscall;
+pub mod usermode;
+pub mod acpi_devices;
+mod acpi_events;
 mod acpi_methods;
+pub mod acpi_namespace;
+pub mod acpi_power;
+mod acpi_registers;
+pub mod acpi_resources;
+pub mod pci_config;
+pub mod pci_express;
+pub mod msi;
+mod sleep;
+mod reset;
 
+pub use acpi_events::{handle_gpe, init_events, next_event, Event};
 pub use acpi_methods::*;
+pub use reset::reboot;
 use rsdp::Rsdp;
 use crate::ST;
 use uefi::table::cfg::{ACPI_GUID, ACPI2_GUID};
@@ -53,6 +68,63 @@ pub enum Error {
     // CouldNotFindSystemFont,
     // NoAcpiTables,
     NoAmlContext,
+    NoFadt,
+
+    /// The requested sleep state is not between S1 and S5
+    InvalidSleepState(usize),
+
+    /// The firmware has no \_Sx object for the sleep state
+    SleepStateNotSupported(usize),
+
+    /// A fixed hardware register is in an address space that can not be
+    /// accessed
+    UnsupportedRegister,
+
+    /// The machine was still running after entering a sleep state
+    SleepFailed,
+
+    /// The FADT does not point to a valid FACS, so there is nowhere to
+    /// put the waking vector
+    NoFacs,
+
+    /// No memory below 1 MiB was free for the wakeup trampoline
+    NoTrampolineMemory,
+
+    /// The page tables are above 4 GiB, where the wakeup trampoline can
+    /// not load them from real mode
+    PageTableTooHigh,
+
+    /// The FADT does not describe a reset register
+    ResetNotSupported,
+
+    /// The firmware did not hand the acpi hardware over to the os
+    AcpiModeNotEnabled,
+
+    /// Acpi methods take at most 7 arguments
+    TooManyArguments,
+
+    /// A resource template ended in the middle of a descriptor
+    InvalidResource,
+
+    /// There are neither ECAM regions in the MCFG nor configuration
+    /// ports to reach the PCI configuration space through
+    NoPciConfigSpace,
+
+    /// The function does not have the MSI or MSI-X capability
+    NoMsi,
+
+    /// More vectors were asked for than the function or the message
+    /// index supports
+    TooManyVectors,
+
+    /// Every MSI vector is in use
+    NoFreeVectors,
+
+    /// The MSI-X table is not in a memory BAR
+    InvalidMsiXTable,
+
+    /// The function can not mask single MSI vectors
+    MaskNotSupported,
     AcpiError(AcpiError),
     AmlError(AmlError),
 }
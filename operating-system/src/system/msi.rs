@@ -0,0 +1,300 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use core::ptr;
+
+use log::debug;
+
+use super::pci_express::{Bar, PciDevice};
+use super::{apic, interrupts, Error};
+
+/// The ids of the MSI and MSI-X capabilities
+const MSI_CAPABILITY: u8 = 0x05;
+const MSIX_CAPABILITY: u8 = 0x11;
+
+/// Where MSI writes have to go to reach a local apic. The destination
+/// apic id goes in bits 12 to 19.
+const MSI_ADDRESS: u64 = 0xFEE0_0000;
+
+/// Set in the command register to stop the function from using its
+/// legacy interrupt pin
+const COMMAND: u16 = 0x04;
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// Bits of the MSI message control register
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASKING: u16 = 1 << 8;
+
+/// Bits of the MSI-X message control register
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+
+/// The size of an entry of the MSI-X table and the offsets of its fields
+const MSIX_ENTRY: u64 = 16;
+const MSIX_ADDRESS: u64 = 0;
+const MSIX_DATA: u64 = 8;
+const MSIX_CONTROL: u64 = 12;
+
+/// Set in the vector control of an MSI-X table entry to mask it
+const MSIX_MASKED: u32 = 1 << 0;
+
+/// What the MSI capability of a function supports
+#[derive(Debug, Clone, Copy)]
+pub struct MsiInfo {
+    /// Where the capability is in the configuration space
+    pub offset: u16,
+
+    /// The number of vectors the function can use, a power of two up to 32
+    pub max_vectors: usize,
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+}
+
+/// What the MSI-X capability of a function supports
+#[derive(Debug, Clone, Copy)]
+pub struct MsiXInfo {
+    /// Where the capability is in the configuration space
+    pub offset: u16,
+
+    /// The number of entries in the table, up to 2048
+    pub table_size: usize,
+
+    /// The BAR and the offset into it of the table and of the pending bit
+    /// array
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+/// Reads the MSI capability of a function, if it has one
+pub fn msi_info(device: &PciDevice) -> Option<MsiInfo> {
+    let offset = device.capability(MSI_CAPABILITY)?;
+    let control = device.read_u16(offset + 2);
+    Some(MsiInfo {
+        offset,
+        max_vectors: 1 << ((control >> 1) & 0b111).min(5),
+        is_64bit: control & MSI_64BIT != 0,
+        per_vector_masking: control & MSI_PER_VECTOR_MASKING != 0,
+    })
+}
+
+/// Reads the MSI-X capability of a function, if it has one
+pub fn msix_info(device: &PciDevice) -> Option<MsiXInfo> {
+    let offset = device.capability(MSIX_CAPABILITY)?;
+    let control = device.read_u16(offset + 2);
+    let table = device.read_u32(offset + 4);
+    let pba = device.read_u32(offset + 8);
+    Some(MsiXInfo {
+        offset,
+        table_size: (control & 0x7FF) as usize + 1,
+        table_bar: (table & 0b111) as u8,
+        table_offset: table & !0b111,
+        pba_bar: (pba & 0b111) as u8,
+        pba_offset: pba & !0b111,
+    })
+}
+
+/// Gets the address and data of a message that raises `vector` on the
+/// current cpu, edge triggered with fixed delivery
+fn message(vector: u8) -> (u64, u32) {
+    (MSI_ADDRESS | ((apic::id() as u64 & 0xFF) << 12), vector as u32)
+}
+
+/// Stops the function from raising its legacy interrupt and lets it write
+/// messages
+fn prepare(device: &PciDevice) {
+    let command = device.read_u16(COMMAND);
+    device.write_u16(COMMAND, command | COMMAND_INTX_DISABLE);
+    device.enable_bus_master();
+}
+
+/// MSI enabled on a function, with a block of consecutive vectors
+pub struct Msi {
+    device: &'static PciDevice,
+    info: MsiInfo,
+    first: u8,
+    count: usize,
+}
+impl Msi {
+    /// Enables MSI with a vector for each of `handlers`, which are called
+    /// with their context when the vector fires. The number of vectors is
+    /// rounded up to a power of two, and the extra vectors have no handler.
+    pub fn enable(
+        device: &'static PciDevice,
+        handlers: &[(fn(usize), usize)],
+    ) -> Result<Msi, Error> {
+        let info = msi_info(device).ok_or(Error::NoMsi)?;
+        let count = handlers.len().max(1).next_power_of_two();
+        if count > info.max_vectors {
+            return Err(Error::TooManyVectors);
+        }
+
+        debug!("Allocating {} MSI vectors for {}", count, device.address);
+        let first = interrupts::allocate_vectors(count).ok_or(Error::NoFreeVectors)?;
+        for (i, &(handler, context)) in handlers.iter().enumerate() {
+            interrupts::set_vector_handler(first + i as u8, handler, context);
+        }
+
+        // The function sets the low bits of the data to the index of the
+        // vector, which is why the block has to be aligned
+        let (address, data) = message(first);
+        let offset = info.offset;
+        let data_offset = if info.is_64bit {
+            device.write_u32(offset + 4, address as u32);
+            device.write_u32(offset + 8, (address >> 32) as u32);
+            offset + 12
+        } else {
+            device.write_u32(offset + 4, address as u32);
+            offset + 8
+        };
+        device.write_u16(data_offset, data as u16);
+
+        prepare(device);
+        let control = device.read_u16(offset + 2) & !(0b111 << 4);
+        let enabled = (count.trailing_zeros() as u16) << 4;
+        device.write_u16(offset + 2, control | enabled | MSI_ENABLE);
+
+        Ok(Msi { device, info, first, count })
+    }
+
+    /// Gets the vector of the `index`th message
+    pub fn vector(&self, index: usize) -> Option<u8> {
+        if index < self.count { Some(self.first + index as u8) } else { None }
+    }
+
+    /// Stops the `index`th message from being sent. Its pending bit is set
+    /// instead, so it is sent once unmasked.
+    pub fn mask(&self, index: usize) -> Result<(), Error> {
+        self.set_masked(index, true)
+    }
+
+    pub fn unmask(&self, index: usize) -> Result<(), Error> {
+        self.set_masked(index, false)
+    }
+
+    fn set_masked(&self, index: usize, masked: bool) -> Result<(), Error> {
+        if !self.info.per_vector_masking {
+            return Err(Error::MaskNotSupported);
+        }
+        if index >= self.count {
+            return Err(Error::TooManyVectors);
+        }
+
+        let mask_offset = self.info.offset + if self.info.is_64bit { 0x10 } else { 0x0C };
+        let bits = self.device.read_u32(mask_offset);
+        let bits = if masked { bits | (1 << index) } else { bits & !(1 << index) };
+        self.device.write_u32(mask_offset, bits);
+        Ok(())
+    }
+
+    /// Turns MSI off again and frees the vectors
+    pub fn disable(self) {
+        let control = self.device.read_u16(self.info.offset + 2);
+        self.device.write_u16(self.info.offset + 2, control & !MSI_ENABLE);
+        interrupts::free_vectors(self.first, self.count);
+    }
+}
+
+/// MSI-X enabled on a function, with a vector of its own for every
+/// table entry in use
+pub struct MsiX {
+    device: &'static PciDevice,
+    info: MsiXInfo,
+
+    /// The physical address of the table
+    table: u64,
+    vectors: Vec<u8>,
+}
+impl MsiX {
+    /// Enables MSI-X, using the first table entries for `handlers`, which
+    /// are called with their context when their vector fires. Every entry
+    /// starts out unmasked.
+    pub fn enable(
+        device: &'static PciDevice,
+        handlers: &[(fn(usize), usize)],
+    ) -> Result<MsiX, Error> {
+        let info = msix_info(device).ok_or(Error::NoMsi)?;
+        if handlers.len() > info.table_size {
+            return Err(Error::TooManyVectors);
+        }
+        let table = match device.bars.get(info.table_bar as usize) {
+            Some(Some(Bar::Memory { address, .. })) => address + info.table_offset as u64,
+            _ => return Err(Error::InvalidMsiXTable),
+        };
+
+        debug!("Allocating {} MSI-X vectors for {}", handlers.len(), device.address);
+        let mut vectors = Vec::new();
+        for &(handler, context) in handlers.iter() {
+            let vector = match interrupts::allocate_vectors(1) {
+                Some(vector) => vector,
+                None => {
+                    for &vector in vectors.iter() {
+                        interrupts::free_vectors(vector, 1);
+                    }
+                    return Err(Error::NoFreeVectors);
+                },
+            };
+            interrupts::set_vector_handler(vector, handler, context);
+            vectors.push(vector);
+        }
+
+        // Keep every entry masked while the table is written
+        prepare(device);
+        let control = device.read_u16(info.offset + 2);
+        device.write_u16(info.offset + 2, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+
+        let msix = MsiX { device, info, table, vectors };
+        for i in 0..info.table_size {
+            msix.write_entry(i, MSIX_CONTROL, MSIX_MASKED);
+        }
+        for (i, &vector) in msix.vectors.iter().enumerate() {
+            let (address, data) = message(vector);
+            msix.write_entry(i, MSIX_ADDRESS, address as u32);
+            msix.write_entry(i, MSIX_ADDRESS + 4, (address >> 32) as u32);
+            msix.write_entry(i, MSIX_DATA, data);
+            msix.write_entry(i, MSIX_CONTROL, 0);
+        }
+
+        let control = device.read_u16(info.offset + 2);
+        device.write_u16(info.offset + 2, control & !MSIX_FUNCTION_MASK);
+        Ok(msix)
+    }
+
+    /// Gets the vector of the `index`th table entry
+    pub fn vector(&self, index: usize) -> Option<u8> {
+        self.vectors.get(index).copied()
+    }
+
+    /// Stops the `index`th table entry from sending its message. Its
+    /// pending bit is set instead, so it is sent once unmasked.
+    pub fn mask(&self, index: usize) -> Result<(), Error> {
+        if index >= self.vectors.len() {
+            return Err(Error::TooManyVectors);
+        }
+        self.write_entry(index, MSIX_CONTROL, MSIX_MASKED);
+        Ok(())
+    }
+
+    pub fn unmask(&self, index: usize) -> Result<(), Error> {
+        if index >= self.vectors.len() {
+            return Err(Error::TooManyVectors);
+        }
+        self.write_entry(index, MSIX_CONTROL, 0);
+        Ok(())
+    }
+
+    /// Turns MSI-X off again and frees the vectors
+    pub fn disable(self) {
+        let control = self.device.read_u16(self.info.offset + 2);
+        self.device.write_u16(self.info.offset + 2, control & !MSIX_ENABLE);
+        for &vector in self.vectors.iter() {
+            interrupts::free_vectors(vector, 1);
+        }
+    }
+
+    fn write_entry(&self, index: usize, field: u64, value: u32) {
+        let address = self.table + index as u64 * MSIX_ENTRY + field;
+        unsafe { ptr::write_volatile(address as *mut u32, value) };
+    }
+}
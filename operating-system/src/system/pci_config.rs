@@ -0,0 +1,180 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use core::ptr;
+
+use log::debug;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::port::{PortRead, PortWrite};
+
+use super::pci_express::PciAddress;
+
+/// The ports of configuration mechanism #1. The address of a register is
+/// written to the address port, then the register is accessed through
+/// the data port.
+const ADDRESS_PORT: u16 = 0xCF8;
+const DATA_PORT: u16 = 0xCFC;
+
+/// Set in the address port to turn the access into a configuration cycle
+const ENABLE: u32 = 1 << 31;
+
+/// The configuration space reachable through the ports. The extended
+/// configuration space of PCIe functions needs ECAM.
+const PORT_CONFIG_SIZE: u16 = 0x100;
+
+/// The size of an entry in the MCFG and of the header in front of them
+const MCFG_HEADER: usize = 44;
+const MCFG_ENTRY: usize = 16;
+
+/// A range of buses whose configuration space is memory mapped, taken
+/// from an entry of the MCFG
+#[derive(Debug, Clone, Copy)]
+struct EcamRegion {
+    base: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+}
+impl EcamRegion {
+    fn contains(&self, segment: u16, bus: u8) -> bool {
+        self.segment == segment && (self.start_bus..=self.end_bus).contains(&bus)
+    }
+
+    /// Gets the physical address of a function's configuration space
+    fn address(&self, address: PciAddress) -> u64 {
+        self.base
+            + (((address.bus - self.start_bus) as u64) << 20
+            | (address.device as u64) << 15
+            | (address.function as u64) << 12)
+    }
+}
+
+/// How a register of the configuration space is reached
+enum Access {
+    /// Through memory at a physical address
+    Ecam(u64),
+
+    /// Through the data port after writing this to the address port
+    Ports(u32),
+
+    /// Not at all, so reads return all ones and writes are dropped
+    None,
+}
+
+static mut REGIONS: Vec<EcamRegion> = Vec::new();
+static mut PORTS: bool = false;
+
+/// Held while the address port is set up for an access through the data
+/// port
+static PORT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Reads the ECAM regions from the MCFG if there is one and checks for
+/// the port mechanism, which serves segment 0 wherever the MCFG does not
+pub(super) fn init(mcfg: Option<&[u8]>) {
+    let mut regions = Vec::new();
+    if let Some(mcfg) = mcfg {
+        // The acpi crate keeps its parsed entries private, so the table is
+        // read directly
+        for entry in mcfg.get(MCFG_HEADER..).unwrap_or(&[]).chunks_exact(MCFG_ENTRY) {
+            let region = unsafe { EcamRegion {
+                base: ptr::read_unaligned(entry.as_ptr() as *const u64),
+                segment: ptr::read_unaligned(entry[8..].as_ptr() as *const u16),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            }};
+            debug!("Found ECAM region {:x?}", region);
+            regions.push(region);
+        }
+    }
+
+    let ports = ports_present();
+    debug!("Configuration ports {}", if ports { "found" } else { "not found" });
+    unsafe {
+        REGIONS = regions;
+        PORTS = ports;
+    }
+}
+
+/// Gets the segments and bus ranges whose configuration space can be
+/// accessed, as (segment, first bus, last bus)
+pub(super) fn bus_ranges() -> Vec<(u16, u8, u8)> {
+    let mut ranges = unsafe { REGIONS.iter() }
+        .map(|region| (region.segment, region.start_bus, region.end_bus))
+        .collect::<Vec<_>>();
+    if unsafe { PORTS } && !ranges.iter().any(|&(segment, _, _)| segment == 0) {
+        ranges.push((0, 0, 255));
+    }
+    ranges
+}
+
+pub fn read_u8(address: PciAddress, offset: u16) -> u8 { read(address, offset, 0xFF) }
+pub fn read_u16(address: PciAddress, offset: u16) -> u16 { read(address, offset, 0xFFFF) }
+pub fn read_u32(address: PciAddress, offset: u16) -> u32 { read(address, offset, 0xFFFF_FFFF) }
+pub fn write_u8(address: PciAddress, offset: u16, value: u8) { write(address, offset, value) }
+pub fn write_u16(address: PciAddress, offset: u16, value: u16) { write(address, offset, value) }
+pub fn write_u32(address: PciAddress, offset: u16, value: u32) { write(address, offset, value) }
+
+/// Reads a register, which has to be aligned to its size. `missing` is
+/// returned for registers that can not be reached, which is what
+/// reading a function that does not exist gives.
+fn read<T: PortRead>(address: PciAddress, offset: u16, missing: T) -> T {
+    match access(address, offset) {
+        Access::Ecam(physical) => unsafe { ptr::read_volatile(physical as *const T) },
+        Access::Ports(config_address) => interrupts::without_interrupts(|| {
+            let _lock = PORT_LOCK.lock();
+            unsafe {
+                u32::write_to_port(ADDRESS_PORT, config_address);
+                T::read_from_port(DATA_PORT + (offset & 0x3))
+            }
+        }),
+        Access::None => missing,
+    }
+}
+
+/// Writes a register, which has to be aligned to its size
+fn write<T: PortWrite>(address: PciAddress, offset: u16, value: T) {
+    match access(address, offset) {
+        Access::Ecam(physical) => unsafe { ptr::write_volatile(physical as *mut T, value) },
+        Access::Ports(config_address) => interrupts::without_interrupts(|| {
+            let _lock = PORT_LOCK.lock();
+            unsafe {
+                u32::write_to_port(ADDRESS_PORT, config_address);
+                T::write_to_port(DATA_PORT + (offset & 0x3), value);
+            }
+        }),
+        Access::None => (),
+    }
+}
+
+/// Picks ECAM when a region covers the bus, and the ports otherwise
+fn access(address: PciAddress, offset: u16) -> Access {
+    let region = unsafe { REGIONS.iter() }
+        .find(|region| region.contains(address.segment, address.bus));
+    if let Some(region) = region {
+        return Access::Ecam(region.address(address) + offset as u64);
+    }
+
+    if unsafe { PORTS } && address.segment == 0 && offset < PORT_CONFIG_SIZE {
+        return Access::Ports(ENABLE
+            | (address.bus as u32) << 16
+            | (address.device as u32) << 11
+            | (address.function as u32) << 8
+            | (offset & 0xFC) as u32);
+    }
+    Access::None
+}
+
+/// Checks for configuration mechanism #1 by seeing whether the address
+/// port keeps the enable bit
+fn ports_present() -> bool {
+    interrupts::without_interrupts(|| {
+        let _lock = PORT_LOCK.lock();
+        unsafe {
+            let old = u32::read_from_port(ADDRESS_PORT);
+            u32::write_to_port(ADDRESS_PORT, ENABLE);
+            let present = u32::read_from_port(ADDRESS_PORT) == ENABLE;
+            u32::write_to_port(ADDRESS_PORT, old);
+            present
+        }
+    })
+}
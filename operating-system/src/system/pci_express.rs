@@ -0,0 +1,374 @@
+extern crate alloc;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+use log::debug;
+
+use super::pci_config::{self, read_u16, read_u32, read_u8, write_u16, write_u32, write_u8};
+use super::Error;
+
+/// Offsets of the fields every configuration space header has
+const VENDOR_ID: u16 = 0x00;
+const DEVICE_ID: u16 = 0x02;
+const COMMAND: u16 = 0x04;
+const STATUS: u16 = 0x06;
+const REVISION: u16 = 0x08;
+const PROG_IF: u16 = 0x09;
+const SUBCLASS: u16 = 0x0A;
+const CLASS: u16 = 0x0B;
+const HEADER_TYPE: u16 = 0x0E;
+const BAR0: u16 = 0x10;
+const CAPABILITIES: u16 = 0x34;
+const INTERRUPT_LINE: u16 = 0x3C;
+const INTERRUPT_PIN: u16 = 0x3D;
+
+/// The secondary bus number of a PCI-to-PCI bridge
+const SECONDARY_BUS: u16 = 0x19;
+
+/// Where the extended capabilities of a PCIe function start
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+
+/// The command register bits that make a function decode its BARs and
+/// let it start transactions of its own
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// Set in the status register when the function has a capabilities list
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Set in the header type of function 0 when the device has more functions
+const MULTIFUNCTION: u8 = 1 << 7;
+
+/// Header types
+const HEADER_GENERAL: u8 = 0;
+const HEADER_BRIDGE: u8 = 1;
+
+/// Read back from the vendor id of a function that does not exist
+const NO_VENDOR: u16 = 0xFFFF;
+
+/// The bus, device and function of a PCI function within its segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register, with the size of the range found
+/// by writing all ones to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+
+        /// Whether the BAR also takes up the next register to hold the
+        /// upper half of a 64 bit address
+        wide: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+/// An entry in the capabilities list or the extended capabilities list
+/// of a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u16,
+
+    /// Where the capability is in the configuration space
+    pub offset: u16,
+}
+
+/// A PCI function found on one of the buses
+#[derive(Debug)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+
+    /// The layout of the header, without the multifunction bit
+    pub header_type: u8,
+    pub interrupt_line: u8,
+
+    /// The legacy interrupt pin the function uses, 1 to 4 for INTA to
+    /// INTD, or 0 if it uses none
+    pub interrupt_pin: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    pub extended_capabilities: Vec<Capability>,
+
+    /// The bus behind the function if it is a PCI-to-PCI bridge
+    pub secondary_bus: Option<u8>,
+
+    /// The bridge the function is behind, or None if it is on a root bus
+    pub parent: Option<PciAddress>,
+}
+impl PciDevice {
+    /// Checks whether the function is a PCI-to-PCI bridge
+    pub fn is_bridge(&self) -> bool { self.header_type == HEADER_BRIDGE }
+
+    /// Finds a capability in the capabilities list by its id
+    pub fn capability(&self, id: u8) -> Option<u16> {
+        self.capabilities.iter()
+            .find(|capability| capability.id == id as u16)
+            .map(|capability| capability.offset)
+    }
+
+    /// Finds a capability in the extended capabilities list by its id
+    pub fn extended_capability(&self, id: u16) -> Option<u16> {
+        self.extended_capabilities.iter()
+            .find(|capability| capability.id == id)
+            .map(|capability| capability.offset)
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 { read_u8(self.address, offset) }
+    pub fn read_u16(&self, offset: u16) -> u16 { read_u16(self.address, offset) }
+    pub fn read_u32(&self, offset: u16) -> u32 { read_u32(self.address, offset) }
+    pub fn write_u8(&self, offset: u16, value: u8) { write_u8(self.address, offset, value) }
+    pub fn write_u16(&self, offset: u16, value: u16) { write_u16(self.address, offset, value) }
+    pub fn write_u32(&self, offset: u16, value: u32) { write_u32(self.address, offset, value) }
+
+    /// Lets the function decode its memory BARs and master the bus, which
+    /// it needs to do DMA or send MSIs
+    pub fn enable_bus_master(&self) {
+        let command = self.read_u16(COMMAND);
+        self.write_u16(COMMAND, command | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    }
+}
+
+static mut DEVICES: Vec<PciDevice> = Vec::new();
+
+/// Walks every bus whose configuration space can be reached, following
+/// PCI-to-PCI bridges, and adds the functions found to the device registry
+pub fn enumerate() -> Result<(), Error> {
+    let ranges = pci_config::bus_ranges();
+    if ranges.is_empty() {
+        return Err(Error::NoPciConfigSpace);
+    }
+
+    let mut scan = Scan { devices: Vec::new(), scanned: BTreeSet::new() };
+    for &(segment, start_bus, end_bus) in ranges.iter() {
+        debug!("Scanning PCI segment {}", segment);
+        let range = BusRange { segment, start_bus, end_bus };
+
+        // Start at the root bus so that buses behind bridges get their
+        // parent. Any other buses with devices on them belong to other
+        // host bridges.
+        scan.bus(&range, start_bus, None);
+        for bus in start_bus..=end_bus {
+            scan.bus(&range, bus, None);
+        }
+    }
+
+    debug!("Found {} PCI functions", scan.devices.len());
+    unsafe { DEVICES = scan.devices };
+    Ok(())
+}
+
+/// Gets every function in the registry
+pub fn devices() -> &'static [PciDevice] {
+    unsafe { &DEVICES }
+}
+
+/// Finds the functions with a vendor and device id
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> impl Iterator<Item = &'static PciDevice> {
+    devices().iter()
+        .filter(move |device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// Finds the functions of a class and subclass, like 0x01 and 0x08 for
+/// NVMe controllers
+pub fn find_by_class(class: u8, subclass: u8) -> impl Iterator<Item = &'static PciDevice> {
+    devices().iter()
+        .filter(move |device| device.class == class && device.subclass == subclass)
+}
+
+/// The buses of a segment that can be scanned
+struct BusRange {
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+/// The state of an enumeration
+struct Scan {
+    devices: Vec<PciDevice>,
+
+    /// The segments and buses that have been scanned, so that a bus is
+    /// never scanned twice even if bridges are misconfigured
+    scanned: BTreeSet<(u16, u8)>,
+}
+impl Scan {
+    fn bus(&mut self, range: &BusRange, bus: u8, parent: Option<PciAddress>) {
+        if !(range.start_bus..=range.end_bus).contains(&bus)
+            || !self.scanned.insert((range.segment, bus))
+        {
+            return;
+        }
+
+        for device in 0..32 {
+            for function in 0..8 {
+                let address = PciAddress { segment: range.segment, bus, device, function };
+                if read_u16(address, VENDOR_ID) == NO_VENDOR {
+                    if function == 0 {
+                        break;
+                    }
+                    continue;
+                }
+
+                let header_type = read_u8(address, HEADER_TYPE);
+                let found = probe(address, header_type & !MULTIFUNCTION, parent);
+                debug!("Found {} {:04x}:{:04x} class {:02x}:{:02x}",
+                    address, found.vendor_id, found.device_id, found.class, found.subclass);
+
+                let secondary_bus = found.secondary_bus;
+                self.devices.push(found);
+                if let Some(secondary_bus) = secondary_bus {
+                    self.bus(range, secondary_bus, Some(address));
+                }
+
+                if function == 0 && header_type & MULTIFUNCTION == 0 {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the header, BARs and capabilities of a function
+fn probe(address: PciAddress, header_type: u8, parent: Option<PciAddress>) -> PciDevice {
+    let bar_count = match header_type {
+        HEADER_GENERAL => 6,
+        HEADER_BRIDGE => 2,
+        _ => 0,
+    };
+    PciDevice {
+        address,
+        vendor_id: read_u16(address, VENDOR_ID),
+        device_id: read_u16(address, DEVICE_ID),
+        class: read_u8(address, CLASS),
+        subclass: read_u8(address, SUBCLASS),
+        prog_if: read_u8(address, PROG_IF),
+        revision: read_u8(address, REVISION),
+        header_type,
+        interrupt_line: read_u8(address, INTERRUPT_LINE),
+        interrupt_pin: read_u8(address, INTERRUPT_PIN),
+        bars: read_bars(address, bar_count),
+        capabilities: capabilities(address),
+        extended_capabilities: extended_capabilities(address),
+        secondary_bus: match header_type {
+            HEADER_BRIDGE => Some(read_u8(address, SECONDARY_BUS)),
+            _ => None,
+        },
+        parent,
+    }
+}
+
+/// Decodes the first `count` BARs and finds their sizes by writing all
+/// ones to them and reading back which address bits stuck. Decoding is
+/// turned off meanwhile so the function does not claim the addresses
+/// the BARs briefly hold.
+fn read_bars(address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = read_u16(address, COMMAND);
+    write_u16(address, COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+
+    let mut i = 0;
+    while i < count {
+        let offset = BAR0 + i as u16 * 4;
+        let (value, mask) = size_register(address, offset);
+
+        if value & 1 != 0 {
+            // Io BARs may only implement the lower 16 bits
+            let mask = mask & !0x3;
+            let mask = if mask & 0xFFFF_0000 == 0 { mask | 0xFFFF_0000 } else { mask };
+            if mask & 0xFFFF != 0 {
+                bars[i] = Some(Bar::Io { port: value & !0x3, size: (!mask).wrapping_add(1) });
+            }
+        } else {
+            let wide = (value >> 1) & 0b11 == 0b10 && i + 1 < count;
+            let (address, mask) = if wide {
+                let (upper, upper_mask) = size_register(address, offset + 4);
+                (
+                    (value & !0xF) as u64 | (upper as u64) << 32,
+                    (mask & !0xF) as u64 | (upper_mask as u64) << 32,
+                )
+            } else {
+                ((value & !0xF) as u64, (mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000)
+            };
+            let implemented = if wide { mask != 0 } else { mask & 0xFFFF_FFFF != 0 };
+            if implemented {
+                bars[i] = Some(Bar::Memory {
+                    address,
+                    size: (!mask).wrapping_add(1),
+                    prefetchable: value & (1 << 3) != 0,
+                    wide,
+                });
+            }
+            if wide {
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+
+    write_u16(address, COMMAND, command);
+    bars
+}
+
+/// Writes all ones to a BAR and restores it, returning its value and
+/// the bits that could be set
+fn size_register(address: PciAddress, offset: u16) -> (u32, u32) {
+    let value = read_u32(address, offset);
+    write_u32(address, offset, 0xFFFF_FFFF);
+    let mask = read_u32(address, offset);
+    write_u32(address, offset, value);
+    (value, mask)
+}
+
+/// Walks the capabilities list. The list can hold at most 48 entries, so
+/// a looping list stops there.
+fn capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if read_u16(address, STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    let mut offset = (read_u8(address, CAPABILITIES) & !0x3) as u16;
+    while offset != 0 && capabilities.len() < 48 {
+        capabilities.push(Capability { id: read_u8(address, offset) as u16, offset });
+        offset = (read_u8(address, offset + 1) & !0x3) as u16;
+    }
+    capabilities
+}
+
+/// Walks the extended capabilities list, which only PCIe functions have
+fn extended_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    let mut offset = EXTENDED_CAPABILITIES;
+    while offset >= EXTENDED_CAPABILITIES && capabilities.len() < 960 {
+        let header = read_u32(address, offset);
+        if header == 0 || header == 0xFFFF_FFFF {
+            break;
+        }
+        capabilities.push(Capability { id: header as u16, offset });
+        offset = ((header >> 20) & 0xFFC) as u16;
+    }
+    capabilities
+}
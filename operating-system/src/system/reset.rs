@@ -0,0 +1,106 @@
+use log::info;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use super::{acpi_methods, acpi_registers, Error};
+
+/// The offset of the flags in the FADT, which the acpi crate does not
+/// have a getter for the reset bit of
+const FADT_FLAGS_OFFSET: usize = 112;
+
+/// Set in the FADT flags when the reset register is supported
+const RESET_REG_SUP: u32 = 1 << 10;
+
+/// The status and command port of the 8042 keyboard controller
+const PS2_COMMAND: u16 = 0x64;
+
+/// The 8042 command that pulses the cpu reset line
+const PS2_PULSE_RESET: u8 = 0xFE;
+
+/// The reset control register of the chipset
+const RESET_CONTROL: u16 = 0xCF9;
+
+/// How long to wait for a reset method to take effect before trying
+/// the next one
+const RESET_WAIT: usize = 10_000_000;
+
+/// Restarts the machine, trying the acpi reset register, the keyboard
+/// controller, the chipset's reset control register and finally a
+/// triple fault. The last method logged is the one that worked.
+pub fn reboot() -> ! {
+    interrupts::disable();
+
+    info!("Resetting using the ACPI reset register");
+    match acpi_reset() {
+        Ok(()) => wait(),
+        Err(err) => info!("Could not use the ACPI reset register: {:?}", err),
+    }
+
+    info!("Resetting using the keyboard controller");
+    unsafe {
+        let mut command = Port::<u8>::new(PS2_COMMAND);
+
+        // Wait for the input buffer to be empty
+        for _ in 0..RESET_WAIT {
+            if command.read() & 0b10 == 0 {
+                break;
+            }
+        }
+        command.write(PS2_PULSE_RESET);
+    }
+    wait();
+
+    info!("Resetting using the reset control register");
+    unsafe {
+        let mut reset = Port::<u8>::new(RESET_CONTROL);
+
+        // Select a hard reset, then start it
+        reset.write(0x02);
+        wait();
+        reset.write(0x06);
+    }
+    wait();
+
+    info!("Resetting using a triple fault");
+    triple_fault()
+}
+
+/// Writes the reset value to the reset register in the FADT
+fn acpi_reset() -> Result<(), Error> {
+    let fadt = acpi_methods::fadt()?;
+    let flags = unsafe {
+        ((fadt as *const _ as *const u8).add(FADT_FLAGS_OFFSET) as *const u32)
+            .read_unaligned()
+    };
+    if flags & RESET_REG_SUP == 0 {
+        return Err(Error::ResetNotSupported);
+    }
+
+    let reg = fadt.reset_register()?;
+    if reg.address == 0 {
+        return Err(Error::ResetNotSupported);
+    }
+    acpi_registers::write(&reg, fadt.reset_value as u64)
+}
+
+/// Loads an empty IDT and raises an exception, which can not be handled
+/// and turns into a triple fault that resets the cpu
+fn triple_fault() -> ! {
+    unsafe {
+        let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+        x86_64::instructions::tables::lidt(&idt);
+        asm!("int3", options(nomem, nostack));
+    }
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Gives a reset method time to take effect
+fn wait() {
+    for _ in 0..RESET_WAIT {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,343 @@
+use core::ptr::{addr_of, addr_of_mut};
+
+use acpi::InterruptModel;
+use log::debug;
+use uefi::table::boot::{AllocateType, MemoryType};
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr0, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+
+use super::{acpi_methods, apic, gdt, syscall, Error};
+use crate::drivers::{self, serial};
+use crate::ST;
+
+/// The firmware jumps to the waking vector in real mode, so the
+/// trampoline has to be below 1 MiB
+const TRAMPOLINE_MAX_ADDRESS: usize = 0xF_FFFF;
+
+/// The long mode active bit of EFER, which is read only
+const EFER_LMA: u64 = 1 << 10;
+
+/// Process context identifiers can only be enabled in long mode
+const CR4_PCIDE: u64 = 1 << 17;
+
+/// The firmware ACPI control structure, which holds the address that
+/// the firmware jumps to when the system wakes up
+#[repr(C, packed)]
+struct Facs {
+    signature: [u8; 4],
+    length: u32,
+    hardware_signature: u32,
+    firmware_waking_vector: u32,
+    global_lock: u32,
+    flags: u32,
+    x_firmware_waking_vector: u64,
+    version: u8,
+    reserved: [u8; 3],
+    ospm_flags: u32,
+}
+
+/// The physical address of the copy of the trampoline below 1 MiB
+static mut TRAMPOLINE: Option<u64> = None;
+
+/// The kernel stack pointer saved by `wakeup_suspend`, after pushing the
+/// callee saved registers
+#[no_mangle]
+static mut WAKEUP_RSP: u64 = 0;
+
+/// The sleep type values used by `enter_s3`
+static mut SLEEP_TYPES: (u64, u64) = (0, 0);
+
+/// The error from entering S3, if `enter_s3` returned
+static mut SLEEP_ERROR: Option<Error> = None;
+
+extern "C" {
+    /// Saves the callee saved registers and the stack pointer, then calls
+    /// `enter`. Returns what `enter` returns if the system did not go to
+    /// sleep, and 1 when it resumes through the trampoline.
+    fn wakeup_suspend(enter: extern "C" fn() -> u64) -> u64;
+
+    /// Where the trampoline jumps to in long mode
+    fn wakeup_resume();
+
+    static wakeup_trampoline: u8;
+    static wakeup_trampoline_end: u8;
+    static wakeup_gdtr_base: u8;
+    static wakeup_gdt: u8;
+    static wakeup_cr0: u8;
+    static wakeup_cr3: u8;
+    static wakeup_cr4: u8;
+    static wakeup_efer: u8;
+    static wakeup_long_mode_target: u8;
+    static wakeup_long_mode: u8;
+    static wakeup_rsp_address: u8;
+    static wakeup_resume_address: u8;
+}
+
+// The firmware starts the trampoline in real mode with cs:ip pointing
+// at its first byte, so it only uses addresses relative to the start of
+// the trampoline until it is in long mode. It goes to long mode directly
+// by enabling PAE, loading the kernel's page tables and setting the
+// protection and paging bits of cr0 at the same time, then jumps to a
+// 64 bit code segment of its own GDT with a far jump whose target is
+// the long mode code in the copy. That target and the fields at the end
+// of the trampoline are filled in by `install_trampoline` and
+// `save_state`.
+global_asm!(
+    "
+    .intel_syntax noprefix
+    .global wakeup_suspend
+    wakeup_suspend:
+        push rbp
+        push rbx
+        push r12
+        push r13
+        push r14
+        push r15
+        sub rsp, 8
+        mov qword ptr [rip + WAKEUP_RSP], rsp
+        call rdi
+        add rsp, 8
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+        ret
+
+    .global wakeup_resume
+    wakeup_resume:
+        add rsp, 8
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+        mov eax, 1
+        ret
+
+    .global wakeup_trampoline
+    .global wakeup_trampoline_end
+    .global wakeup_gdtr_base
+    .global wakeup_gdt
+    .global wakeup_cr0
+    .global wakeup_cr3
+    .global wakeup_cr4
+    .global wakeup_efer
+    .global wakeup_long_mode_target
+    .global wakeup_long_mode
+    .global wakeup_rsp_address
+    .global wakeup_resume_address
+
+    .balign 16
+    .code16
+    wakeup_trampoline:
+        cli
+        cld
+        mov ax, cs
+        mov ds, ax
+        lgdt [wakeup_gdtr - wakeup_trampoline]
+        mov eax, dword ptr [wakeup_cr4 - wakeup_trampoline]
+        mov cr4, eax
+        mov eax, dword ptr [wakeup_cr3 - wakeup_trampoline]
+        mov cr3, eax
+        mov ecx, 0xC0000080
+        mov eax, dword ptr [wakeup_efer - wakeup_trampoline]
+        mov edx, dword ptr [wakeup_efer - wakeup_trampoline + 4]
+        wrmsr
+        mov eax, dword ptr [wakeup_cr0 - wakeup_trampoline]
+        mov cr0, eax
+        .byte 0x66, 0xEA
+    wakeup_long_mode_target:
+        .long 0
+        .word 0x08
+
+    .code64
+    wakeup_long_mode:
+        mov ax, 0x10
+        mov ds, ax
+        mov es, ax
+        mov fs, ax
+        mov gs, ax
+        mov ss, ax
+        mov rax, qword ptr [rip + wakeup_rsp_address]
+        mov rsp, qword ptr [rax]
+        jmp qword ptr [rip + wakeup_resume_address]
+
+    .balign 8
+    wakeup_gdt:
+        .quad 0
+        .quad 0x00AF9A000000FFFF
+        .quad 0x00CF92000000FFFF
+    wakeup_gdtr:
+        .word 23
+    wakeup_gdtr_base:
+        .long 0
+    .balign 8
+    wakeup_cr0:
+        .quad 0
+    wakeup_cr3:
+        .quad 0
+    wakeup_cr4:
+        .quad 0
+    wakeup_efer:
+        .quad 0
+    wakeup_rsp_address:
+        .quad 0
+    wakeup_resume_address:
+        .quad 0
+    wakeup_trampoline_end:
+    "
+);
+
+/// Suspends the system to RAM (S3) and returns once it has woken up
+///
+/// Memory keeps its contents in S3 but the cpu and the devices lose
+/// their state, so the control registers are saved in the trampoline and
+/// everything else (the GDT, IDT, system call msrs, apics, the serial
+/// port and the keyboard controller) is set up again after waking up.
+/// The PIT is left alone, since the kernel only uses it to calibrate the
+/// apic timer at boot.
+pub fn suspend() -> Result<(), Error> {
+    let trampoline = install_trampoline()?;
+
+    debug!("Setting the firmware waking vector to {:#x}", trampoline);
+    set_waking_vector(trampoline)?;
+
+    let sleep_types = acpi_methods::prepare_sleep(3)?;
+
+    interrupts::disable();
+    unsafe {
+        save_state(trampoline)?;
+        SLEEP_TYPES = sleep_types;
+        SLEEP_ERROR = None;
+    }
+
+    debug!("Entering S3");
+    if unsafe { wakeup_suspend(enter_s3) } != 1 {
+        interrupts::enable();
+        return Err(unsafe { SLEEP_ERROR.take() }.unwrap_or(Error::SleepFailed));
+    }
+
+    debug!("Restoring the cpu state");
+    restore_state();
+
+    debug!("Restoring the device state");
+    serial::resume_port();
+    drivers::resume();
+    interrupts::enable();
+
+    debug!("Running the wake methods");
+    acpi_methods::wakeup(3)
+}
+
+/// Enters S3 and only returns if that failed
+extern "C" fn enter_s3() -> u64 {
+    unsafe {
+        // Nothing in the caches survives S3
+        asm!("wbinvd", options(nostack, preserves_flags));
+
+        if let Err(err) = acpi_methods::enter_sleep(SLEEP_TYPES) {
+            SLEEP_ERROR = Some(err);
+            return 0;
+        }
+    }
+
+    // The cpu stops shortly after SLP_EN is set
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+    0
+}
+
+/// Copies the trampoline below 1 MiB the first time it is needed and
+/// returns its physical address
+fn install_trampoline() -> Result<u64, Error> {
+    if let Some(addr) = unsafe { TRAMPOLINE } {
+        return Ok(addr);
+    }
+
+    let start = unsafe { addr_of!(wakeup_trampoline) } as usize;
+    let len = unsafe { addr_of!(wakeup_trampoline_end) } as usize - start;
+
+    debug!("Allocating a page for the wakeup trampoline");
+    let st = unsafe { ST.as_ref() }.ok_or(Error::NoTrampolineMemory)?;
+    let addr = st.boot_services()
+        .allocate_pages(
+            AllocateType::MaxAddress(TRAMPOLINE_MAX_ADDRESS),
+            MemoryType::LOADER_CODE,
+            1,
+        )
+        .map_err(|_| Error::NoTrampolineMemory)?
+        .split().1;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(start as *const u8, addr as *mut u8, len);
+        patch(addr, addr_of!(wakeup_gdtr_base), (addr + offset(addr_of!(wakeup_gdt))) as u32);
+        patch(addr, addr_of!(wakeup_long_mode_target),
+            (addr + offset(addr_of!(wakeup_long_mode))) as u32);
+        patch(addr, addr_of!(wakeup_rsp_address), addr_of!(WAKEUP_RSP) as u64);
+        patch(addr, addr_of!(wakeup_resume_address), wakeup_resume as usize as u64);
+        TRAMPOLINE = Some(addr);
+    }
+    Ok(addr)
+}
+
+/// Saves the control registers and EFER into the trampoline
+///
+/// The trampoline loads cr3 while still in real mode, so the page tables
+/// have to be below 4 GiB.
+unsafe fn save_state(trampoline: u64) -> Result<(), Error> {
+    let (pml4, _) = Cr3::read_raw();
+    let cr3 = pml4.start_address().as_u64();
+    if cr3 > u32::MAX as u64 {
+        return Err(Error::PageTableTooHigh);
+    }
+
+    patch(trampoline, addr_of!(wakeup_cr0), Cr0::read_raw());
+    patch(trampoline, addr_of!(wakeup_cr3), cr3);
+    patch(trampoline, addr_of!(wakeup_cr4), Cr4::read_raw() & !CR4_PCIDE);
+    patch(trampoline, addr_of!(wakeup_efer), Efer::read_raw() & !EFER_LMA);
+    Ok(())
+}
+
+/// Sets up the parts of the cpu that the trampoline does not restore
+fn restore_state() {
+    unsafe { gdt::reload() };
+    super::interrupts::enable();
+    syscall::init();
+
+    match acpi_methods::interrupt_model() {
+        Some(InterruptModel::Apic(model)) => apic::resume(model),
+        _ => debug!("No APIC to restore"),
+    }
+}
+
+/// Points the firmware waking vector in the FACS at the trampoline
+fn set_waking_vector(trampoline: u64) -> Result<(), Error> {
+    let facs = acpi_methods::fadt()?.facs_address()? as *mut Facs;
+    unsafe {
+        if (*facs).signature != *b"FACS" {
+            return Err(Error::NoFacs);
+        }
+
+        // The 64 bit vector takes precedence when it is not 0
+        addr_of_mut!((*facs).firmware_waking_vector).write_unaligned(trampoline as u32);
+        if (*facs).length as usize >= core::mem::size_of::<Facs>() {
+            addr_of_mut!((*facs).x_firmware_waking_vector).write_unaligned(0);
+        }
+    }
+    Ok(())
+}
+
+/// Gets the offset of a symbol from the start of the trampoline
+fn offset(symbol: *const u8) -> u64 {
+    symbol as u64 - unsafe { addr_of!(wakeup_trampoline) } as u64
+}
+
+/// Writes a value to the copy of the trampoline at the offset of a symbol
+unsafe fn patch<T>(trampoline: u64, symbol: *const u8, value: T) {
+    ((trampoline + offset(symbol)) as *mut T).write_unaligned(value);
+}
@@ -0,0 +1,153 @@
+use log::{debug, trace};
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+
+use super::gdt;
+use crate::syscalls;
+
+/// The registers saved by the system call entry stubs. Both the
+/// SYSCALL instruction and the `int 0x80` fallback build this frame
+/// on the kernel stack before calling `syscall_dispatch`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    /// The system call number
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+
+    /// The user's instruction pointer (saved by SYSCALL)
+    pub rcx: u64,
+
+    /// The user's rflags (saved by SYSCALL)
+    pub r11: u64,
+
+    /// The user's stack pointer
+    pub rsp: u64,
+}
+
+/// The top of the kernel stack that SYSCALL switches to. This is a
+/// copy of the ring 0 stack in the TSS.
+#[no_mangle]
+static mut SYSCALL_KERNEL_RSP: u64 = 0;
+
+/// Scratch space for the user's stack pointer while switching stacks
+#[no_mangle]
+static mut SYSCALL_USER_RSP: u64 = 0;
+
+/// Enables the SYSCALL/SYSRET instructions
+pub fn init() {
+    let selectors = gdt::selectors();
+
+    debug!("Setting the syscall kernel stack");
+    unsafe { SYSCALL_KERNEL_RSP = gdt::kernel_stack().as_u64() };
+
+    debug!("Writing the STAR, LSTAR and FMASK msrs");
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .expect("Invalid segment selectors for SYSCALL");
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+
+    // Interrupts stay disabled until the kernel stack has been set up
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG);
+
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+/// Sets the stack that system calls and interrupts from ring 3 switch
+/// to, both for SYSCALL and in the TSS
+///
+/// This is unsafe for the same reasons as `gdt::set_kernel_stack`
+pub unsafe fn set_kernel_stack(stack: VirtAddr) {
+    gdt::set_kernel_stack(stack);
+    SYSCALL_KERNEL_RSP = stack.as_u64();
+}
+
+extern "C" {
+    /// The entry point of the SYSCALL instruction
+    fn syscall_entry();
+
+    /// The entry point of the `int 0x80` fallback
+    pub fn syscall_int80_entry();
+}
+
+// SYSCALL leaves the user's stack in place, so the stub switches to the
+// kernel stack before saving anything. `int 0x80` already had its stack
+// switched by the cpu using the TSS, so it only pads the stack to keep
+// it 16 byte aligned, saves registers and returns with iretq.
+global_asm!(
+    "
+    .intel_syntax noprefix
+    .global syscall_entry
+    syscall_entry:
+        mov qword ptr [rip + SYSCALL_USER_RSP], rsp
+        mov rsp, qword ptr [rip + SYSCALL_KERNEL_RSP]
+        push qword ptr [rip + SYSCALL_USER_RSP]
+        push r11
+        push rcx
+        push r9
+        push r8
+        push r10
+        push rdx
+        push rsi
+        push rdi
+        push rax
+        mov rdi, rsp
+        call syscall_dispatch
+        add rsp, 8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop r10
+        pop r8
+        pop r9
+        pop rcx
+        pop r11
+        pop rsp
+        sysretq
+
+    .global syscall_int80_entry
+    syscall_int80_entry:
+        sub rsp, 8
+        push qword ptr [rsp + 32]
+        push r11
+        push rcx
+        push r9
+        push r8
+        push r10
+        push rdx
+        push rsi
+        push rdi
+        push rax
+        mov rdi, rsp
+        call syscall_dispatch
+        add rsp, 8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop r10
+        pop r8
+        pop r9
+        pop rcx
+        pop r11
+        add rsp, 16
+        iretq
+    "
+);
+
+/// Called by both entry stubs with the saved user registers. The
+/// return value is placed in rax when returning to the user.
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
+    trace!("syscall {}: {:#x?}", frame.rax, frame);
+    syscalls::dispatch(frame)
+}
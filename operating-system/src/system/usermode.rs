@@ -0,0 +1,155 @@
+use log::debug;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::mapper::MapToError;
+
+use super::gdt;
+use crate::memory::paging;
+
+/// The kernel stack pointer saved when entering user mode. Exiting
+/// user mode restores it, returning from `usermode_enter`.
+#[no_mangle]
+static mut USERMODE_KERNEL_RSP: u64 = 0;
+
+extern "C" {
+    fn usermode_enter(entry: u64, stack: u64, cs: u64, ss: u64) -> u64;
+    fn usermode_exit(code: u64) -> !;
+}
+
+// usermode_enter saves the callee-saved registers and the stack pointer,
+// then builds an interrupt frame and uses iretq to drop to ring 3.
+// usermode_exit unwinds back to the saved stack and returns `code` from
+// usermode_enter.
+global_asm!(
+    "
+    .intel_syntax noprefix
+    .global usermode_enter
+    usermode_enter:
+        push rbx
+        push rbp
+        push r12
+        push r13
+        push r14
+        push r15
+        mov qword ptr [rip + USERMODE_KERNEL_RSP], rsp
+        push rcx
+        push rsi
+        push 0x202
+        push rdx
+        push rdi
+        xor eax, eax
+        xor ebx, ebx
+        xor ecx, ecx
+        xor edx, edx
+        xor esi, esi
+        xor edi, edi
+        xor ebp, ebp
+        xor r8d, r8d
+        xor r9d, r9d
+        xor r10d, r10d
+        xor r11d, r11d
+        xor r12d, r12d
+        xor r13d, r13d
+        xor r14d, r14d
+        xor r15d, r15d
+        iretq
+
+    .global usermode_exit
+    usermode_exit:
+        mov rsp, qword ptr [rip + USERMODE_KERNEL_RSP]
+        mov rax, rdi
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbp
+        pop rbx
+        ret
+    "
+);
+
+/// Runs code in ring 3 until it makes the exit system call, then
+/// returns the exit code. Can be called from a system call handler to
+/// run another program while the caller waits.
+///
+/// This is unsafe because `entry` and `stack` must be mapped as user
+/// accessible in the active page table
+pub unsafe fn run(entry: VirtAddr, stack: VirtAddr) -> u64 {
+    let selectors = gdt::selectors();
+    let enabled = interrupts::are_enabled();
+
+    // Keep the stack of an outer call to run so that it can still exit
+    let outer = USERMODE_KERNEL_RSP;
+
+    let code = usermode_enter(
+        entry.as_u64(),
+        stack.as_u64(),
+        selectors.user_code.0 as u64,
+        selectors.user_data.0 as u64,
+    );
+
+    USERMODE_KERNEL_RSP = outer;
+
+    // The system call that exited left interrupts masked
+    if enabled {
+        interrupts::enable();
+    }
+    code
+}
+
+/// The exit code of a program that was stopped because it faulted
+pub const FAULT_EXIT_CODE: u64 = u64::MAX;
+
+/// Leaves user mode, returning `code` from the call to `run` that
+/// entered it. Must be called from a system call handler, or from the
+/// handler of an exception raised in user mode.
+pub fn exit(code: u64) -> ! { unsafe { usermode_exit(code) } }
+
+/// Where the test program's code is mapped
+const TEST_CODE_ADDR: u64 = 0x7000_0000_0000;
+
+/// Where the test program's stack is mapped
+const TEST_STACK_ADDR: u64 = 0x7000_0001_0000;
+
+/// A tiny program that writes a message to the console with each
+/// system call ABI and exits
+#[rustfmt::skip]
+const TEST_PROGRAM: [u8; 90] = [
+    0x48, 0xC7, 0xC0, 0x01, 0x00, 0x00, 0x00, // mov rax, 1 (write)
+    0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00, // mov rdi, 1 (stdout)
+    0x48, 0x8D, 0x35, 0x32, 0x00, 0x00, 0x00, // lea rsi, [rip + message]
+    0x48, 0xC7, 0xC2, 0x13, 0x00, 0x00, 0x00, // mov rdx, 19
+    0x0F, 0x05,                               // syscall
+    0x48, 0xC7, 0xC0, 0x01, 0x00, 0x00, 0x00, // mov rax, 1 (write)
+    0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00, // mov rdi, 1 (stdout)
+    0x48, 0x8D, 0x35, 0x14, 0x00, 0x00, 0x00, // lea rsi, [rip + message]
+    0x48, 0xC7, 0xC2, 0x13, 0x00, 0x00, 0x00, // mov rdx, 19
+    0xCD, 0x80,                               // int 0x80
+    0x48, 0xC7, 0xC0, 0x00, 0x00, 0x00, 0x00, // mov rax, 0 (exit)
+    0x31, 0xFF,                               // xor edi, edi
+    0x0F, 0x05,                               // syscall
+    // message: "Hello from ring 3!\n"
+    b'H', b'e', b'l', b'l', b'o', b' ', b'f', b'r', b'o', b'm', b' ',
+    b'r', b'i', b'n', b'g', b' ', b'3', b'!', b'\n',
+];
+
+/// Maps and runs TEST_PROGRAM in ring 3
+pub fn test() -> Result<u64, MapToError<Size4KiB>> {
+    let code_page = Page::containing_address(VirtAddr::new(TEST_CODE_ADDR));
+    let stack_page = Page::containing_address(VirtAddr::new(TEST_STACK_ADDR));
+
+    debug!("Mapping the user mode test program");
+    let code_frame = paging::map_user_page(code_page, PageTableFlags::empty())?;
+    paging::map_user_page(stack_page, PageTableFlags::WRITABLE)?;
+
+    // Copy the program into its frame
+    unsafe {
+        let dst = paging::phys_to_virt(code_frame.start_address()).as_mut_ptr::<u8>();
+        core::ptr::copy_nonoverlapping(TEST_PROGRAM.as_ptr(), dst, TEST_PROGRAM.len());
+    }
+
+    debug!("Entering user mode");
+    let stack_top = stack_page.start_address() + stack_page.size();
+    Ok(unsafe { run(code_page.start_address(), stack_top) })
+}
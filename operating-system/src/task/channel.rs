@@ -0,0 +1,94 @@
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::AtomicWaker;
+
+/// The state shared between the senders and the receiver of a channel
+struct Inner<T> {
+    queue: Mutex<VecDeque<T>>,
+    waker: AtomicWaker,
+    senders: AtomicUsize,
+}
+
+/// Creates an unbounded multi-producer, single-consumer channel
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        queue: Mutex::new(VecDeque::new()),
+        waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+/// The sending half of a channel. Can be cloned to create more senders.
+pub struct Sender<T>(Arc<Inner<T>>);
+impl<T> Sender<T> {
+    /// Pushes a value onto the channel and wakes the receiver
+    pub fn send(&self, value: T) {
+        interrupts::without_interrupts(|| self.0.queue.lock().push_back(value));
+        self.0.waker.wake();
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
+        Sender(self.0.clone())
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Wake the receiver when the last sender goes away so that it
+        // can observe that the channel is closed
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.waker.wake();
+        }
+    }
+}
+
+/// The receiving half of a channel
+pub struct Receiver<T>(Arc<Inner<T>>);
+impl<T> Receiver<T> {
+    /// Takes a value off of the channel without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        interrupts::without_interrupts(|| self.0.queue.lock().pop_front())
+    }
+
+    /// Waits for the next value on the channel. Resolves to None once
+    /// every sender has been dropped and the channel is empty.
+    pub fn recv(&mut self) -> Recv<'_, T> { Recv { receiver: self } }
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+impl<'a, T> Future for Recv<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let inner = &self.receiver.0;
+        if let Some(value) = self.receiver.try_recv() {
+            return Poll::Ready(Some(value));
+        }
+
+        // Register before checking again so a send between the two
+        // checks still wakes this task
+        inner.waker.register(cx.waker());
+        if let Some(value) = self.receiver.try_recv() {
+            return Poll::Ready(Some(value));
+        }
+
+        if inner.senders.load(Ordering::Acquire) == 0 {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
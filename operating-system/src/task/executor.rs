@@ -0,0 +1,216 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{channel, timer, Task, TaskId};
+
+/// The maximum number of tasks that can be waiting to be polled
+const QUEUE_CAPACITY: usize = 256;
+
+/// Tasks spawned with `spawn` that have not yet been picked up
+/// by the executor
+static SPAWN_QUEUE: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+/// The executor that runs the kernel's tasks. Tasks are not `Send`, so
+/// it can not be kept in a Mutex, and `POLLING` guards it instead.
+static mut KERNEL_EXECUTOR: Option<Executor> = None;
+
+/// Set while the kernel executor is polling tasks, so that it is never
+/// entered twice
+static POLLING: AtomicBool = AtomicBool::new(false);
+
+/// Work handed to the main loop by `run_on_main_loop`
+static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+
+/// A function that the main loop runs outside of any task
+struct Job(Box<dyn FnOnce()>);
+
+// There is one cpu, and the job queue is only locked with interrupts
+// disabled
+unsafe impl Send for Job {}
+
+/// Spawns a task onto the kernel executor
+pub fn spawn(future: impl Future<Output = ()> + 'static) {
+    let task = Task::new(future);
+    interrupts::without_interrupts(|| SPAWN_QUEUE.lock().push(task));
+}
+
+/// Polls every kernel task that is ready. Called by the kernel's main
+/// loop, and by system calls that have to wait for a device, so that
+/// the kernel's tasks keep running while a program waits. Does nothing
+/// if a task is already being polled, which is why tasks start programs
+/// with `run_on_main_loop`.
+pub fn run_ready() {
+    if POLLING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let executor = unsafe { KERNEL_EXECUTOR.get_or_insert_with(Executor::new) };
+    executor.poll();
+    POLLING.store(false, Ordering::Release);
+}
+
+/// Runs `f` from the kernel's main loop, outside of any task, and
+/// resolves to what it returns. Used for work that has to let the
+/// kernel's tasks run while it waits, like running a program.
+pub async fn run_on_main_loop<T: 'static>(f: impl FnOnce() -> T + 'static) -> T {
+    let (sender, mut receiver) = channel::channel();
+    let job = Job(Box::new(move || sender.send(f())));
+    interrupts::without_interrupts(|| JOBS.lock().push(job));
+    receiver.recv().await.expect("The main loop dropped a job")
+}
+
+/// Runs the kernel's tasks and the jobs they hand to the main loop
+/// forever, halting the cpu whenever there is no work to be done
+pub fn run() -> ! {
+    loop {
+        run_ready();
+        run_jobs();
+        sleep_if_idle();
+    }
+}
+
+/// Runs every job handed to the main loop, in the order they came in
+fn run_jobs() {
+    let jobs = interrupts::without_interrupts(|| core::mem::take(&mut *JOBS.lock()));
+    for job in jobs {
+        (job.0)();
+    }
+}
+
+/// Halts until the next interrupt if no task is ready to run
+fn sleep_if_idle() {
+    // Interrupts are disabled before checking the queue so that a
+    // wakeup cannot be missed between the check and the hlt
+    interrupts::disable();
+    let idle = match unsafe { KERNEL_EXECUTOR.as_ref() } {
+        Some(executor) => executor.queue.is_empty(),
+        None => true,
+    };
+    if idle && SPAWN_QUEUE.lock().is_empty() && JOBS.lock().is_empty() {
+        interrupts::enable_and_hlt();
+    } else {
+        interrupts::enable();
+    }
+}
+
+/// A queue of tasks that are ready to be polled.
+///
+/// Wakers push to this queue from interrupt handlers, so the queue
+/// never grows past its initial capacity (no allocation happens
+/// inside of an interrupt) and is only ever locked with interrupts
+/// disabled.
+struct TaskQueue(Mutex<VecDeque<TaskId>>);
+impl TaskQueue {
+    fn new() -> TaskQueue {
+        TaskQueue(Mutex::new(VecDeque::with_capacity(QUEUE_CAPACITY)))
+    }
+
+    fn push(&self, id: TaskId) {
+        interrupts::without_interrupts(|| {
+            let mut queue = self.0.lock();
+
+            // A task only needs to be in the queue once
+            if queue.len() < QUEUE_CAPACITY && !queue.contains(&id) {
+                queue.push_back(id);
+            }
+        })
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        interrupts::without_interrupts(|| self.0.lock().pop_front())
+    }
+
+    fn is_empty(&self) -> bool { self.0.lock().is_empty() }
+}
+
+struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    queue: Arc<TaskQueue>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+impl Executor {
+    /// Creates a new Executor object
+    fn new() -> Executor {
+        Executor {
+            tasks: BTreeMap::new(),
+            queue: Arc::new(TaskQueue::new()),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a task to the executor and schedules it to be polled
+    fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("Task with the same ID already exists");
+        }
+        self.queue.push(id);
+    }
+
+    /// Polls every task that has been woken, including tasks that were
+    /// woken by a timer and that were just spawned
+    fn poll(&mut self) {
+        timer::wake_expired();
+        self.spawn_pending();
+        self.run_ready_tasks();
+    }
+
+    /// Moves tasks spawned through the global `spawn` function onto
+    /// this executor
+    fn spawn_pending(&mut self) {
+        let pending = interrupts::without_interrupts(|| {
+            core::mem::take(&mut *SPAWN_QUEUE.lock())
+        });
+        for task in pending {
+            self.spawn(task);
+        }
+    }
+
+    /// Polls every task that has been woken
+    fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.queue.pop() {
+            // The task may have already completed
+            let task = match self.tasks.get_mut(&id) {
+                Some(task) => task,
+                None => continue,
+            };
+
+            let queue = &self.queue;
+            let waker = self
+                .waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::new(id, queue.clone()));
+            let mut context = Context::from_waker(waker);
+
+            // Remove the task once it has finished
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&id);
+                self.waker_cache.remove(&id);
+            }
+        }
+    }
+}
+
+struct TaskWaker {
+    id: TaskId,
+    queue: Arc<TaskQueue>,
+}
+impl TaskWaker {
+    fn new(id: TaskId, queue: Arc<TaskQueue>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, queue }))
+    }
+}
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) { self.queue.push(self.id); }
+
+    fn wake_by_ref(self: &Arc<Self>) { self.queue.push(self.id); }
+}
@@ -0,0 +1,207 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use super::AtomicWaker;
+
+/// The number of scancodes that can be buffered before they are dropped
+const QUEUE_SIZE: usize = 128;
+
+/// The data port of the PS/2 controller
+const PS2_DATA_PORT: u16 = 0x60;
+
+static SCANCODES: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// A fixed size ring buffer so that the interrupt handler never allocates
+struct ScancodeQueue {
+    buffer: [u8; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+impl ScancodeQueue {
+    const fn new() -> ScancodeQueue {
+        ScancodeQueue {
+            buffer: [0; QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, scancode: u8) -> bool {
+        if self.len == QUEUE_SIZE {
+            return false;
+        }
+        self.buffer[(self.head + self.len) % QUEUE_SIZE] = scancode;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let scancode = self.buffer[self.head];
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(scancode)
+    }
+}
+
+/// Called by the keyboard interrupt handler
+pub(crate) fn handle_interrupt() {
+    // Read the scancode from the PS/2 controller
+    let mut port = Port::<u8>::new(PS2_DATA_PORT);
+    let scancode = unsafe { port.read() };
+
+    // The queue is only locked with interrupts disabled, so it is never
+    // contended on a single cpu. Drop the scancode if it is.
+    if let Some(mut queue) = SCANCODES.try_lock() {
+        if queue.push(scancode) {
+            WAKER.wake();
+        }
+    }
+}
+
+/// Waits for the next raw scancode from the keyboard
+pub fn next_scancode() -> NextScancode { NextScancode }
+
+/// Takes the next raw scancode from the keyboard without waiting
+pub fn try_scancode() -> Option<u8> {
+    interrupts::without_interrupts(|| SCANCODES.lock().pop())
+}
+
+pub struct NextScancode;
+impl Future for NextScancode {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<u8> {
+        if let Some(scancode) = try_scancode() {
+            return Poll::Ready(scancode);
+        }
+
+        WAKER.register(cx.waker());
+        match try_scancode() {
+            Some(scancode) => Poll::Ready(scancode),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// A key press as decoded by a `Keyboard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    PageUp,
+    PageDown,
+}
+
+/// Decodes scancode set 1 into characters
+pub struct Keyboard {
+    shift: bool,
+    caps_lock: bool,
+
+    /// Whether the last scancode was the 0xE0 prefix of an extended key
+    extended: bool,
+}
+impl Keyboard {
+    /// Creates a new Keyboard object
+    pub const fn new() -> Keyboard {
+        Keyboard {
+            shift: false,
+            caps_lock: false,
+            extended: false,
+        }
+    }
+
+    /// Waits for the next key press that produces a character
+    pub async fn read_char(&mut self) -> char {
+        loop {
+            if let Some(c) = self.decode(next_scancode().await) {
+                return c;
+            }
+        }
+    }
+
+    /// Whether either shift key is held down
+    pub fn shift(&self) -> bool { self.shift }
+
+    /// Updates the modifier state and returns the character produced by
+    /// a scancode (if any)
+    pub fn decode(&mut self, scancode: u8) -> Option<char> {
+        match self.decode_key(scancode)? {
+            Key::Char(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Updates the modifier state and returns the key pressed by a
+    /// scancode (if any)
+    pub fn decode_key(&mut self, scancode: u8) -> Option<Key> {
+        if scancode == 0xE0 {
+            self.extended = true;
+            return None;
+        }
+        if core::mem::replace(&mut self.extended, false) {
+            return match scancode {
+                0x49 => Some(Key::PageUp),
+                0x51 => Some(Key::PageDown),
+
+                // Keypad enter and slash, the rest have no character
+                0x1C => Some(Key::Char('\n')),
+                0x35 => Some(Key::Char('/')),
+                _ => None,
+            };
+        }
+
+        match scancode {
+            // Left and right shift pressed/released
+            0x2A | 0x36 => self.shift = true,
+            0xAA | 0xB6 => self.shift = false,
+
+            // Caps lock pressed
+            0x3A => self.caps_lock = !self.caps_lock,
+
+            // Other key releases are ignored
+            code if code & 0x80 != 0 => (),
+
+            code => {
+                let (lower, upper) = *SCANCODE_SET_1.get(code as usize)?;
+                if lower == '\0' {
+                    return None;
+                }
+                let c = if self.shift { upper } else { lower };
+                return Some(Key::Char(if self.caps_lock && c.is_ascii_alphabetic() {
+                    if self.shift {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c.to_ascii_uppercase()
+                    }
+                } else {
+                    c
+                }));
+            },
+        }
+        None
+    }
+}
+
+/// The (unshifted, shifted) characters for each key of scancode set 1
+#[rustfmt::skip]
+const SCANCODE_SET_1: [(char, char); 58] = [
+    ('\0', '\0'), ('\u{1b}', '\u{1b}'), ('1', '!'), ('2', '@'), ('3', '#'),
+    ('4', '$'), ('5', '%'), ('6', '^'), ('7', '&'), ('8', '*'), ('9', '('),
+    ('0', ')'), ('-', '_'), ('=', '+'), ('\u{8}', '\u{8}'), ('\t', '\t'),
+    ('q', 'Q'), ('w', 'W'), ('e', 'E'), ('r', 'R'), ('t', 'T'), ('y', 'Y'),
+    ('u', 'U'), ('i', 'I'), ('o', 'O'), ('p', 'P'), ('[', '{'), (']', '}'),
+    ('\n', '\n'), ('\0', '\0'), ('a', 'A'), ('s', 'S'), ('d', 'D'),
+    ('f', 'F'), ('g', 'G'), ('h', 'H'), ('j', 'J'), ('k', 'K'), ('l', 'L'),
+    (';', ':'), ('\'', '"'), ('`', '~'), ('\0', '\0'), ('\\', '|'),
+    ('z', 'Z'), ('x', 'X'), ('c', 'C'), ('v', 'V'), ('b', 'B'), ('n', 'N'),
+    ('m', 'M'), (',', '<'), ('.', '>'), ('/', '?'), ('\0', '\0'),
+    ('*', '*'), ('\0', '\0'), (' ', ' '),
+];
@@ -0,0 +1,48 @@
+pub mod channel;
+pub mod executor;
+pub mod keyboard;
+pub mod timer;
+mod waker;
+
+pub use executor::{run_on_main_loop, run_ready, spawn};
+pub use waker::AtomicWaker;
+
+extern crate alloc;
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+/// A unique identifier given to every task spawned on the executor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+impl TaskId {
+    fn new() -> TaskId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A future that is driven to completion by the kernel executor
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+// There is one cpu, and a task only moves from the spawn queue to the
+// executor, both of which are locked with interrupts disabled
+unsafe impl Send for Task {}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
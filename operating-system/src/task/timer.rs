@@ -0,0 +1,150 @@
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The frequency that the APIC timer is programmed to fire at
+pub const TICKS_PER_SECOND: u64 = 1000;
+
+/// The number of timer interrupts since the APIC timer was started
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The earliest deadline in `SLEEPERS`, or u64::MAX if nobody sleeps
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+lazy_static! {
+    /// The waker of every pending sleep, keyed by its deadline and id.
+    /// Only locked with interrupts disabled, and never by the timer
+    /// interrupt, so that it does not allocate or free memory.
+    static ref SLEEPERS: Mutex<BTreeMap<(u64, u64), Waker>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Called by the timer interrupt handler on every tick
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Wakes every task whose deadline has passed. Called by the executor,
+/// which the timer interrupt brings out of `hlt` on every tick.
+pub fn wake_expired() {
+    let now = ticks();
+    if now < NEXT_DEADLINE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    loop {
+        // Wake outside the lock, since waking can take other locks
+        let waker = interrupts::without_interrupts(|| {
+            let mut sleepers = SLEEPERS.lock();
+            match sleepers.keys().next() {
+                Some(&key) if key.0 <= now => sleepers.remove(&key),
+                next => {
+                    let next = next.map_or(u64::MAX, |&(deadline, _)| deadline);
+                    NEXT_DEADLINE.store(next, Ordering::Relaxed);
+                    None
+                },
+            }
+        });
+        match waker {
+            Some(waker) => waker.wake(),
+            None => break,
+        }
+    }
+}
+
+/// Gets the number of ticks since the timer was started
+pub fn ticks() -> u64 { TICKS.load(Ordering::Relaxed) }
+
+/// Gets the number of milliseconds since the timer was started
+pub fn uptime_ms() -> u64 { ticks() * 1000 / TICKS_PER_SECOND }
+
+/// Returns a future that completes after `ms` milliseconds
+pub fn sleep(ms: u64) -> Sleep {
+    let ticks = (ms * TICKS_PER_SECOND + 999) / 1000;
+    Sleep::until(self::ticks() + ticks.max(1))
+}
+
+/// A future that completes once the tick count reaches a deadline
+pub struct Sleep {
+    deadline: u64,
+
+    /// Tells sleeps with the same deadline apart in `SLEEPERS`
+    id: u64,
+}
+impl Sleep {
+    fn until(deadline: u64) -> Sleep {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Sleep {
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        // Register the waker before checking the deadline again so
+        // that a tick between the two checks is not missed. A sleep
+        // keeps one waker, which is replaced if it is polled by another
+        // task.
+        interrupts::without_interrupts(|| {
+            let mut sleepers = SLEEPERS.lock();
+            match sleepers.get_mut(&(self.deadline, self.id)) {
+                Some(waker) if waker.will_wake(cx.waker()) => (),
+                Some(waker) => *waker = cx.waker().clone(),
+                None => {
+                    sleepers.insert((self.deadline, self.id), cx.waker().clone());
+                    NEXT_DEADLINE.fetch_min(self.deadline, Ordering::Relaxed);
+                },
+            }
+        });
+
+        if ticks() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // A sleep that is dropped early does not need waking
+        interrupts::without_interrupts(|| {
+            SLEEPERS.lock().remove(&(self.deadline, self.id));
+        });
+    }
+}
+
+/// A stream of ticks that fire every `period_ms` milliseconds
+pub struct Interval {
+    period_ms: u64,
+    next: Sleep,
+}
+impl Interval {
+    pub fn new(period_ms: u64) -> Interval {
+        Interval {
+            period_ms,
+            next: sleep(period_ms),
+        }
+    }
+
+    /// Waits for the next tick of the interval
+    pub async fn tick(&mut self) {
+        (&mut self.next).await;
+        self.next = Sleep::until(
+            self.next.deadline + (self.period_ms * TICKS_PER_SECOND / 1000).max(1),
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use core::task::Waker;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Holds the waker of a single task so that it can be woken from
+/// an interrupt handler.
+pub struct AtomicWaker {
+    waker: Mutex<Option<Waker>>,
+}
+impl AtomicWaker {
+    pub const fn new() -> AtomicWaker {
+        AtomicWaker {
+            waker: Mutex::new(None),
+        }
+    }
+
+    /// Registers the waker that should be woken on the next call to
+    /// wake. Interrupts are disabled while the lock is held so that
+    /// an interrupt handler can never spin on it.
+    pub fn register(&self, waker: &Waker) {
+        interrupts::without_interrupts(|| {
+            let mut slot = self.waker.lock();
+            match *slot {
+                Some(ref w) if w.will_wake(waker) => (),
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Wakes the registered task (if any). This is safe to call from
+    /// interrupt handlers.
+    pub fn wake(&self) {
+        // The lock can only be contended by another interrupt handler,
+        // in which case that handler is already waking the task
+        if let Some(mut slot) = self.waker.try_lock() {
+            if let Some(waker) = slot.take() {
+                waker.wake();
+            }
+        }
+    }
+}